@@ -50,8 +50,13 @@ impl Request {
             .unwrap_or_default()
     }
 
+    /// Session identifier from the `Session` header, without the timeout
+    /// parameter that clients may echo back.
     pub fn session(&self) -> Option<&str> {
-        self.headers.get("Session").map(|val| val.as_str())
+        self.headers
+            .get("Session")
+            .and_then(|val| val.split(';').next())
+            .map(|session_id| session_id.trim())
     }
 
     pub fn transport(&self) -> Result<Vec<Transport>, Error> {
@@ -203,6 +203,13 @@ pub enum Parameter {
     ServerPort(Port),
     Ssrc(String),
     Mode(Method),
+    /// RTP and RTCP multiplexed on the same port or channel (RFC 7826
+    /// section 18.54).
+    RtcpMux,
+    /// Parameter we do not know anything about, kept as is. Unknown pa-
+    /// rameters are passed on instead of failing the whole header, so
+    /// that the receiver can decide to decline them.
+    Extension(String),
 }
 
 impl fmt::Display for Parameter {
@@ -244,6 +251,12 @@ impl fmt::Display for Parameter {
             Parameter::Mode(method) => {
                 write!(f, "mode=\"{}\"", method)
             }
+            Parameter::RtcpMux => {
+                write!(f, "RTCP-mux")
+            }
+            Parameter::Extension(parameter) => {
+                write!(f, "{}", parameter)
+            }
         }
     }
 }
@@ -328,9 +341,8 @@ impl FromStr for Parameter {
                 let method = parse_or_err(var, val)?;
                 Ok(Parameter::Mode(method))
             }
-            _ => Err(Error::TransportParameterUnknown {
-                var: var.to_string(),
-            }),
+            "RTCP-mux" | "rtcp-mux" => Ok(Parameter::RtcpMux),
+            _ => Ok(Parameter::Extension(s.to_string())),
         }
     }
 }
@@ -562,6 +574,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_rtcp_mux() {
+        assert_eq!(
+            "RTP/AVP/TCP;RTCP-mux".parse::<Transport>().unwrap(),
+            Transport::new()
+                .with_lower_protocol(Lower::Tcp)
+                .with_parameter(Parameter::RtcpMux),
+        );
+    }
+
+    #[test]
+    fn parse_unknown_parameter() {
+        let transport = "RTP/AVP/TCP;unicast;setup=active;interleaved=0-1"
+            .parse::<Transport>()
+            .unwrap();
+        assert_eq!(
+            transport,
+            Transport::new()
+                .with_lower_protocol(Lower::Tcp)
+                .with_parameter(Parameter::Unicast)
+                .with_parameter(Parameter::Extension("setup=active".to_string()))
+                .with_parameter(Parameter::Interleaved(Channel::Range(0, 1))),
+        );
+        assert_eq!(
+            transport.to_string(),
+            "RTP/AVP/TCP;unicast;setup=active;interleaved=0-1",
+        );
+    }
+
     #[test]
     fn parse_rfc2326_section_12_39_examples() {
         assert_eq!(
@@ -1,5 +1,6 @@
 use std::error::Error;
 use std::fmt;
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
 
 use serde::Deserialize;
@@ -7,17 +8,146 @@ use serde::Deserialize;
 use config::{Config, ConfigError};
 
 use crate::media::MediaDescriptor;
+use crate::session::setup::{RtcpMode, SinkFraming};
 
 #[derive(Debug, Deserialize)]
 pub struct AppConfig {
     pub server: Server,
     pub media: Vec<Item>,
+    /// Sessions that write the RTP output of a source to a file rather
+    /// than to a client.
+    #[serde(default)]
+    pub sinks: Vec<Sink>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Server {
     pub host: String,
     pub port: u16,
+    /// Tear down the earlier session of a client when the same client sets
+    /// up a new session, for example after reconnecting. Disabled if not
+    /// set.
+    pub evict_on_reconnect: Option<ClientMatch>,
+    /// Largest RTP payload to produce, in bytes. Lowered to what the
+    /// transport of a session can carry if it is too large.
+    pub max_rtp_payload: Option<usize>,
+    /// Serve a JSON status page at this path to plain HTTP `GET` requests
+    /// on the RTSP port. Disabled if not set.
+    pub status_path: Option<String>,
+    /// Limit on the total number of bytes that sessions may buffer, for
+    /// example for slow clients. Unlimited if not set.
+    pub max_buffered_bytes: Option<usize>,
+    /// Limit on the number of sessions that may exist at the same time.
+    /// Unlimited if not set.
+    pub max_sessions: Option<usize>,
+    /// Limit on the number of sessions that a single client (by IP ad-
+    /// dress) may have at the same time. Unlimited if not set.
+    pub max_sessions_per_client: Option<usize>,
+    /// Limit on the number of sessions that a single client (by IP ad-
+    /// dress) may set up within `client_setup_window`. Unlimited if not
+    /// set.
+    pub max_setups_per_client: Option<usize>,
+    /// Seconds of the sliding window that `max_setups_per_client` applies
+    /// to. Defaults to 60 seconds if not set.
+    pub client_setup_window: Option<u64>,
+    /// Address to bind the RTP and RTCP sockets of sessions over UDP to.
+    /// All interfaces if not set.
+    pub udp_bind_address: Option<IpAddr>,
+    /// Inclusive range of ports to bind the RTP and RTCP sockets of ses-
+    /// sions over UDP to, for example to match firewall rules. Ephemeral
+    /// ports are used if not set.
+    pub udp_port_range: Option<(u16, u16)>,
+    /// Seconds without signs of life from a client after which its ses-
+    /// sion is torn down. Defaults to 60 seconds if not set.
+    pub session_timeout: Option<u64>,
+    /// Seconds that sessions get to stop when the server shuts down, after
+    /// which they are aborted. Defaults to 5 seconds if not set.
+    pub shutdown_timeout: Option<u64>,
+    /// Send RTP over UDP to the ports that clients send their own packets
+    /// from instead of the ports they announce, for clients behind NAT.
+    /// Disabled if not set.
+    pub symmetric_rtp: Option<bool>,
+    /// Number of failed sends in a row after which a session over UDP is
+    /// torn down. Defaults to 3 if not set.
+    pub udp_max_send_errors: Option<usize>,
+    /// Mark RTP and RTCP over UDP for prioritization on managed networks.
+    /// Disabled if not set.
+    pub udp_qos: Option<bool>,
+    /// DSCP value to mark RTP and RTCP over UDP with if `udp_qos` is en-
+    /// abled. Defaults to 46 (expedited forwarding) if not set.
+    pub udp_dscp: Option<u8>,
+    /// Length of generated session IDs. Defaults to 8 if not set.
+    pub session_id_length: Option<usize>,
+    /// Characters that generated session IDs consist of. Defaults to
+    /// digits, without a leading zero, if not set.
+    pub session_id_alphabet: Option<String>,
+    /// Number of hops that multicast RTP and RTCP may travel. Defaults to
+    /// 1 (the local network) if not set.
+    pub multicast_ttl: Option<u32>,
+    /// Loop multicast RTP and RTCP back to receivers on the host of the
+    /// server itself. Enabled if not set.
+    pub multicast_loopback: Option<bool>,
+    /// Number of packets in a row from the source that may fail to mux
+    /// before a session is torn down. Packets that fail are dropped until
+    /// then. Defaults to 10 if not set.
+    pub max_mux_errors: Option<usize>,
+    /// Seconds between the RTCP sender reports that sessions send on top
+    /// of those of the muxer, or 0 to send none. Below 5 (the minimum of
+    /// RFC 3550) is only safe for unicast. Defaults to 5 seconds if not
+    /// set.
+    pub sender_report_interval: Option<u64>,
+    /// Maximum bitrate in bits per second at which each session sends.
+    /// Sessions are not limited if not set.
+    pub max_session_bitrate: Option<u64>,
+    /// Number of bytes a session may send at once while it stays below
+    /// `max_session_bitrate`. Defaults to what the bitrate allows in 100
+    /// milliseconds if not set.
+    pub max_session_burst: Option<usize>,
+    /// MTU of the path to clients over UDP. RTP packets that would not fit
+    /// are split up (H.264 only). Must be at least 576. Packets are sent
+    /// as the muxer produces them if not set.
+    pub udp_mtu: Option<usize>,
+    /// Send a mid-gray H.264 keyframe to clients as soon as they start
+    /// playing, so that they show something before the next keyframe of
+    /// the source. Disabled if not set.
+    pub synthetic_keyframe_on_join: Option<bool>,
+    /// Drop disposable (non-reference) H.264 frames of sessions over in-
+    /// terleaved connections while more than this many messages wait to be
+    /// written to the client. Disabled if not set.
+    pub drop_disposable_frames_backlog: Option<usize>,
+    /// Directory to write the RTP output of every client session to as
+    /// well, one file per session named after its ID. Errors writing a
+    /// recording do not affect the session. Disabled if not set.
+    pub record_dir: Option<PathBuf>,
+    /// Whether sessions send and receive RTCP (`enabled`), only receive it
+    /// (`rtp_only`), or do without it altogether (`disabled`). Enabled if
+    /// not set.
+    pub rtcp: Option<RtcpMode>,
+    /// HTTP service to ask whether to admit each session before it is set
+    /// up. Sessions are admitted without asking if not set.
+    pub admission: Option<AdmissionService>,
+}
+
+/// HTTP service that decides whether to admit sessions, see [`HttpAdmis-
+/// sion`](crate::session::admission::HttpAdmission).
+#[derive(Debug, Deserialize)]
+pub struct AdmissionService {
+    pub address: SocketAddr,
+    pub path: String,
+    /// Seconds that the service gets to reply, after which the session is
+    /// refused. Defaults to 2 seconds if not set.
+    pub timeout: Option<u64>,
+}
+
+/// How to recognize that two sessions belong to the same client.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientMatch {
+    /// Same IP address.
+    Address,
+    /// Same value for the given request header, such as a client id or
+    /// token that the client sends along with SETUP.
+    Header(String),
 }
 
 #[derive(Debug, Deserialize)]
@@ -26,6 +156,39 @@ pub struct Item {
     pub path: String,
     pub kind: MediaKind,
     pub source: String,
+    pub max_viewers: Option<usize>,
+    /// Rescale packet timestamps to follow the rate at which packets ac-
+    /// tually arrive, for stream sources whose clock drifts with respect
+    /// to ours.
+    #[serde(default)]
+    pub drift_compensation: bool,
+    /// Multicast group and RTP port to deliver the source to when clients
+    /// ask for multicast, such as `239.0.0.1:5004`. RTCP is sent to the
+    /// port that follows. Multicast is refused if not set.
+    pub multicast_group: Option<SocketAddr>,
+    /// Give up on the source after this many attempts in a row to restart
+    /// it failed, backing off exponentially between attempts. Stopping the
+    /// source ends its sessions. Retried every 60 seconds for as long as it
+    /// takes if not set.
+    pub reconnect_attempts: Option<usize>,
+}
+
+/// Session that writes the RTP output of the source at `path` to `file`,
+/// for example to archive it or to chain it into another process through
+/// a named pipe.
+#[derive(Debug, Deserialize)]
+pub struct Sink {
+    pub path: String,
+    pub file: PathBuf,
+    /// How packets are framed in the file. Length-prefixed if not set.
+    #[serde(default)]
+    pub framing: SinkFraming,
+}
+
+impl fmt::Display for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} -> {}", self.path, self.file.display())
+    }
 }
 
 impl Item {
@@ -69,8 +232,39 @@ impl Default for AppConfig {
             server: Server {
                 host: "127.0.0.1".to_string(),
                 port: 554,
+                evict_on_reconnect: None,
+                max_rtp_payload: None,
+                status_path: None,
+                max_buffered_bytes: None,
+                max_sessions: None,
+                max_sessions_per_client: None,
+                max_setups_per_client: None,
+                client_setup_window: None,
+                udp_bind_address: None,
+                udp_port_range: None,
+                session_timeout: None,
+                shutdown_timeout: None,
+                symmetric_rtp: None,
+                udp_max_send_errors: None,
+                udp_qos: None,
+                udp_dscp: None,
+                session_id_length: None,
+                session_id_alphabet: None,
+                multicast_ttl: None,
+                multicast_loopback: None,
+                max_mux_errors: None,
+                sender_report_interval: None,
+                max_session_bitrate: None,
+                max_session_burst: None,
+                udp_mtu: None,
+                synthetic_keyframe_on_join: None,
+                drop_disposable_frames_backlog: None,
+                record_dir: None,
+                rtcp: None,
+                admission: None,
             },
             media: Vec::new(),
+            sinks: Vec::new(),
         }
     }
 }
@@ -1,14 +1,27 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use tokio::sync::{RwLock, RwLockReadGuard};
 
 use oddity_rtsp_protocol::{Error, Method, Range, Request, Response, RtpInfo, Status, Transport};
 
+use crate::app::config::ClientMatch;
 use crate::app::AppContext;
-use crate::net::connection::ResponseSenderTx;
-use crate::session::session_manager::RegisterSessionError;
-use crate::session::setup::{SessionSetup, SessionSetupError};
-use crate::session::{PlaySessionError, SessionId};
+use crate::net::connection::{InterleavedReceivers, ResponseSenderTx};
+use crate::session::pacing::Pacing;
+use crate::session::session_manager::{
+    KeepAliveError, RegisterSessionError, TeardownError, WorkerStatus,
+};
+use crate::session::setup::{
+    ClientIdentity, RtcpMode, RtpPayloadLimit, SessionSetup, SessionSetupError,
+};
+use crate::session::stats::SessionStats;
+use crate::session::udp::{BindError, MulticastGroup, UdpBind};
+use crate::session::{PlaySessionError, SessionId, SessionInfo, SessionTransport};
+use crate::source::{self, SourcePath};
 
 /// Identifies the server by its product name and version. We use
 /// the built-in `concat` and `env` macros to construct this string
@@ -18,14 +31,300 @@ static SERVER: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSI
 
 pub struct AppHandler {
     context: Arc<RwLock<AppContext>>,
+    server_ports: Vec<u16>,
+    reconnect_eviction: Option<ClientMatch>,
+    max_rtp_payload: Option<usize>,
+    status_path: Option<String>,
+    udp_bind: UdpBind,
+    session_timeout: Option<Duration>,
+    symmetric_rtp: bool,
+    udp_max_send_errors: Option<usize>,
+    max_mux_errors: Option<usize>,
+    sender_report_interval: Option<Duration>,
+    pacing: Option<Pacing>,
+    udp_mtu: Option<usize>,
+    synthetic_keyframe_on_join: bool,
+    drop_disposable_frames_backlog: Option<usize>,
+    record_dir: Option<PathBuf>,
+    rtcp_mode: RtcpMode,
+    udp_dscp: Option<u8>,
+    multicast_groups: HashMap<SourcePath, MulticastGroup>,
 }
 
 impl AppHandler {
-    pub fn new(context: Arc<RwLock<AppContext>>) -> Self {
-        Self { context }
+    pub fn new(context: Arc<RwLock<AppContext>>, server_port: u16) -> Self {
+        Self {
+            context,
+            server_ports: vec![server_port],
+            reconnect_eviction: None,
+            max_rtp_payload: None,
+            status_path: None,
+            udp_bind: UdpBind::default(),
+            session_timeout: None,
+            symmetric_rtp: false,
+            udp_max_send_errors: None,
+            max_mux_errors: None,
+            sender_report_interval: None,
+            pacing: None,
+            udp_mtu: None,
+            synthetic_keyframe_on_join: false,
+            drop_disposable_frames_backlog: None,
+            record_dir: None,
+            rtcp_mode: RtcpMode::default(),
+            udp_dscp: None,
+            multicast_groups: HashMap::new(),
+        }
+    }
+
+    /// Evict the earlier session of a client that sets up a new session,
+    /// using `client_match` to recognize the client.
+    pub fn with_reconnect_eviction(mut self, client_match: Option<ClientMatch>) -> Self {
+        self.reconnect_eviction = client_match;
+        self
+    }
+
+    /// Limit the size of RTP payloads produced for sessions. Clients may
+    /// still ask for a different size with the `Blocksize` header.
+    pub fn with_max_rtp_payload(mut self, max_rtp_payload: Option<usize>) -> Self {
+        self.max_rtp_payload = max_rtp_payload;
+        self
+    }
+
+    /// Serve the status page at the given path to plain HTTP requests.
+    pub fn with_status_path(mut self, status_path: Option<String>) -> Self {
+        self.status_path = status_path;
+        self
+    }
+
+    /// Bind the sockets of sessions over UDP to the given address and
+    /// port range.
+    pub fn with_udp_bind(mut self, udp_bind: UdpBind) -> Self {
+        self.udp_bind = udp_bind;
+        self
+    }
+
+    /// Time out sessions after the given time without signs of life from
+    /// the client, instead of the default.
+    pub fn with_session_timeout(mut self, session_timeout: Option<Duration>) -> Self {
+        self.session_timeout = session_timeout;
+        self
+    }
+
+    /// Send RTP over UDP to wherever clients send their own packets from,
+    /// for clients behind NAT.
+    pub fn with_symmetric_rtp(mut self, symmetric_rtp: bool) -> Self {
+        self.symmetric_rtp = symmetric_rtp;
+        self
+    }
+
+    /// Stop sessions over UDP after the given number of failed sends in a
+    /// row, instead of the default.
+    pub fn with_udp_max_send_errors(mut self, udp_max_send_errors: Option<usize>) -> Self {
+        self.udp_max_send_errors = udp_max_send_errors;
+        self
+    }
+
+    /// Stop sessions after the given number of packets in a row fail to
+    /// mux, instead of the default.
+    pub fn with_max_mux_errors(mut self, max_mux_errors: Option<usize>) -> Self {
+        self.max_mux_errors = max_mux_errors;
+        self
+    }
+
+    /// Send sender reports in sessions on the given interval, instead of
+    /// the default.
+    pub fn with_sender_report_interval(mut self, sender_report_interval: Option<Duration>) -> Self {
+        self.sender_report_interval = sender_report_interval;
+        self
+    }
+
+    /// Fit the RTP packets of sessions over UDP to the given MTU.
+    pub fn with_udp_mtu(mut self, udp_mtu: Option<usize>) -> Self {
+        self.udp_mtu = udp_mtu;
+        self
+    }
+
+    /// Send a synthetic keyframe to clients as soon as they start play-
+    /// ing.
+    pub fn with_synthetic_keyframe_on_join(mut self, synthetic_keyframe_on_join: bool) -> Self {
+        self.synthetic_keyframe_on_join = synthetic_keyframe_on_join;
+        self
+    }
+
+    /// Drop disposable frames of sessions over interleaved connections
+    /// while more than the given number of messages wait for the client.
+    pub fn with_drop_disposable_frames_backlog(
+        mut self,
+        drop_disposable_frames_backlog: Option<usize>,
+    ) -> Self {
+        self.drop_disposable_frames_backlog = drop_disposable_frames_backlog;
+        self
+    }
+
+    /// Record every session to the given directory as well.
+    pub fn with_record_dir(mut self, record_dir: Option<PathBuf>) -> Self {
+        self.record_dir = record_dir;
+        self
+    }
+
+    /// Send and receive RTCP in sessions as the given mode says.
+    pub fn with_rtcp_mode(mut self, rtcp_mode: RtcpMode) -> Self {
+        self.rtcp_mode = rtcp_mode;
+        self
+    }
+
+    /// Pace sessions so that none of them exceeds the given bitrate.
+    pub fn with_pacing(mut self, pacing: Option<Pacing>) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Mark the traffic of sessions over UDP with the given DSCP value.
+    pub fn with_udp_dscp(mut self, udp_dscp: Option<u8>) -> Self {
+        self.udp_dscp = udp_dscp;
+        self
+    }
+
+    /// Deliver sources to the given multicast groups, by path, when cli-
+    /// ents ask for multicast.
+    pub fn with_multicast_groups(
+        mut self,
+        multicast_groups: HashMap<SourcePath, MulticastGroup>,
+    ) -> Self {
+        self.multicast_groups = multicast_groups;
+        self
+    }
+
+    /// Path of the status page, if enabled.
+    pub fn status_path(&self) -> Option<&str> {
+        self.status_path.as_deref()
+    }
+
+    /// Render the status page as JSON, for example:
+    ///
+    /// ```text
+    /// {"sessions":1,"buffered_bytes":0,"sources":[{"path":"/a","sessions":1},{"path":"/b","sessions":0}],
+    ///  "session_list":[{"id":"1234","source":"/a","transport":{"kind":"sink"},"created":1700000000,
+    ///                   "ssrc":3735928559,"worker":"running"}],
+    ///  "ssrc_collisions":0,"healthy":true,"dead_sessions":0}
+    /// ```
+    ///
+    /// Sessions are listed from oldest to newest. Sessions that have not
+    /// sent any RTP yet have no SSRC (`null`). `ssrc_collisions` counts
+    /// the SSRCs that more than one session uses. The server is healthy if
+    /// the session manager runs and the worker of no session finished
+    /// without the session being removed, see [`SessionManager::health`].
+    ///
+    /// [`SessionManager::health`]: crate::session::session_manager::SessionManager::health
+    pub async fn status(&self) -> String {
+        let context = self.use_context().await;
+        let mut paths = context.source_manager.paths().await;
+        paths.sort();
+        let num_sessions_per_source = context.session_manager.num_sessions_per_source().await;
+        let mut session_list = context.session_manager.list_sessions().await;
+        session_list.sort_by_key(|info| info.created);
+        let ssrcs = context.session_manager.ssrcs().await;
+        let ssrc_collisions = ssrcs
+            .values()
+            .filter(|session_ids| session_ids.len() > 1)
+            .count();
+        let ssrcs = ssrcs
+            .into_iter()
+            .flat_map(|(ssrc, session_ids)| {
+                session_ids
+                    .into_iter()
+                    .map(move |session_id| (session_id, ssrc))
+            })
+            .collect::<HashMap<_, _>>();
+        let health = context.session_manager.health().await;
+
+        let sources = paths
+            .iter()
+            .map(|path| {
+                format!(
+                    "{{\"path\":{},\"sessions\":{}}}",
+                    json_string(path),
+                    num_sessions_per_source.get(path).copied().unwrap_or(0),
+                )
+            })
+            .collect::<Vec<_>>();
+        let session_list = session_list
+            .iter()
+            .map(|info| {
+                format!(
+                    "{{{},\"ssrc\":{},\"worker\":{}}}",
+                    session_fields_json(info),
+                    ssrcs
+                        .get(&info.id)
+                        .map(|ssrc| ssrc.to_string())
+                        .unwrap_or("null".to_string()),
+                    match health.sessions.get(&info.id) {
+                        Some(WorkerStatus::Running) => "\"running\"",
+                        Some(WorkerStatus::Finished) => "\"finished\"",
+                        // Removed between listing the sessions and check-
+                        // ing their health.
+                        None => "null",
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        format!(
+            "{{\"sessions\":{},\"buffered_bytes\":{},\"sources\":[{}],\"session_list\":[{}],\
+             \"ssrc_collisions\":{},\"healthy\":{},\"dead_sessions\":{}}}",
+            num_sessions_per_source.values().sum::<usize>(),
+            context.session_manager.buffered_bytes(),
+            sources.join(","),
+            session_list.join(","),
+            ssrc_collisions,
+            health.is_healthy(),
+            health.dead,
+        )
     }
 
-    pub async fn handle(&self, request: &Request, responder: &ResponseSenderTx) -> Response {
+    /// Render the status page at `path`: the overview at the status path,
+    /// see [`AppHandler::status`], or the details of the session with the
+    /// ID that follows it, such as `/status/1234`, see [`AppHandler::ses-
+    /// sion_status`]. `None` if there is no such page.
+    pub async fn status_page(&self, path: &str) -> Option<String> {
+        let status_path = self.status_path.as_deref()?;
+        if path == status_path {
+            return Some(self.status().await);
+        }
+        let session_id = path.strip_prefix(status_path)?.strip_prefix('/')?;
+        self.session_status(&SessionId::from(session_id)).await
+    }
+
+    /// Render the details of a session as JSON, if it exists, including
+    /// the SDP it was set up with and what it has sent so far, for exam-
+    /// ple:
+    ///
+    /// ```text
+    /// {"id":"1234","source":"/a","transport":{"kind":"sink"},"created":1700000000,"sdp":"v=0\r\n...",
+    ///  "stats":{"rtp_packets":10,"rtp_bytes":10240,"rtcp_packets":1,"rtcp_bytes":28,"mux_errors":0,
+    ///           "mux_dropped":0,"rtp_dropped":0,"queue_depth":0}}
+    /// ```
+    pub async fn session_status(&self, id: &SessionId) -> Option<String> {
+        let context = self.use_context().await;
+        let info = context.session_manager.session_info(id).await?;
+        let stats = context.session_manager.session_stats(id).await?;
+        Some(format!(
+            "{{{},\"sdp\":{},\"stats\":{}}}",
+            session_fields_json(&info),
+            info.sdp
+                .as_deref()
+                .map(json_string)
+                .unwrap_or("null".to_string()),
+            stats_json(&stats),
+        ))
+    }
+
+    pub async fn handle(
+        &self,
+        request: &Request,
+        peer_addr: Option<SocketAddr>,
+        responder: &ResponseSenderTx,
+        interleaved: &InterleavedReceivers,
+    ) -> Response {
         tracing::trace!(%request, "handling request");
 
         // Check the Require header and make sure all requested options are
@@ -38,6 +337,10 @@ impl AppHandler {
             /* Stateless */
             Method::Options => {
                 tracing::trace!("handling OPTIONS request");
+                // Some clients keep their session alive with OPTIONS instead of GET_PARAMETER.
+                if let Some(session_id) = request.session() {
+                    let _ = self.keep_alive(session_id).await;
+                }
                 reply_to_options_with_supported_methods(request)
             }
             Method::Announce => {
@@ -76,16 +379,35 @@ impl AppHandler {
             }
             Method::GetParameter => {
                 tracing::trace!("handling GET_PARAMETER request");
-                reply_method_not_supported(request)
+                // Clients send an empty GET_PARAMETER to keep their session alive (RFC 2326
+                // section 10.8). We do not have any parameters.
+                if let Some(session_id) = request.session() {
+                    match self.keep_alive(session_id).await {
+                        Ok(()) => reply_to_get_parameter(request),
+                        Err(KeepAliveError::NotFound) => reply_session_not_found(request),
+                        Err(err @ KeepAliveError::ControlBroken) => {
+                            tracing::error!(%request, %err, "failed to keep session alive");
+                            reply_internal_server_error(request)
+                        }
+                    }
+                } else {
+                    reply_method_not_supported(request)
+                }
             }
             Method::SetParameter => {
                 tracing::trace!("handling SET_PARAMETER request");
+                if let Some(session_id) = request.session() {
+                    // We cannot set any parameters, but the request still shows that the client
+                    // is there.
+                    let _ = self.keep_alive(session_id).await;
+                }
                 reply_method_not_supported(request)
             }
             /* Stateful */
             Method::Setup => {
                 tracing::trace!("handling SETUP request");
-                if request.session().is_some() {
+                if let Some(session_id) = request.session() {
+                    let _ = self.keep_alive(session_id).await;
                     // RFC specification allows negatively responding to SETUP request with Session
                     // IDs by responding with 459 Aggregate Operation Not Allowed. By handling this
                     // here we don't have to deal with clients trying to change transport parameters
@@ -128,10 +450,16 @@ impl AppHandler {
                     }
                 };
 
-                let session_setup = match SessionSetup::from_rtsp_candidate_transports(
+                let mut session_setup = match SessionSetup::from_rtsp_candidate_transports(
                     transport,
                     media_info,
                     responder.clone(),
+                    interleaved,
+                    peer_addr.map(|peer_addr| peer_addr.ip()),
+                    &self.server_ports,
+                    self.multicast_groups
+                        .get(&source::normalize_path(request.path().to_string()))
+                        .copied(),
                 )
                 .await
                 {
@@ -140,6 +468,10 @@ impl AppHandler {
                     | Err(SessionSetupError::DestinationInvalid) => {
                         return reply_unsupported_transport(request);
                     }
+                    Err(SessionSetupError::ClientPortInvalid(err)) => {
+                        tracing::debug!(%request, %err, "client requested invalid ports");
+                        return reply_unsupported_transport(request);
+                    }
                     Err(SessionSetupError::Media(err)) => {
                         tracing::error!(
                           %request, %err,
@@ -147,9 +479,112 @@ impl AppHandler {
                         );
                         return reply_internal_server_error(request);
                     }
+                    Err(err @ SessionSetupError::RtpPayloadTooLarge { .. }) => {
+                        tracing::debug!(%request, %err, "transport cannot carry rtp payload");
+                        return reply_unsupported_transport(request);
+                    }
+                    Err(err @ SessionSetupError::AddressFamilyMismatch { .. }) => {
+                        tracing::debug!(%request, %err, "cannot send to client");
+                        return reply_unsupported_transport(request);
+                    }
+                    Err(
+                        err @ (SessionSetupError::InterleavedChannelInUse { .. }
+                        | SessionSetupError::NoInterleavedChannelsAvailable),
+                    ) => {
+                        tracing::debug!(%request, %err, "interleaved channels not available");
+                        return reply_unsupported_transport(request);
+                    }
+                    Err(err @ SessionSetupError::MtuTooSmall { .. })
+                    | Err(err @ SessionSetupError::Bind(_)) => {
+                        tracing::error!(%request, %err, "failed to setup session");
+                        return reply_internal_server_error(request);
+                    }
                 };
                 tracing::trace!(path = request.path(), "setup session");
 
+                // A block size asked for by the client is a demand, the configured maximum only a
+                // preference (RFC 2326 section 12.7).
+                let rtp_payload_limit = match request.headers.get("Blocksize") {
+                    Some(block_size) => match block_size.trim().parse() {
+                        Ok(block_size) => Some(RtpPayloadLimit::Required(block_size)),
+                        Err(_) => {
+                            return reply_header_field_not_valid(request);
+                        }
+                    },
+                    None => self.max_rtp_payload.map(RtpPayloadLimit::Preferred),
+                };
+                if let Some(rtp_payload_limit) = rtp_payload_limit {
+                    session_setup = match session_setup.with_max_rtp_payload(rtp_payload_limit) {
+                        Ok(session_setup) => session_setup,
+                        Err(err) => {
+                            tracing::debug!(%request, %err, "client requested impossible block size");
+                            return reply_unsupported_transport(request);
+                        }
+                    };
+                }
+
+                session_setup = match session_setup.with_udp_bind(self.udp_bind.clone()) {
+                    Ok(session_setup) => session_setup
+                        .with_symmetric_rtp(self.symmetric_rtp)
+                        .with_dscp(self.udp_dscp),
+                    Err(err) => {
+                        tracing::debug!(%request, %err, "cannot send to client");
+                        return reply_unsupported_transport(request);
+                    }
+                };
+                if let Some(udp_max_send_errors) = self.udp_max_send_errors {
+                    session_setup = session_setup.with_max_send_errors(udp_max_send_errors);
+                }
+                if let Some(max_mux_errors) = self.max_mux_errors {
+                    session_setup = session_setup.with_max_mux_errors(max_mux_errors);
+                }
+                if let Some(sender_report_interval) = self.sender_report_interval {
+                    session_setup =
+                        session_setup.with_sender_report_interval(sender_report_interval);
+                }
+                session_setup = session_setup
+                    .with_pacing(self.pacing)
+                    .with_rtcp_mode(self.rtcp_mode)
+                    .with_synthetic_keyframe_on_join(self.synthetic_keyframe_on_join)
+                    .with_drop_disposable_frames_backlog(self.drop_disposable_frames_backlog)
+                    .with_record_dir(self.record_dir.clone());
+                session_setup = match session_setup.with_mtu(self.udp_mtu) {
+                    Ok(session_setup) => session_setup,
+                    Err(err) => {
+                        tracing::error!(%request, %err, "invalid mtu");
+                        return reply_internal_server_error(request);
+                    }
+                };
+
+                session_setup.client_identity = match &self.reconnect_eviction {
+                    Some(ClientMatch::Address) => {
+                        peer_addr.map(|peer_addr| ClientIdentity::Address(peer_addr.ip()))
+                    }
+                    Some(ClientMatch::Header(header)) => request
+                        .headers
+                        .get(header)
+                        .map(|token| ClientIdentity::Token(token.clone())),
+                    None => None,
+                };
+
+                if let Some(session_timeout) = self.session_timeout {
+                    session_setup = session_setup.with_timeout(session_timeout);
+                }
+                let timeout = session_setup.timeout;
+
+                // Bind the sockets last, so that the client learns their ports from the response.
+                session_setup = match session_setup.bind_udp().await {
+                    Ok(session_setup) => session_setup,
+                    Err(err @ SessionSetupError::Bind(BindError::NoPortsAvailable { .. })) => {
+                        tracing::warn!(%request, %err, "no udp ports left for session");
+                        return reply_not_admitted(request, Status::ServiceUnavailable);
+                    }
+                    Err(err) => {
+                        tracing::error!(%request, %err, "failed to bind sockets for session");
+                        return reply_internal_server_error(request);
+                    }
+                };
+
                 let transport = session_setup.rtsp_transport.clone();
                 match self
                     .use_context()
@@ -161,14 +596,44 @@ impl AppHandler {
                     // Session was successfully registered!
                     Ok(session_id) => {
                         tracing::trace!(path=request.path(), %session_id, "registered session");
-                        reply_to_setup(request, &session_id, &transport)
+                        reply_to_setup(request, &session_id, timeout, &transport)
                     }
-                    // In the highly unlikely case that the randomly generated session was already
-                    // in use before.
-                    Err(RegisterSessionError::AlreadyRegistered) => {
-                        tracing::error!(
-              %request,
-              "session id already present (collision)");
+                    // In the highly unlikely case that every generated session ID was already
+                    // in use.
+                    Err(RegisterSessionError::NoUniqueId) => {
+                        tracing::error!(%request, "no unique session id available");
+                        reply_internal_server_error(request)
+                    }
+                    Err(RegisterSessionError::NotAdmitted(err)) => {
+                        tracing::debug!(%request, %err, "session not admitted");
+                        reply_not_admitted(request, err.status())
+                    }
+                    Err(RegisterSessionError::TooManyViewers) => {
+                        tracing::debug!(%request, "source has reached its maximum number of viewers");
+                        reply_not_enough_bandwidth(request)
+                    }
+                    Err(RegisterSessionError::SourceDraining) => {
+                        tracing::debug!(%request, "source is draining");
+                        reply_not_admitted(request, Status::ServiceUnavailable)
+                    }
+                    Err(RegisterSessionError::MemoryExhausted) => {
+                        tracing::debug!(%request, "sessions buffer too much memory");
+                        reply_not_admitted(request, Status::ServiceUnavailable)
+                    }
+                    Err(err @ RegisterSessionError::CapacityExceeded { .. }) => {
+                        tracing::debug!(%request, %err, "server is at capacity");
+                        reply_not_admitted(request, Status::ServiceUnavailable)
+                    }
+                    Err(err @ RegisterSessionError::RateLimited { .. }) => {
+                        tracing::debug!(%request, %err, "client is rate limited");
+                        reply_not_admitted(request, Status::ServiceUnavailable)
+                    }
+                    Err(err @ RegisterSessionError::Bind(BindError::NoPortsAvailable { .. })) => {
+                        tracing::warn!(%request, %err, "no udp ports left for session");
+                        reply_not_admitted(request, Status::ServiceUnavailable)
+                    }
+                    Err(err @ RegisterSessionError::Bind(_)) => {
+                        tracing::error!(%request, %err, "failed to bind sockets for session");
                         reply_internal_server_error(request)
                     }
                 }
@@ -208,8 +673,8 @@ impl AppHandler {
                             // `now-` range.
                             let range = range.unwrap_or_else(Range::new_for_live);
                             // Construct RTP-Info based on the request URI, and the stream
-                            // state, which includes the last RTP sequence number, and the
-                            // current RTP timestamp.
+                            // state, which holds the sequence number and RTP timestamp of
+                            // the first packet the session sends after PLAY.
                             let rtp_info = RtpInfo::new_with_timing(
                                 &request.uri().to_string(),
                                 stream_state.rtp_seq,
@@ -237,7 +702,24 @@ impl AppHandler {
             }
             Method::Pause => {
                 tracing::trace!("handling PAUSE request");
-                reply_method_not_supported(request)
+                if let Some(session_id) = request.session() {
+                    match self
+                        .use_context()
+                        .await
+                        .session_manager
+                        .pause(&session_id.into())
+                        .await
+                    {
+                        Some(Ok(())) => reply_to_pause(request),
+                        Some(Err(err)) => {
+                            tracing::error!(%request, %err, "failed to pause session");
+                            reply_internal_server_error(request)
+                        }
+                        None => reply_session_not_found(request),
+                    }
+                } else {
+                    reply_session_not_found(request)
+                }
             }
             Method::Record => {
                 tracing::trace!("handling RECORD request");
@@ -246,16 +728,15 @@ impl AppHandler {
             Method::Teardown => {
                 tracing::trace!("handling TEARDOWN request");
                 if let Some(session_id) = request.session() {
-                    if self
+                    match self
                         .use_context()
                         .await
                         .session_manager
                         .teardown(&session_id.into())
                         .await
                     {
-                        reply_to_teardown(request)
-                    } else {
-                        reply_session_not_found(request)
+                        Ok(()) => reply_to_teardown(request),
+                        Err(TeardownError::NotFound) => reply_session_not_found(request),
                     }
                 } else {
                     reply_session_not_found(request)
@@ -271,6 +752,14 @@ impl AppHandler {
         }
     }
 
+    async fn keep_alive(&self, session_id: &str) -> Result<(), KeepAliveError> {
+        self.use_context()
+            .await
+            .session_manager
+            .keep_alive(&session_id.into())
+            .await
+    }
+
     #[inline]
     async fn use_context(&self) -> RwLockReadGuard<'_, AppContext> {
         self.context.read().await
@@ -283,6 +772,83 @@ fn is_request_require_supported(request: &Request) -> bool {
     request.require().is_none()
 }
 
+/// Render the metadata that the status page shows of every session as
+/// the fields of a JSON object, without the braces.
+fn session_fields_json(info: &SessionInfo) -> String {
+    format!(
+        "\"id\":{},\"source\":{},\"transport\":{},\"created\":{}",
+        json_string(&info.id.to_string()),
+        json_string(&info.source_path),
+        transport_json(&info.transport),
+        unix_time(info.created),
+    )
+}
+
+/// Render the counters of a session as a JSON object.
+fn stats_json(stats: &SessionStats) -> String {
+    format!(
+        "{{\"rtp_packets\":{},\"rtp_bytes\":{},\"rtcp_packets\":{},\"rtcp_bytes\":{},\
+         \"mux_errors\":{},\"mux_dropped\":{},\"rtp_dropped\":{},\"queue_depth\":{}}}",
+        stats.rtp_packets,
+        stats.rtp_bytes,
+        stats.rtcp_packets,
+        stats.rtcp_bytes,
+        stats.mux_errors,
+        stats.mux_dropped,
+        stats.rtp_dropped,
+        stats.queue_depth,
+    )
+}
+
+/// Render how a session delivers its stream as a JSON object.
+fn transport_json(transport: &SessionTransport) -> String {
+    match transport {
+        SessionTransport::Udp {
+            rtp_remote,
+            rtcp_remote,
+        } => format!(
+            "{{\"kind\":\"udp\",\"rtp_remote\":{},\"rtcp_remote\":{}}}",
+            json_string(&rtp_remote.to_string()),
+            json_string(&rtcp_remote.to_string()),
+        ),
+        SessionTransport::UdpMulticast { group } => format!(
+            "{{\"kind\":\"udp_multicast\",\"group\":{}}}",
+            json_string(&group.to_string()),
+        ),
+        SessionTransport::TcpInterleaved {
+            rtp_channel,
+            rtcp_channel,
+        } => format!(
+            "{{\"kind\":\"tcp_interleaved\",\"rtp_channel\":{},\"rtcp_channel\":{}}}",
+            rtp_channel, rtcp_channel,
+        ),
+        SessionTransport::Sink => "{\"kind\":\"sink\"}".to_string(),
+    }
+}
+
+/// Seconds since the Unix epoch, or zero for times before it.
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or(0)
+}
+
+/// Encode a string as JSON string literal.
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
 #[inline]
 fn is_request_one_of_content_types_supported(request: &Request) -> bool {
     // We only support SDP
@@ -294,7 +860,10 @@ fn reply_to_options_with_supported_methods(request: &Request) -> Response {
     Response::ok()
         .with_cseq_of(request)
         .with_header("Server", SERVER)
-        .with_header("Public", "OPTIONS, DESCRIBE, SETUP, PLAY, TEARDOWN")
+        .with_header(
+            "Public",
+            "OPTIONS, DESCRIBE, SETUP, PLAY, PAUSE, TEARDOWN, GET_PARAMETER",
+        )
         .build()
 }
 
@@ -308,15 +877,39 @@ fn reply_to_describe_with_media_sdp(request: &Request, sdp_contents: String) ->
 }
 
 #[inline]
-fn reply_to_setup(request: &Request, session_id: &SessionId, transport: &Transport) -> Response {
+fn reply_to_setup(
+    request: &Request,
+    session_id: &SessionId,
+    timeout: Duration,
+    transport: &Transport,
+) -> Response {
     Response::ok()
         .with_cseq_of(request)
         .with_header("Server", SERVER)
-        .with_header("Session", session_id)
+        .with_header(
+            "Session",
+            format!("{};timeout={}", session_id, timeout.as_secs()),
+        )
         .with_header("Transport", transport)
         .build()
 }
 
+#[inline]
+fn reply_to_get_parameter(request: &Request) -> Response {
+    Response::ok()
+        .with_cseq_of(request)
+        .with_header("Server", SERVER)
+        .build()
+}
+
+#[inline]
+fn reply_to_pause(request: &Request) -> Response {
+    Response::ok()
+        .with_cseq_of(request)
+        .with_header("Server", SERVER)
+        .build()
+}
+
 #[inline]
 fn reply_to_teardown(request: &Request) -> Response {
     Response::ok()
@@ -425,6 +1018,22 @@ fn reply_unsupported_transport(request: &Request) -> Response {
         .build()
 }
 
+#[inline]
+fn reply_not_enough_bandwidth(request: &Request) -> Response {
+    Response::error(Status::NotEnoughBandwidth)
+        .with_cseq_of(request)
+        .with_header("Server", SERVER)
+        .build()
+}
+
+#[inline]
+fn reply_not_admitted(request: &Request, status: Status) -> Response {
+    Response::error(status)
+        .with_cseq_of(request)
+        .with_header("Server", SERVER)
+        .build()
+}
+
 #[inline]
 fn reply_session_not_found(request: &Request) -> Response {
     tracing::debug!(
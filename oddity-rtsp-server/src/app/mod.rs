@@ -1,17 +1,31 @@
 pub mod config;
 pub mod handler;
 
+use std::collections::HashMap;
 use std::error::Error;
 use std::sync::Arc;
+use std::time::Duration;
 
-use tokio::sync::RwLock;
+use tokio::fs;
+use tokio::select;
+use tokio::sync::{mpsc, RwLock};
 
 use crate::app::config::AppConfig;
 use crate::app::handler::AppHandler;
 use crate::net::server::Server;
+use crate::runtime::task_manager::Task;
 use crate::runtime::Runtime;
+use crate::session::admission::HttpAdmission;
+use crate::session::client_limit::{ClientLimits, SetupRate};
+use crate::session::pacing::Pacing;
+use crate::session::sender_report;
 use crate::session::session_manager::SessionManager;
+use crate::session::setup::{SendToSink, SessionSetup};
+use crate::session::udp::{self, MulticastGroup, UdpBind};
+use crate::session::SessionIdConfig;
+use crate::source;
 use crate::source::source_manager::SourceManager;
+use crate::source::{ReconnectPolicy, SourceRemovedRx, SourceRemovedTx};
 
 macro_rules! handle_err {
     ($rt:ident, $expr:expr) => {
@@ -28,20 +42,26 @@ macro_rules! handle_err {
 pub struct App {
     server: Server,
     context: Arc<RwLock<AppContext>>,
+    source_removal: Task,
     runtime: Arc<Runtime>,
+    drain_timeout: Duration,
 }
 
 impl App {
     pub async fn start(config: AppConfig) -> Result<App, Box<dyn Error>> {
         let runtime = Arc::new(Runtime::new());
 
-        let mut context = initialize_context(runtime.clone()).await;
+        let (source_removed_tx, source_removed_rx) = mpsc::unbounded_channel();
+        let mut context = initialize_context(&config, runtime.clone(), source_removed_tx).await;
         handle_err!(
             runtime,
             register_sources_with_context(&config, &mut context,).await
         )?;
+        handle_err!(runtime, start_sinks(&config, &mut context).await)?;
 
         let context = Arc::new(RwLock::new(context));
+        let source_removal =
+            start_source_removal(context.clone(), source_removed_rx, runtime.as_ref()).await;
         let server = handle_err!(
             runtime,
             initialize_server(&config, context.clone(), runtime.clone(),).await
@@ -50,13 +70,25 @@ impl App {
         Ok(Self {
             server,
             context,
+            source_removal,
             runtime,
+            drain_timeout: config
+                .server
+                .shutdown_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(SessionManager::DEFAULT_DRAIN_TIMEOUT),
         })
     }
 
     pub async fn stop(&mut self) {
         self.server.stop().await;
-        self.context.write().await.session_manager.stop().await;
+        self.source_removal.stop().await;
+        self.context
+            .write()
+            .await
+            .session_manager
+            .stop(self.drain_timeout)
+            .await;
         self.context.write().await.source_manager.stop().await;
         self.runtime.stop().await;
     }
@@ -67,7 +99,68 @@ async fn initialize_server(
     context: Arc<RwLock<AppContext>>,
     runtime: Arc<Runtime>,
 ) -> Result<Server, Box<dyn Error>> {
-    let handler = AppHandler::new(context.clone());
+    let multicast_groups = config
+        .media
+        .iter()
+        .filter_map(|item| {
+            let group = MulticastGroup::new(item.multicast_group?)
+                .with_ttl(
+                    config
+                        .server
+                        .multicast_ttl
+                        .unwrap_or(MulticastGroup::DEFAULT_TTL),
+                )
+                .with_loopback(config.server.multicast_loopback.unwrap_or(true));
+            Some((source::normalize_path(item.path.clone()), group))
+        })
+        .collect::<HashMap<_, _>>();
+    let sender_report_interval = config
+        .server
+        .sender_report_interval
+        .map(Duration::from_secs);
+    if let Some(interval) = sender_report_interval
+        .filter(|interval| !interval.is_zero() && *interval < sender_report::RFC_3550_MIN_INTERVAL)
+    {
+        tracing::warn!(
+            ?interval,
+            "sender report interval below rfc 3550 minimum, only safe for unicast sessions",
+        );
+    }
+    let handler = AppHandler::new(context.clone(), config.server.port)
+        .with_reconnect_eviction(config.server.evict_on_reconnect.clone())
+        .with_max_rtp_payload(config.server.max_rtp_payload)
+        .with_status_path(config.server.status_path.clone())
+        .with_session_timeout(config.server.session_timeout.map(Duration::from_secs))
+        .with_symmetric_rtp(config.server.symmetric_rtp.unwrap_or(false))
+        .with_udp_max_send_errors(config.server.udp_max_send_errors)
+        .with_max_mux_errors(config.server.max_mux_errors)
+        .with_sender_report_interval(sender_report_interval)
+        .with_udp_mtu(config.server.udp_mtu)
+        .with_synthetic_keyframe_on_join(config.server.synthetic_keyframe_on_join.unwrap_or(false))
+        .with_drop_disposable_frames_backlog(config.server.drop_disposable_frames_backlog)
+        .with_record_dir(config.server.record_dir.clone())
+        .with_rtcp_mode(config.server.rtcp.unwrap_or_default())
+        .with_pacing(config.server.max_session_bitrate.map(|max_bitrate| {
+            let pacing = Pacing::new(max_bitrate);
+            match config.server.max_session_burst {
+                Some(burst) => pacing.with_burst(burst),
+                None => pacing,
+            }
+        }))
+        .with_udp_dscp(config.server.udp_qos.unwrap_or(false).then(|| {
+            config
+                .server
+                .udp_dscp
+                .unwrap_or(udp::DSCP_EXPEDITED_FORWARDING)
+        }))
+        .with_multicast_groups(multicast_groups)
+        .with_udp_bind(UdpBind {
+            ip: config
+                .server
+                .udp_bind_address
+                .unwrap_or(UdpBind::default().ip),
+            port_range: config.server.udp_port_range,
+        });
     Server::start(
         config.server.host.parse()?,
         config.server.port,
@@ -78,10 +171,51 @@ async fn initialize_server(
     .map_err(|err| err.into())
 }
 
-async fn initialize_context(runtime: Arc<Runtime>) -> AppContext {
+async fn initialize_context(
+    config: &AppConfig,
+    runtime: Arc<Runtime>,
+    source_removed_tx: SourceRemovedTx,
+) -> AppContext {
+    let source_manager = SourceManager::start(runtime.clone(), Some(source_removed_tx)).await;
+    let session_manager = SessionManager::start(runtime.clone(), None)
+        .await
+        .with_memory_limit(config.server.max_buffered_bytes)
+        .with_max_sessions(config.server.max_sessions)
+        .with_client_limits(ClientLimits {
+            max_sessions: config.server.max_sessions_per_client,
+            setup_rate: config
+                .server
+                .max_setups_per_client
+                .map(|max_setups| SetupRate {
+                    max_setups,
+                    window: config
+                        .server
+                        .client_setup_window
+                        .map(Duration::from_secs)
+                        .unwrap_or(SetupRate::DEFAULT_WINDOW),
+                }),
+        })
+        .with_session_ids(SessionIdConfig {
+            len: config
+                .server
+                .session_id_length
+                .unwrap_or(SessionIdConfig::default().len),
+            alphabet: config.server.session_id_alphabet.clone(),
+        });
+    let session_manager = match &config.server.admission {
+        Some(service) => session_manager.with_admission(
+            HttpAdmission::new(service.address, service.path.clone()).with_timeout(
+                service
+                    .timeout
+                    .map(Duration::from_secs)
+                    .unwrap_or(HttpAdmission::DEFAULT_TIMEOUT),
+            ),
+        ),
+        None => session_manager,
+    };
     AppContext {
-        source_manager: SourceManager::start(runtime.clone()).await,
-        session_manager: SessionManager::start(runtime.clone()).await,
+        source_manager,
+        session_manager,
     }
 }
 
@@ -98,6 +232,9 @@ async fn register_sources_with_context(
                 item.name.as_str(),
                 item.path.clone(),
                 item.as_media_descriptor()?,
+                item.max_viewers,
+                item.drift_compensation,
+                item.reconnect_attempts.map(ReconnectPolicy::new),
             )
             .await?;
     }
@@ -105,6 +242,70 @@ async fn register_sources_with_context(
     Ok(())
 }
 
+async fn start_sinks(config: &AppConfig, context: &mut AppContext) -> Result<(), Box<dyn Error>> {
+    tracing::trace!("starting sinks");
+    for sink in config.sinks.iter() {
+        tracing::info!(%sink, "starting sink");
+        let mut source_delegate = context
+            .source_manager
+            .subscribe(&source::normalize_path(sink.path.clone()))
+            .await
+            .ok_or_else(|| format!("no source at {}", sink.path))?;
+        let media_info = source_delegate
+            .query_media_info()
+            .await
+            .ok_or_else(|| format!("failed to query media info of source at {}", sink.path))?;
+        // Opening a named pipe waits until something reads from it.
+        let file = fs::File::create(&sink.file).await?;
+        let session_setup =
+            SessionSetup::from_sink(SendToSink::new(file).with_framing(sink.framing), media_info)
+                .await?;
+        let session_id = context
+            .session_manager
+            .setup(source_delegate, session_setup)
+            .await?;
+        tracing::debug!(%sink, %session_id, "started sink");
+    }
+    tracing::trace!("started sinks");
+    Ok(())
+}
+
+/// Tear down the sessions of sources that the source manager removed,
+/// because they stopped for good, rather than leaving their clients with
+/// sessions that never play again.
+async fn start_source_removal(
+    context: Arc<RwLock<AppContext>>,
+    mut source_removed_rx: SourceRemovedRx,
+    runtime: &Runtime,
+) -> Task {
+    runtime
+        .task()
+        .spawn(move |mut task_context| async move {
+            loop {
+                select! {
+                  // CANCEL SAFETY: `mpsc::UnboundedReceiver::recv` is cancel safe.
+                  path = source_removed_rx.recv() => {
+                    match path {
+                      Some(path) => {
+                        tracing::warn!(%path, "source removed, tearing down its sessions");
+                        context
+                          .read()
+                          .await
+                          .session_manager
+                          .teardown_all_for_source(&path)
+                          .await;
+                      },
+                      None => break,
+                    }
+                  },
+                  // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
+                  _ = task_context.wait_for_stop() => break,
+                }
+            }
+        })
+        .await
+}
+
 pub struct AppContext {
     source_manager: SourceManager,
     session_manager: SessionManager,
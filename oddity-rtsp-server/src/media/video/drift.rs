@@ -0,0 +1,204 @@
+//! Compensation for an upstream clock that runs slightly faster or slo-
+//! wer than ours. The timestamps of packets relayed from such a source
+//! slowly diverge from wallclock time, and with them the RTP timestamps
+//! the muxer derives from them. Over hours-long sessions, this breaks the
+//! relationship between NTP and RTP time in sender reports.
+//!
+//! [`DriftCompensator`] measures the rate at which packets arrive against
+//! the rate their timestamps advance, and rescales timestamps to track
+//! the measured rate. Corrections are small and applied gradually, so
+//! that jitter in arrival times does not result in visible jumps.
+
+use std::time::Duration;
+
+use video_rs as video;
+
+pub struct DriftCompensator {
+    /// Arrival time and timestamp of the first packet, the reference for
+    /// measuring the rate.
+    origin: Option<(Duration, Duration)>,
+    /// Start of the current segment: the timestamp before and after cor-
+    /// rection. Each rate update starts a new segment, so that the cor-
+    /// rected timestamps stay continuous.
+    segment: (f64, f64),
+    /// Wallclock seconds per second of media time.
+    rate: f64,
+    last_timestamp: Duration,
+    last_update: Duration,
+}
+
+impl DriftCompensator {
+    /// Minimum amount of time between updates of the rate.
+    const UPDATE_INTERVAL: Duration = Duration::from_secs(10);
+
+    /// Largest correction we are willing to apply. Any upstream clock that
+    /// is off by more is broken in other ways, and we should not try to
+    /// follow it.
+    const MAX_CORRECTION: f64 = 0.005;
+
+    /// Fraction of the difference between the measured and current rate
+    /// that is applied with each update.
+    const SMOOTHING: f64 = 0.25;
+
+    pub fn new() -> Self {
+        Self {
+            origin: None,
+            segment: (0.0, 0.0),
+            rate: 1.0,
+            last_timestamp: Duration::ZERO,
+            last_update: Duration::ZERO,
+        }
+    }
+
+    /// Observe the arrival of a packet with the given timestamp. The arri-
+    /// val time is relative to any fixed point in (monotonic) time.
+    pub fn observe(&mut self, arrival: Duration, timestamp: Duration) {
+        let (origin_arrival, origin_timestamp) = match self.origin {
+            Some(origin) if timestamp >= self.last_timestamp => origin,
+            // This is the first packet, or the timestamps jumped backwards.
+            // Either way, we start measuring over from here, while keeping
+            // corrected time continuous.
+            origin => {
+                let corrected = match origin {
+                    Some(_) => self.correct(self.last_timestamp),
+                    None => timestamp,
+                };
+                self.start_segment(timestamp, corrected);
+                self.origin = Some((arrival, timestamp));
+                self.last_timestamp = timestamp;
+                self.last_update = arrival;
+                return;
+            }
+        };
+        self.last_timestamp = timestamp;
+
+        let elapsed_media = timestamp.saturating_sub(origin_timestamp);
+        if arrival.saturating_sub(self.last_update) < Self::UPDATE_INTERVAL
+            || elapsed_media < Self::UPDATE_INTERVAL
+        {
+            return;
+        }
+        self.last_update = arrival;
+
+        let elapsed_wallclock = arrival.saturating_sub(origin_arrival);
+        let measured = (elapsed_wallclock.as_secs_f64() / elapsed_media.as_secs_f64())
+            .clamp(1.0 - Self::MAX_CORRECTION, 1.0 + Self::MAX_CORRECTION);
+        let rate = self.rate + (measured - self.rate) * Self::SMOOTHING;
+
+        self.start_segment(timestamp, self.correct(timestamp));
+        self.rate = rate;
+        tracing::trace!(measured, rate, "updated clock drift compensation");
+    }
+
+    /// Correct a timestamp according to the current rate.
+    pub fn correct(&self, timestamp: Duration) -> Duration {
+        let (segment_in, segment_out) = self.segment;
+        let corrected = segment_out + (timestamp.as_secs_f64() - segment_in) * self.rate;
+        Duration::from_secs_f64(corrected.max(0.0))
+    }
+
+    /// Observe the arrival of a packet and correct its timestamps. Pack-
+    /// ets without timestamps are left alone.
+    pub fn update(&mut self, arrival: Duration, packet: &mut video::Packet) {
+        let dts = packet.dts();
+        if dts.has_value() {
+            let dts = Duration::from(dts);
+            self.observe(arrival, dts);
+            packet.set_dts(&self.correct(dts).into());
+        }
+        let pts = packet.pts();
+        if pts.has_value() {
+            packet.set_pts(&self.correct(Duration::from(pts)).into());
+        }
+    }
+
+    fn start_segment(&mut self, timestamp: Duration, corrected: Duration) {
+        self.segment = (timestamp.as_secs_f64(), corrected.as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use super::DriftCompensator;
+
+    /// Feed packets at 25 fps for `secs` seconds, where the upstream clock
+    /// runs at `upstream_rate` times the speed of ours. Returns the last
+    /// arrival time and corrected timestamp.
+    fn feed(compensator: &mut DriftCompensator, secs: u64, upstream_rate: f64) -> (f64, f64) {
+        let mut last = (0.0, 0.0);
+        for i in 0..secs * 25 {
+            let arrival = Duration::from_millis(i * 40);
+            let timestamp = Duration::from_secs_f64(arrival.as_secs_f64() * upstream_rate);
+            compensator.observe(arrival, timestamp);
+            last = (
+                arrival.as_secs_f64(),
+                compensator.correct(timestamp).as_secs_f64(),
+            );
+        }
+        last
+    }
+
+    #[test]
+    fn no_drift() {
+        let mut compensator = DriftCompensator::new();
+        let (arrival, corrected) = feed(&mut compensator, 600, 1.0);
+        assert!((compensator.rate - 1.0).abs() < 1e-9);
+        assert!((corrected - arrival).abs() < 1e-3);
+    }
+
+    #[test]
+    fn tracks_upstream_clock_that_runs_fast() {
+        let mut compensator = DriftCompensator::new();
+        let (arrival, corrected) = feed(&mut compensator, 3600, 1.001);
+        assert!((compensator.rate - 1.0 / 1.001).abs() < 1e-6);
+        // Without compensation, the timestamps would be 3.6 seconds ahead
+        // of wallclock time by now.
+        assert!((corrected - arrival).abs() < 0.1);
+    }
+
+    #[test]
+    fn correction_is_limited() {
+        let mut compensator = DriftCompensator::new();
+        feed(&mut compensator, 3600, 1.1);
+        assert!((compensator.rate - (1.0 - DriftCompensator::MAX_CORRECTION)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn corrected_timestamps_are_continuous() {
+        let mut compensator = DriftCompensator::new();
+        let mut last = Duration::ZERO;
+        for i in 0..25 * 600 {
+            let arrival = Duration::from_millis(i * 40);
+            let timestamp = Duration::from_secs_f64(arrival.as_secs_f64() * 1.002);
+            compensator.observe(arrival, timestamp);
+            let corrected = compensator.correct(timestamp);
+            if i > 0 {
+                let step = (corrected - last).as_secs_f64();
+                assert!((step - 0.04).abs() < 0.001);
+            }
+            last = corrected;
+        }
+    }
+
+    #[test]
+    fn first_timestamp_is_unchanged() {
+        let mut compensator = DriftCompensator::new();
+        compensator.observe(Duration::ZERO, Duration::from_secs(1000));
+        assert_eq!(
+            compensator.correct(Duration::from_secs(1000)),
+            Duration::from_secs(1000),
+        );
+    }
+
+    #[test]
+    fn restarts_when_timestamps_jump_backwards() {
+        let mut compensator = DriftCompensator::new();
+        let (_, before) = feed(&mut compensator, 60, 1.0);
+        compensator.observe(Duration::from_secs(61), Duration::ZERO);
+        let after = compensator.correct(Duration::ZERO).as_secs_f64();
+        assert!(after >= before);
+    }
+}
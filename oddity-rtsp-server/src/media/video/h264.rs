@@ -0,0 +1,409 @@
+//! Generation of a synthetic H.264 keyframe.
+//!
+//! Clients that join a long-GOP stream see nothing until the next natural
+//! keyframe arrives. As a last resort we can send them a tiny IDR picture
+//! that is entirely mid-gray. It is generated from the SPS and PPS of the
+//! stream so that the decoder initializes with the right parameters.
+//!
+//! Every macroblock is coded as `I_16x16` with DC prediction and no resi-
+//! dual, which costs about one byte per macroblock with CAVLC.
+
+use std::error;
+use std::fmt;
+
+const NAL_UNIT_TYPE_IDR: u8 = 5;
+const NAL_REF_IDC_HIGHEST: u8 = 3;
+
+const SLICE_TYPE_I_ALL: u32 = 7;
+const MB_TYPE_I_16X16_DC_NO_RESIDUAL: u32 = 3;
+const INTRA_CHROMA_PRED_MODE_DC: u32 = 0;
+const DISABLE_DEBLOCKING_FILTER: u32 = 1;
+
+/// Profiles that carry chroma format and bit depth in the SPS.
+const HIGH_PROFILES: [u8; 12] = [100, 110, 122, 244, 44, 83, 86, 118, 128, 138, 139, 134];
+
+/// Generate a mid-gray IDR picture for the stream described by `sps` and
+/// `pps`. Both are NAL units without start code, as produced by the muxer.
+/// The returned NAL unit has no start code either.
+pub fn gray_idr(sps: &[u8], pps: &[u8]) -> Result<Vec<u8>> {
+    let sps = Sps::parse(sps)?;
+    let pps = Pps::parse(pps)?;
+
+    let mut bits = BitWriter::new();
+    bits.write_ue(0); // first_mb_in_slice
+    bits.write_ue(SLICE_TYPE_I_ALL);
+    bits.write_ue(pps.id);
+    bits.write_bits(0, sps.log2_max_frame_num); // frame_num
+    if !sps.frame_mbs_only {
+        bits.write_bit(false); // field_pic_flag
+    }
+    bits.write_ue(0); // idr_pic_id
+    match sps.pic_order_cnt {
+        PicOrderCnt::Lsb { log2_max_lsb } => {
+            bits.write_bits(0, log2_max_lsb); // pic_order_cnt_lsb
+            if pps.bottom_field_pic_order_in_frame_present {
+                bits.write_se(0); // delta_pic_order_cnt_bottom
+            }
+        }
+        PicOrderCnt::Delta { always_zero: false } => {
+            bits.write_se(0); // delta_pic_order_cnt[0]
+            if pps.bottom_field_pic_order_in_frame_present {
+                bits.write_se(0); // delta_pic_order_cnt[1]
+            }
+        }
+        PicOrderCnt::Delta { always_zero: true } | PicOrderCnt::Implicit => {}
+    }
+    if pps.redundant_pic_cnt_present {
+        bits.write_ue(0); // redundant_pic_cnt
+    }
+    bits.write_bit(false); // no_output_of_prior_pics_flag
+    bits.write_bit(false); // long_term_reference_flag
+    bits.write_se(0); // slice_qp_delta
+    if pps.deblocking_filter_control_present {
+        bits.write_ue(DISABLE_DEBLOCKING_FILTER);
+    }
+
+    for _ in 0..sps.pic_size_in_mbs() {
+        bits.write_ue(MB_TYPE_I_16X16_DC_NO_RESIDUAL);
+        if sps.has_chroma {
+            bits.write_ue(INTRA_CHROMA_PRED_MODE_DC);
+        }
+        bits.write_se(0); // mb_qp_delta
+
+        // Intra16x16DCLevel is always coded. Without any neighboring coef-
+        // ficients `nC` is zero, and the coeff_token for zero coefficients
+        // is a single one bit.
+        bits.write_bit(true);
+    }
+
+    let mut nal = vec![(NAL_REF_IDC_HIGHEST << 5) | NAL_UNIT_TYPE_IDR];
+    nal.extend(add_emulation_prevention(&bits.finish()));
+    Ok(nal)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyntheticKeyframeError {
+    CodecNotSupported,
+    Truncated,
+    /// The parameter sets use a feature we cannot generate a picture for
+    /// (such as CABAC, interlaced MBAFF coding or 4:4:4 chroma).
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for SyntheticKeyframeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyntheticKeyframeError::CodecNotSupported => write!(f, "codec not supported"),
+            SyntheticKeyframeError::Truncated => write!(f, "parameter set truncated"),
+            SyntheticKeyframeError::Unsupported(feature) => {
+                write!(f, "unsupported stream feature: {}", feature)
+            }
+        }
+    }
+}
+
+impl error::Error for SyntheticKeyframeError {}
+
+type Result<T> = std::result::Result<T, SyntheticKeyframeError>;
+
+enum PicOrderCnt {
+    Lsb { log2_max_lsb: u32 },
+    Delta { always_zero: bool },
+    Implicit,
+}
+
+struct Sps {
+    has_chroma: bool,
+    log2_max_frame_num: u32,
+    pic_order_cnt: PicOrderCnt,
+    width_in_mbs: u32,
+    height_in_map_units: u32,
+    frame_mbs_only: bool,
+}
+
+impl Sps {
+    fn parse(nal: &[u8]) -> Result<Self> {
+        let rbsp = remove_emulation_prevention(nal.get(1..).unwrap_or_default());
+        let mut bits = BitReader::new(&rbsp);
+
+        let profile_idc = bits.read_bits(8)? as u8;
+        let _constraint_flags_and_level_idc = bits.read_bits(16)?;
+        let _seq_parameter_set_id = bits.read_ue()?;
+
+        let mut chroma_format_idc = 1;
+        if HIGH_PROFILES.contains(&profile_idc) {
+            chroma_format_idc = bits.read_ue()?;
+            if chroma_format_idc == 3 {
+                return Err(SyntheticKeyframeError::Unsupported("4:4:4 chroma"));
+            }
+            let _bit_depth_luma_minus8 = bits.read_ue()?;
+            let _bit_depth_chroma_minus8 = bits.read_ue()?;
+            let _qpprime_y_zero_transform_bypass_flag = bits.read_bit()?;
+            if bits.read_bit()? {
+                // Scaling matrices do not matter for a picture without
+                // residual, but skipping them requires parsing them.
+                return Err(SyntheticKeyframeError::Unsupported("scaling matrix"));
+            }
+        }
+
+        let log2_max_frame_num = bits.read_ue()? + 4;
+        let pic_order_cnt = match bits.read_ue()? {
+            0 => PicOrderCnt::Lsb {
+                log2_max_lsb: bits.read_ue()? + 4,
+            },
+            1 => {
+                let always_zero = bits.read_bit()?;
+                let _offset_for_non_ref_pic = bits.read_se()?;
+                let _offset_for_top_to_bottom_field = bits.read_se()?;
+                for _ in 0..bits.read_ue()? {
+                    let _offset_for_ref_frame = bits.read_se()?;
+                }
+                PicOrderCnt::Delta { always_zero }
+            }
+            _ => PicOrderCnt::Implicit,
+        };
+        let _max_num_ref_frames = bits.read_ue()?;
+        let _gaps_in_frame_num_value_allowed_flag = bits.read_bit()?;
+        let width_in_mbs = bits.read_ue()? + 1;
+        let height_in_map_units = bits.read_ue()? + 1;
+        let frame_mbs_only = bits.read_bit()?;
+        if !frame_mbs_only && bits.read_bit()? {
+            return Err(SyntheticKeyframeError::Unsupported("mbaff"));
+        }
+
+        Ok(Sps {
+            has_chroma: chroma_format_idc != 0,
+            log2_max_frame_num,
+            pic_order_cnt,
+            width_in_mbs,
+            height_in_map_units,
+            frame_mbs_only,
+        })
+    }
+
+    fn pic_size_in_mbs(&self) -> u32 {
+        let frame_height_in_mbs = if self.frame_mbs_only {
+            self.height_in_map_units
+        } else {
+            self.height_in_map_units * 2
+        };
+        self.width_in_mbs * frame_height_in_mbs
+    }
+}
+
+struct Pps {
+    id: u32,
+    bottom_field_pic_order_in_frame_present: bool,
+    deblocking_filter_control_present: bool,
+    redundant_pic_cnt_present: bool,
+}
+
+impl Pps {
+    fn parse(nal: &[u8]) -> Result<Self> {
+        let rbsp = remove_emulation_prevention(nal.get(1..).unwrap_or_default());
+        let mut bits = BitReader::new(&rbsp);
+
+        let id = bits.read_ue()?;
+        let _seq_parameter_set_id = bits.read_ue()?;
+        if bits.read_bit()? {
+            return Err(SyntheticKeyframeError::Unsupported("cabac"));
+        }
+        let bottom_field_pic_order_in_frame_present = bits.read_bit()?;
+        if bits.read_ue()? != 0 {
+            return Err(SyntheticKeyframeError::Unsupported("slice groups"));
+        }
+        let _num_ref_idx_l0_default_active_minus1 = bits.read_ue()?;
+        let _num_ref_idx_l1_default_active_minus1 = bits.read_ue()?;
+        let _weighted_pred_flag = bits.read_bit()?;
+        let _weighted_bipred_idc = bits.read_bits(2)?;
+        let _pic_init_qp_minus26 = bits.read_se()?;
+        let _pic_init_qs_minus26 = bits.read_se()?;
+        let _chroma_qp_index_offset = bits.read_se()?;
+        let deblocking_filter_control_present = bits.read_bit()?;
+        let _constrained_intra_pred_flag = bits.read_bit()?;
+        let redundant_pic_cnt_present = bits.read_bit()?;
+
+        Ok(Pps {
+            id,
+            bottom_field_pic_order_in_frame_present,
+            deblocking_filter_control_present,
+            redundant_pic_cnt_present,
+        })
+    }
+}
+
+struct BitReader<'buf> {
+    buf: &'buf [u8],
+    pos: usize,
+}
+
+impl<'buf> BitReader<'buf> {
+    fn new(buf: &'buf [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn read_bit(&mut self) -> Result<bool> {
+        let byte = self
+            .buf
+            .get(self.pos / 8)
+            .ok_or(SyntheticKeyframeError::Truncated)?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1 == 1;
+        self.pos += 1;
+        Ok(bit)
+    }
+
+    fn read_bits(&mut self, n: u32) -> Result<u32> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = (value << 1) | self.read_bit()? as u32;
+        }
+        Ok(value)
+    }
+
+    fn read_ue(&mut self) -> Result<u32> {
+        let mut leading_zeros = 0;
+        while !self.read_bit()? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return Err(SyntheticKeyframeError::Truncated);
+            }
+        }
+        Ok((1 << leading_zeros) - 1 + self.read_bits(leading_zeros)?)
+    }
+
+    fn read_se(&mut self) -> Result<i32> {
+        let value = self.read_ue()?;
+        if value % 2 == 1 {
+            Ok(value.div_ceil(2) as i32)
+        } else {
+            Ok(-((value / 2) as i32))
+        }
+    }
+}
+
+struct BitWriter {
+    buf: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            len: 0,
+        }
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        if self.len / 8 == self.buf.len() {
+            self.buf.push(0);
+        }
+        if bit {
+            *self.buf.last_mut().unwrap() |= 1 << (7 - self.len % 8);
+        }
+        self.len += 1;
+    }
+
+    fn write_bits(&mut self, value: u32, n: u32) {
+        for i in (0..n).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+
+    fn write_ue(&mut self, value: u32) {
+        let value = value as u64 + 1;
+        let len = 64 - value.leading_zeros();
+        self.write_bits(0, len - 1);
+        for i in (0..len).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+
+    fn write_se(&mut self, value: i32) {
+        if value > 0 {
+            self.write_ue(value as u32 * 2 - 1);
+        } else {
+            self.write_ue(value.unsigned_abs() * 2);
+        }
+    }
+
+    /// Finish with `rbsp_trailing_bits` and return the RBSP.
+    fn finish(mut self) -> Vec<u8> {
+        self.write_bit(true);
+        self.buf
+    }
+}
+
+fn remove_emulation_prevention(buf: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(buf.len());
+    let mut zeros = 0;
+    for &byte in buf {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0x00 { zeros + 1 } else { 0 };
+        out.push(byte);
+    }
+    out
+}
+
+fn add_emulation_prevention(buf: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(buf.len());
+    let mut zeros = 0;
+    for &byte in buf {
+        if zeros >= 2 && byte <= 0x03 {
+            out.push(0x03);
+            zeros = 0;
+        }
+        zeros = if byte == 0x00 { zeros + 1 } else { 0 };
+        out.push(byte);
+    }
+    out
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+
+    use super::*;
+
+    // Baseline profile, 32x16 pixels (two macroblocks), `pic_order_cnt_type`
+    // 2 and single PPS with deblocking filter control present.
+    pub(crate) const SPS: [u8; 7] = [0x67, 0x42, 0x00, 0x1e, 0xda, 0x2e, 0x40];
+    pub(crate) const PPS: [u8; 4] = [0x68, 0xce, 0x3c, 0x80];
+
+    #[test]
+    fn generate_gray_idr() {
+        assert_eq!(
+            gray_idr(&SPS, &PPS).unwrap(),
+            vec![0x65, 0x88, 0x84, 0xa2, 0x72, 0x78],
+        );
+    }
+
+    #[test]
+    fn generate_gray_idr_cabac_unsupported() {
+        assert_eq!(
+            gray_idr(&SPS, &[0x68, 0xee, 0x3c, 0x80]),
+            Err(SyntheticKeyframeError::Unsupported("cabac")),
+        );
+    }
+
+    #[test]
+    fn generate_gray_idr_truncated() {
+        assert_eq!(
+            gray_idr(&SPS[..4], &PPS),
+            Err(SyntheticKeyframeError::Truncated),
+        );
+    }
+
+    #[test]
+    fn emulation_prevention_round_trip() {
+        let rbsp = [0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x03];
+        let escaped = add_emulation_prevention(&rbsp);
+        assert_eq!(
+            escaped,
+            vec![0x00, 0x00, 0x03, 0x01, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03, 0x03],
+        );
+        assert_eq!(remove_emulation_prevention(&escaped), rbsp);
+    }
+}
@@ -1,2 +1,4 @@
+pub mod drift;
+pub mod h264;
 pub mod reader;
 pub mod rtp_muxer;
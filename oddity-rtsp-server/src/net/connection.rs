@@ -1,9 +1,14 @@
+use std::collections::HashMap;
 use std::fmt;
 use std::io::ErrorKind;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
 
 use futures::SinkExt;
 
+use tokio::io::AsyncReadExt;
 use tokio::net;
 use tokio::select;
 use tokio::sync::mpsc;
@@ -15,6 +20,7 @@ use oddity_rtsp_protocol::{
 };
 
 use crate::net::handler::Handler;
+use crate::net::status;
 use crate::runtime::task_manager::{Task, TaskContext};
 use crate::runtime::Runtime;
 
@@ -26,8 +32,199 @@ pub enum ConnectionState {
 pub type ConnectionStateTx = mpsc::UnboundedSender<ConnectionState>;
 pub type ConnectionStateRx = mpsc::UnboundedReceiver<ConnectionState>;
 
-pub type ResponseSenderTx = mpsc::UnboundedSender<ResponseMaybeInterleaved>;
-pub type ResponseSenderRx = mpsc::UnboundedReceiver<ResponseMaybeInterleaved>;
+/// Sending half of the queue of messages to write to the client. Keeps
+/// track of how many messages are waiting to be written, which serves as
+/// congestion signal for the sessions that stream over the connection.
+///
+/// The queue is bounded for messages that may be dropped, such as RTP:
+/// if the client stops reading, they are dropped rather than buffered
+/// without bound. Other messages, such as RTCP, are always queued.
+#[derive(Debug, Clone)]
+pub struct ResponseSenderTx {
+    tx: mpsc::UnboundedSender<ResponseMaybeInterleaved>,
+    backlog: Arc<Backlog>,
+}
+
+impl ResponseSenderTx {
+    /// Messages that may be dropped are only queued while fewer than this
+    /// many messages are waiting to be written. Since RTP packets are at
+    /// most about 1500 bytes, this bounds the backlog to a few megabytes.
+    pub const MAX_BACKLOG: usize = 2048;
+
+    pub fn send(
+        &self,
+        message: ResponseMaybeInterleaved,
+    ) -> Result<(), mpsc::error::SendError<ResponseMaybeInterleaved>> {
+        let len = payload_len(&message);
+        self.backlog.add(len);
+        self.tx.send(message).inspect_err(|_| {
+            self.backlog.sub(len);
+        })
+    }
+
+    /// Queue a message unless the backlog is full, in which case the mes-
+    /// sage is dropped. Returns whether the message was queued.
+    pub fn send_or_drop(
+        &self,
+        message: ResponseMaybeInterleaved,
+    ) -> Result<bool, mpsc::error::SendError<ResponseMaybeInterleaved>> {
+        let len = payload_len(&message);
+        if !self.backlog.try_add(len, Self::MAX_BACKLOG) {
+            return Ok(false);
+        }
+        self.tx.send(message).map(|()| true).inspect_err(|_| {
+            self.backlog.sub(len);
+        })
+    }
+
+    /// Number of messages that have been queued but not yet written to
+    /// the client.
+    pub fn backlog(&self) -> usize {
+        self.backlog.messages.load(Ordering::Relaxed)
+    }
+
+    /// Number of payload bytes in the messages that have been queued but
+    /// not yet written to the client.
+    pub fn backlog_bytes(&self) -> usize {
+        self.backlog.bytes.load(Ordering::Relaxed)
+    }
+}
+
+pub struct ResponseSenderRx {
+    rx: mpsc::UnboundedReceiver<ResponseMaybeInterleaved>,
+    backlog: Arc<Backlog>,
+}
+
+impl ResponseSenderRx {
+    pub async fn recv(&mut self) -> Option<ResponseMaybeInterleaved> {
+        let message = self.rx.recv().await;
+        if let Some(message) = message.as_ref() {
+            self.backlog.sub(payload_len(message));
+        }
+        message
+    }
+}
+
+#[derive(Debug, Default)]
+struct Backlog {
+    messages: AtomicUsize,
+    bytes: AtomicUsize,
+}
+
+impl Backlog {
+    fn add(&self, bytes: usize) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Add a message unless there are `max` messages already.
+    fn try_add(&self, bytes: usize, max: usize) -> bool {
+        let added = self
+            .messages
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |messages| {
+                (messages < max).then_some(messages + 1)
+            })
+            .is_ok();
+        if added {
+            self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        }
+        added
+    }
+
+    fn sub(&self, bytes: usize) {
+        self.messages.fetch_sub(1, Ordering::Relaxed);
+        self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+fn payload_len(message: &ResponseMaybeInterleaved) -> usize {
+    match message {
+        ResponseMaybeInterleaved::Message(response) => {
+            response.body.as_ref().map(|body| body.len()).unwrap_or(0)
+        }
+        ResponseMaybeInterleaved::Interleaved { payload, .. } => payload.len(),
+    }
+}
+
+pub fn response_channel() -> (ResponseSenderTx, ResponseSenderRx) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let backlog = Arc::new(Backlog::default());
+    (
+        ResponseSenderTx {
+            tx,
+            backlog: backlog.clone(),
+        },
+        ResponseSenderRx { rx, backlog },
+    )
+}
+
+pub type InterleavedTx = mpsc::UnboundedSender<Bytes>;
+pub type InterleavedRx = mpsc::UnboundedReceiver<Bytes>;
+
+/// Routes interleaved data received from the client to the receiver
+/// registered for the channel it was sent on. Also keeps track of the
+/// pairs of channels that the sessions on the connection occupy, so that
+/// the RTP of two sessions is never sent over the same channel.
+#[derive(Clone, Default)]
+pub struct InterleavedReceivers(Arc<Mutex<HashMap<u8, InterleavedClaim>>>);
+
+/// Channels occupied by a session, keyed by its RTCP channel in
+/// [`InterleavedReceivers`].
+struct InterleavedClaim {
+    rtp_channel: u8,
+    tx: InterleavedTx,
+}
+
+impl InterleavedReceivers {
+    /// Claim the given pair of channels and register to receive data sent
+    /// over the RTCP channel. The channels stay occupied until the re-
+    /// ceiver is dropped. Returns `None` if either channel is occupied
+    /// already, or if both are the same channel.
+    pub fn claim(&self, rtp_channel: u8, rtcp_channel: u8) -> Option<InterleavedRx> {
+        let mut receivers = self.0.lock().unwrap();
+        receivers.retain(|_, claim| !claim.tx.is_closed());
+        let occupied = |channel: u8| {
+            receivers.iter().any(|(rtcp_channel, claim)| {
+                *rtcp_channel == channel || claim.rtp_channel == channel
+            })
+        };
+        if rtp_channel == rtcp_channel || occupied(rtp_channel) || occupied(rtcp_channel) {
+            return None;
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        receivers.insert(rtcp_channel, InterleavedClaim { rtp_channel, tx });
+        Some(rx)
+    }
+
+    /// Claim the first free pair of an even RTP channel and the odd RTCP
+    /// channel after it, see [`InterleavedReceivers::claim`]. Returns `None`
+    /// if no pair is free.
+    pub fn claim_free_pair(&self) -> Option<(u8, u8, InterleavedRx)> {
+        (0..=u8::MAX - 1).step_by(2).find_map(|rtp_channel| {
+            self.claim(rtp_channel, rtp_channel + 1)
+                .map(|rx| (rtp_channel, rtp_channel + 1, rx))
+        })
+    }
+
+    /// Dispatch data to receiver of channel. Returns `false` if there is
+    /// no one listening on that channel.
+    fn dispatch(&self, channel: u8, payload: Bytes) -> bool {
+        let mut receivers = self.0.lock().unwrap();
+        match receivers.get(&channel) {
+            Some(InterleavedClaim { tx, .. }) => {
+                if tx.send(payload).is_ok() {
+                    true
+                } else {
+                    // Receiver is gone, so there is no reason to hold on
+                    // to the registration any longer.
+                    receivers.remove(&channel);
+                    false
+                }
+            }
+            None => false,
+        }
+    }
+}
 
 pub struct Connection {
     worker: Task,
@@ -41,7 +238,7 @@ impl Connection {
         state_tx: ConnectionStateTx,
         runtime: &Runtime,
     ) -> Self {
-        let (sender_tx, sender_rx) = mpsc::unbounded_channel();
+        let (sender_tx, sender_rx) = response_channel();
 
         tracing::trace!(%id, "starting connection");
         let worker = runtime
@@ -79,18 +276,58 @@ impl Connection {
         mut task_context: TaskContext,
     ) {
         let mut disconnected = false;
+        let interleaved = InterleavedReceivers::default();
 
-        let addr = inner
-            .peer_addr()
+        let peer_addr = inner.peer_addr().ok();
+        let addr = peer_addr
             .map(|peer_addr| peer_addr.to_string())
             .unwrap_or("?".to_string());
-        let (read, write) = inner.into_split();
+        let (mut read, mut write) = inner.into_split();
+
+        // If the status page is enabled, find out whether the client is just a browser or `curl`
+        // with a plain HTTP request before handing the connection to the RTSP codec.
+        let mut probed = Vec::new();
+        if handler.status_path().is_some() {
+            select! {
+              // CANCEL SAFETY: Not cancel safe, but the connection is dropped if cancelled.
+              probe = status::probe(&mut read) => {
+                match probe {
+                  Ok(status::Probe::Rtsp(buf)) => {
+                    probed = buf;
+                  },
+                  Ok(status::Probe::HttpGet(path)) => {
+                    tracing::debug!(%id, %addr, %path, "connection: status page requested");
+                    let body = handler.status_page(&path).await;
+                    if let Err(err) = status::respond(&mut write, body).await {
+                      tracing::debug!(%err, %id, %addr, "connection: failed to send status page");
+                    }
+                    let _ = state_tx.send(ConnectionState::Disconnected(id));
+                    return;
+                  },
+                  Err(err) => {
+                    tracing::error!(%err, %id, %addr, "connection: failed to read request");
+                    let _ = state_tx.send(ConnectionState::Closed(id));
+                    return;
+                  },
+                }
+              },
+              // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
+              _ = task_context.wait_for_stop() => {
+                tracing::trace!(%id, %addr, "connection worker stopping");
+                let _ = state_tx.send(ConnectionState::Closed(id));
+                return;
+              },
+            }
+        }
+
+        let read = std::io::Cursor::new(probed).chain(read);
         let mut inbound = codec::FramedRead::new(read, Codec::<AsServer>::new());
         let mut outbound = codec::FramedWrite::new(write, Codec::<AsServer>::new());
 
         loop {
             select! {
-              // CANCEL SAFETY: `mpsc::UnboundedReceiver::recv` is cancel safe.
+              // CANCEL SAFETY: `ResponseSenderRx::recv` is cancel safe since it only wraps
+              // `mpsc::UnboundedReceiver::recv`, which is cancel safe.
               message = response_rx.recv() => {
                 match message {
                   Some(message) => {
@@ -118,7 +355,7 @@ impl Connection {
                   Some(Ok(request)) => {
                     match request {
                       RequestMaybeInterleaved::Message(request) => {
-                        let response = handler.handle(&request, &response_tx).await;
+                        let response = handler.handle(&request, peer_addr, &response_tx, &interleaved).await;
                         let response = ResponseMaybeInterleaved::Message(response);
                         match outbound.send(response).await {
                           Ok(()) => {},
@@ -133,8 +370,10 @@ impl Connection {
                           },
                         }
                       },
-                      RequestMaybeInterleaved::Interleaved { channel, .. } => {
-                        tracing::debug!(%id, %addr, %channel, "ignored request with interleaved data");
+                      RequestMaybeInterleaved::Interleaved { channel, payload } => {
+                        if !interleaved.dispatch(channel, payload) {
+                          tracing::debug!(%id, %addr, %channel, "ignored request with interleaved data");
+                        }
                       },
                     }
                   },
@@ -202,3 +441,55 @@ impl ConnectionIdGenerator {
         ConnectionId::new(id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use oddity_rtsp_protocol::ResponseMaybeInterleaved;
+
+    use super::{response_channel, InterleavedReceivers, ResponseSenderTx};
+
+    fn interleaved(channel: u8) -> ResponseMaybeInterleaved {
+        ResponseMaybeInterleaved::Interleaved {
+            channel,
+            payload: vec![0; 1400].into(),
+        }
+    }
+
+    #[test]
+    fn stalled_client_drops_instead_of_buffering() {
+        // Nothing reads from the receiver, like a client that stopped reading.
+        let (sender, _receiver) = response_channel();
+        let mut dropped = 0;
+        for _ in 0..ResponseSenderTx::MAX_BACKLOG + 100 {
+            if !sender.send_or_drop(interleaved(0)).unwrap() {
+                dropped += 1;
+            }
+        }
+        assert_eq!(dropped, 100);
+        assert_eq!(sender.backlog(), ResponseSenderTx::MAX_BACKLOG);
+        assert_eq!(sender.backlog_bytes(), ResponseSenderTx::MAX_BACKLOG * 1400);
+
+        // Messages that must not be dropped still go through.
+        sender.send(interleaved(1)).unwrap();
+        assert_eq!(sender.backlog(), ResponseSenderTx::MAX_BACKLOG + 1);
+    }
+
+    #[test]
+    fn interleaved_channels_are_claimed_once() {
+        let receivers = InterleavedReceivers::default();
+        let (rtp_channel, rtcp_channel, first) = receivers.claim_free_pair().unwrap();
+        assert_eq!((rtp_channel, rtcp_channel), (0, 1));
+        let (rtp_channel, rtcp_channel, _second) = receivers.claim_free_pair().unwrap();
+        assert_eq!((rtp_channel, rtcp_channel), (2, 3));
+
+        assert!(receivers.claim(1, 2).is_none());
+        assert!(receivers.claim(5, 0).is_none());
+        assert!(receivers.claim(4, 4).is_none());
+        assert!(receivers.claim(4, 9).is_some());
+
+        // Channels are free again once the session is gone.
+        drop(first);
+        assert!(receivers.claim(0, 1).is_some());
+    }
+}
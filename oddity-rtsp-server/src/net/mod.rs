@@ -2,3 +2,4 @@ pub mod connection;
 pub mod connection_manager;
 pub mod handler;
 pub mod server;
+pub mod status;
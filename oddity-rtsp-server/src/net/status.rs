@@ -0,0 +1,142 @@
+//! Minimal HTTP status page on the RTSP port. A plain HTTP `GET` can be
+//! told apart from RTSP by its first bytes, since there is no RTSP meth-
+//! od called `GET`. This is just enough HTTP for a browser or `curl` to
+//! fetch the status, not a general purpose HTTP server.
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const METHOD_GET: &[u8] = b"GET ";
+const END_OF_HEAD: &[u8] = b"\r\n\r\n";
+const MAX_HEAD_LEN: usize = 8192;
+
+/// Result of probing the start of a connection.
+pub enum Probe {
+    /// Connection speaks RTSP (or at least not HTTP). Contains the bytes
+    /// that were read while probing, which must be fed to the RTSP codec
+    /// before anything else.
+    Rtsp(Vec<u8>),
+    /// Connection sent an HTTP `GET` for the contained path.
+    HttpGet(String),
+}
+
+/// Read just enough from the connection to decide whether it is an HTTP
+/// `GET` request. If it is, the complete request head is consumed.
+pub async fn probe(read: &mut (impl AsyncRead + Unpin)) -> io::Result<Probe> {
+    let mut buf = Vec::new();
+    while buf.len() < METHOD_GET.len() && METHOD_GET.starts_with(&buf) {
+        if read.read_buf(&mut buf).await? == 0 {
+            return Ok(Probe::Rtsp(buf));
+        }
+    }
+    if !buf.starts_with(METHOD_GET) {
+        return Ok(Probe::Rtsp(buf));
+    }
+
+    let mut searched = 0;
+    while !buf[searched..]
+        .windows(END_OF_HEAD.len())
+        .any(|window| window == END_OF_HEAD)
+    {
+        if buf.len() > MAX_HEAD_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "http request head too large",
+            ));
+        }
+        searched = buf.len().saturating_sub(END_OF_HEAD.len() - 1);
+        if read.read_buf(&mut buf).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+    }
+
+    request_path(&buf)
+        .map(|path| Probe::HttpGet(path.to_string()))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "http request line malformed"))
+}
+
+/// Write an HTTP response with the given JSON body, or `404 Not Found`
+/// if there is no body, and close the connection.
+pub async fn respond(
+    write: &mut (impl AsyncWrite + Unpin),
+    body: Option<String>,
+) -> io::Result<()> {
+    write.write_all(&response(body)).await?;
+    write.shutdown().await
+}
+
+/// Extract the path from the request line of an HTTP `GET` request,
+/// without any query string.
+fn request_path(head: &[u8]) -> Option<&str> {
+    let line = head.split(|&byte| byte == b'\r').next()?;
+    let line = std::str::from_utf8(line).ok()?;
+    let mut parts = line.split(' ');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some("GET"), Some(target), Some(version), None) if version.starts_with("HTTP/") => {
+            target.split('?').next()
+        }
+        _ => None,
+    }
+}
+
+fn response(body: Option<String>) -> Vec<u8> {
+    let (status, content_type, body) = match body {
+        Some(body) => ("200 OK", "application/json", body),
+        None => ("404 Not Found", "text/plain", "not found\n".to_string()),
+    };
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+    .into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{probe, request_path, response, Probe};
+
+    #[test]
+    fn parse_request_path() {
+        assert_eq!(
+            request_path(b"GET /status?pretty HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+            Some("/status"),
+        );
+        assert_eq!(request_path(b"GET /status\r\n\r\n"), None);
+        assert_eq!(
+            request_path(b"GET rtsp://localhost/ RTSP/1.0\r\n\r\n"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn probe_http_get() {
+        let mut read = &b"GET /status HTTP/1.1\r\nHost: localhost\r\n\r\n"[..];
+        assert!(matches!(
+            probe(&mut read).await.unwrap(),
+            Probe::HttpGet(path) if path == "/status",
+        ));
+    }
+
+    #[tokio::test]
+    async fn probe_rtsp_returns_bytes_read() {
+        let request = b"OPTIONS rtsp://localhost/ RTSP/1.0\r\nCSeq: 1\r\n\r\n";
+        let mut read = &request[..];
+        match probe(&mut read).await.unwrap() {
+            Probe::Rtsp(buf) => {
+                // Whatever was consumed while probing must be handed back.
+                assert_eq!([&buf[..], read].concat(), request);
+            }
+            Probe::HttpGet(_) => panic!("probed rtsp request as http"),
+        }
+    }
+
+    #[test]
+    fn response_not_found() {
+        assert!(response(None).starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+    }
+}
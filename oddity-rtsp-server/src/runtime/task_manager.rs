@@ -28,7 +28,6 @@ impl TaskManager {
         }
     }
 
-    #[allow(clippy::let_underscore_future)]
     pub async fn spawn<F, T>(&self, f: F) -> Task
     where
         F: FnOnce(TaskContext) -> T + Send + 'static,
@@ -44,7 +43,7 @@ impl TaskManager {
             let (hold_tx, hold_rx) = oneshot::channel();
             let (stop_tx, stop_rx) = mpsc::channel(1);
             let stop_all_rx = self.stop_tx.subscribe();
-            let _ = task::spawn(async move {
+            let handle = task::spawn(async move {
                 // Instantiate task context here. After the fut-
                 // ure genrated by `f` has finished, it will be
                 // dropped automatically, which will cause the
@@ -58,7 +57,7 @@ impl TaskManager {
 
                 f(task_context).await;
             });
-            Task::new(hold_rx, stop_tx)
+            Task::new(hold_rx, stop_tx, handle.abort_handle())
         } else {
             Task::none()
         }
@@ -89,13 +88,19 @@ impl TaskManager {
 pub struct Task {
     hold: Option<oneshot::Receiver<()>>,
     stop: Option<mpsc::Sender<()>>,
+    abort: Option<task::AbortHandle>,
 }
 
 impl Task {
-    pub fn new(hold_rx: oneshot::Receiver<()>, stop_tx: mpsc::Sender<()>) -> Task {
+    pub fn new(
+        hold_rx: oneshot::Receiver<()>,
+        stop_tx: mpsc::Sender<()>,
+        abort: task::AbortHandle,
+    ) -> Task {
         Self {
             hold: Some(hold_rx),
             stop: Some(stop_tx),
+            abort: Some(abort),
         }
     }
 
@@ -103,6 +108,24 @@ impl Task {
         Task {
             hold: None,
             stop: None,
+            abort: None,
+        }
+    }
+
+    /// Handle to abort the task with, for when it does not respond to the
+    /// stop signal. Aborting drops the task wherever it is suspended, so it
+    /// does not get to clean up.
+    pub fn abort_handle(&self) -> Option<task::AbortHandle> {
+        self.abort.clone()
+    }
+
+    /// Whether the task has finished, either because it returned, panick-
+    /// ed or was aborted. Does not block. A task that was never started
+    /// counts as finished.
+    pub fn is_finished(&self) -> bool {
+        match &self.abort {
+            Some(abort) => abort.is_finished(),
+            None => true,
         }
     }
 
@@ -0,0 +1,275 @@
+use std::error;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time;
+
+use oddity_rtsp_protocol as rtsp;
+
+use crate::session::setup::SessionSetup;
+use crate::source::SourcePathRef;
+
+pub type AdmissionFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<(), AdmissionError>> + Send + 'a>>;
+
+/// Programmable admission control. The session manager consults the
+/// admission policy right before it commits any resources for a new
+/// session of the source at `source_path`. Implementers can consult any
+/// external state (a quota service, a license server, time-of-day rules,
+/// ...) to decide, see [`HttpAdmission`] for one that asks a service.
+///
+/// We cannot use `async fn` here since the policy is stored as a trait
+/// object, so implementers return a boxed future instead, for example:
+///
+/// ```ignore
+/// impl Admission for MyPolicy {
+///     fn admit<'a>(
+///         &'a self,
+///         source_path: &'a SourcePathRef,
+///         setup: &'a SessionSetup,
+///     ) -> AdmissionFuture<'a> {
+///         Box::pin(async move { self.quota_service.check(source_path, setup).await })
+///     }
+/// }
+/// ```
+pub trait Admission: Send + Sync {
+    fn admit<'a>(
+        &'a self,
+        source_path: &'a SourcePathRef,
+        setup: &'a SessionSetup,
+    ) -> AdmissionFuture<'a>;
+}
+
+/// Default admission policy that admits every session.
+pub struct AlwaysAdmit;
+
+impl Admission for AlwaysAdmit {
+    fn admit<'a>(
+        &'a self,
+        _source_path: &'a SourcePathRef,
+        _setup: &'a SessionSetup,
+    ) -> AdmissionFuture<'a> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Admission policy that asks an HTTP service whether to admit each ses-
+/// sion. The service gets a `GET` request for its path, with the path of
+/// the source and the IP address of the client (if known) in the query:
+///
+/// ```text
+/// GET /admit?path=%2Fcamera&client=10.0.0.1 HTTP/1.1
+/// ```
+///
+/// Any `2xx` status admits the session. `403 Forbidden` and `453 Not
+/// Enough Bandwidth` are passed on to the client. The session is refused
+/// as unavailable if the service replies with anything else, or does not
+/// reply in time.
+pub struct HttpAdmission {
+    address: SocketAddr,
+    path: String,
+    timeout: Duration,
+}
+
+impl HttpAdmission {
+    /// Time that the service gets to reply unless configured otherwise.
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// Longest status line of the service that is accepted.
+    const MAX_STATUS_LINE_LEN: u64 = 1024;
+
+    pub fn new(address: SocketAddr, path: String) -> Self {
+        Self {
+            address,
+            path,
+            timeout: Self::DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Give the service the given time to reply instead of the default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Ask the service about a session, and return the status it replied
+    /// with.
+    async fn ask(&self, source_path: &SourcePathRef, client_ip: Option<IpAddr>) -> io::Result<u16> {
+        let mut query = format!("path={}", percent_encode(source_path));
+        if let Some(client_ip) = client_ip {
+            query.push_str(&format!(
+                "&client={}",
+                percent_encode(&client_ip.to_string())
+            ));
+        }
+        let request = format!(
+            "GET {}?{} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            self.path, query, self.address,
+        );
+
+        let mut stream = TcpStream::connect(self.address).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut status_line = String::new();
+        BufReader::new(stream.take(Self::MAX_STATUS_LINE_LEN))
+            .read_line(&mut status_line)
+            .await?;
+        parse_status(&status_line)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid status line"))
+    }
+}
+
+impl Admission for HttpAdmission {
+    fn admit<'a>(
+        &'a self,
+        source_path: &'a SourcePathRef,
+        setup: &'a SessionSetup,
+    ) -> AdmissionFuture<'a> {
+        Box::pin(async move {
+            match time::timeout(self.timeout, self.ask(source_path, setup.client_ip)).await {
+                Ok(Ok(status)) => decide(status),
+                Ok(Err(err)) => {
+                    tracing::warn!(%err, address = %self.address, "failed to ask admission service");
+                    Err(AdmissionError::Unavailable)
+                }
+                Err(_) => {
+                    tracing::warn!(address = %self.address, "admission service did not reply in time");
+                    Err(AdmissionError::Unavailable)
+                }
+            }
+        })
+    }
+}
+
+/// Decide on a session from the status that the admission service replied
+/// with.
+fn decide(status: u16) -> Result<(), AdmissionError> {
+    match status {
+        200..=299 => Ok(()),
+        403 => Err(AdmissionError::Forbidden),
+        453 => Err(AdmissionError::NotEnoughBandwidth),
+        _ => {
+            tracing::warn!(status, "unexpected reply from admission service");
+            Err(AdmissionError::Unavailable)
+        }
+    }
+}
+
+/// Status code in an HTTP status line such as `HTTP/1.1 200 OK`.
+fn parse_status(status_line: &str) -> Option<u16> {
+    let mut parts = status_line.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some(version), Some(status)) if version.starts_with("HTTP/") => status.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Percent-encode everything but unreserved characters (RFC 3986 section
+/// 2.3), so that `value` can go in a query.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum AdmissionError {
+    Forbidden,
+    NotEnoughBandwidth,
+    Unavailable,
+}
+
+impl AdmissionError {
+    /// RTSP status code to reply with when a session is not admitted.
+    pub fn status(&self) -> rtsp::Status {
+        match self {
+            AdmissionError::Forbidden => rtsp::Status::Forbidden,
+            AdmissionError::NotEnoughBandwidth => rtsp::Status::NotEnoughBandwidth,
+            AdmissionError::Unavailable => rtsp::Status::ServiceUnavailable,
+        }
+    }
+}
+
+impl fmt::Display for AdmissionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AdmissionError::Forbidden => write!(f, "forbidden"),
+            AdmissionError::NotEnoughBandwidth => write!(f, "not enough bandwidth"),
+            AdmissionError::Unavailable => write!(f, "unavailable"),
+        }
+    }
+}
+
+impl error::Error for AdmissionError {}
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::{decide, parse_status, percent_encode, AdmissionError, HttpAdmission};
+
+    #[tokio::test]
+    async fn asks_service_with_source_and_client() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let service = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 1024];
+            let len = stream.read(&mut request).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 453 Not Enough Bandwidth\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(request[..len].to_vec()).unwrap()
+        });
+
+        let admission = HttpAdmission::new(address, "/admit".to_string());
+        let client_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let status = admission.ask("/camera 1", Some(client_ip)).await.unwrap();
+        assert!(matches!(
+            decide(status),
+            Err(AdmissionError::NotEnoughBandwidth)
+        ));
+        assert!(service
+            .await
+            .unwrap()
+            .starts_with("GET /admit?path=%2Fcamera%201&client=10.0.0.1 HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn decide_by_status() {
+        assert!(decide(204).is_ok());
+        assert!(matches!(decide(403), Err(AdmissionError::Forbidden)));
+        assert!(matches!(decide(500), Err(AdmissionError::Unavailable)));
+        assert!(matches!(decide(302), Err(AdmissionError::Unavailable)));
+    }
+
+    #[test]
+    fn parse_status_line() {
+        assert_eq!(parse_status("HTTP/1.1 200 OK\r\n"), Some(200));
+        assert_eq!(parse_status("HTTP/1.0 403\r\n"), Some(403));
+        assert_eq!(parse_status("RTSP/1.0 200 OK\r\n"), None);
+        assert_eq!(parse_status(""), None);
+    }
+
+    #[test]
+    fn percent_encode_query_value() {
+        assert_eq!(percent_encode("/a b~c"), "%2Fa%20b~c");
+        assert_eq!(percent_encode("::1"), "%3A%3A1");
+    }
+}
@@ -0,0 +1,83 @@
+//! Single cancellation primitive for session workers.
+//!
+//! A session can be stopped for many reasons: a `TEARDOWN` from the cli-
+//! ent, the server shutting down, its source going away or being drained,
+//! or the client reconnecting elsewhere. All of these trip the same can-
+//! cellation, which records why the session was cancelled. The session
+//! loops select on only this one cancellation instead of on every mecha-
+//! nism separately.
+
+use std::sync::{Arc, Mutex};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::session::StopReason;
+
+#[derive(Clone, Default)]
+pub struct Cancellation {
+    token: CancellationToken,
+    reason: Arc<Mutex<Option<StopReason>>>,
+}
+
+impl Cancellation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel with the given reason. If the cancellation was tripped be-
+    /// fore, the earlier reason is retained and `false` is returned.
+    pub fn cancel(&self, reason: StopReason) -> bool {
+        {
+            let mut current = self.reason.lock().unwrap();
+            if current.is_some() {
+                return false;
+            }
+            *current = Some(reason);
+        }
+        self.token.cancel();
+        true
+    }
+
+    /// Reason of cancellation, if cancelled.
+    pub fn reason(&self) -> Option<StopReason> {
+        *self.reason.lock().unwrap()
+    }
+
+    /// Wait until cancelled and return the reason.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancel safe.
+    pub async fn cancelled(&self) -> StopReason {
+        self.token.cancelled().await;
+        // The reason is always set before the token is cancelled.
+        self.reason().unwrap_or(StopReason::Teardown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::Cancellation;
+    use crate::session::StopReason;
+
+    #[tokio::test]
+    async fn first_reason_wins() {
+        let cancellation = Cancellation::new();
+        assert_eq!(cancellation.reason(), None);
+        assert!(cancellation.cancel(StopReason::Evicted));
+        assert!(!cancellation.clone().cancel(StopReason::Teardown));
+        assert_eq!(cancellation.cancelled().await, StopReason::Evicted);
+    }
+
+    #[tokio::test]
+    async fn cancel_from_other_task() {
+        let cancellation = Cancellation::new();
+        let waiter = tokio::spawn({
+            let cancellation = cancellation.clone();
+            async move { cancellation.cancelled().await }
+        });
+        cancellation.cancel(StopReason::Evicted);
+        assert_eq!(waiter.await.unwrap(), StopReason::Evicted);
+    }
+}
@@ -0,0 +1,92 @@
+//! Limits on the sessions of a single client, so that one client that
+//! hammers the server with SETUP requests cannot crowd out the rest.
+//! Clients are told apart by their IP address.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Limits that apply to each client. Clients are not limited by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientLimits {
+    /// Most sessions a client may have at the same time.
+    pub max_sessions: Option<usize>,
+    /// Most sessions a client may set up within a sliding window.
+    pub setup_rate: Option<SetupRate>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetupRate {
+    pub max_setups: usize,
+    pub window: Duration,
+}
+
+impl SetupRate {
+    /// Window unless configured otherwise.
+    pub const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+}
+
+/// When clients set up their recent sessions.
+#[derive(Debug, Default)]
+pub struct SetupHistory(HashMap<IpAddr, VecDeque<Instant>>);
+
+impl SetupHistory {
+    /// Record a setup by `client_ip` at `now`, unless the client already
+    /// set up as many sessions as `rate` allows in the window that ends at
+    /// `now`. Returns whether the setup was recorded.
+    pub fn admit(&mut self, client_ip: IpAddr, rate: &SetupRate, now: Instant) -> bool {
+        // Forget setups that fell out of the window, and clients without
+        // any left, so that the history does not grow without bound.
+        self.0.retain(|_, setups| {
+            while let Some(at) = setups.front() {
+                if now.saturating_duration_since(*at) < rate.window {
+                    break;
+                }
+                setups.pop_front();
+            }
+            !setups.is_empty()
+        });
+
+        let setups = self.0.entry(client_ip).or_default();
+        if setups.len() >= rate.max_setups {
+            return false;
+        }
+        setups.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::{SetupHistory, SetupRate};
+
+    #[test]
+    fn window_slides() {
+        let client_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let other_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let rate = SetupRate {
+            max_setups: 2,
+            window: Duration::from_secs(10),
+        };
+        let start = Instant::now();
+        let mut history = SetupHistory::default();
+        assert!(history.admit(client_ip, &rate, start));
+        assert!(history.admit(client_ip, &rate, start + Duration::from_secs(5)));
+        assert!(!history.admit(client_ip, &rate, start + Duration::from_secs(9)));
+        assert!(history.admit(other_ip, &rate, start + Duration::from_secs(9)));
+        // The first setup falls out of the window, the second does not.
+        assert!(history.admit(client_ip, &rate, start + Duration::from_secs(10)));
+        assert!(!history.admit(client_ip, &rate, start + Duration::from_secs(14)));
+        // Clients whose setups all fell out of the window are forgotten.
+        assert!(history.admit(client_ip, &rate, start + Duration::from_secs(30)));
+        assert_eq!(history.0.len(), 1);
+    }
+}
@@ -0,0 +1,728 @@
+//! Delivery of the muxed output of a session to its target. Sessions run
+//! the same loop whatever their target, see [`SessionLoop`], and leave
+//! everything that depends on the target to a [`Delivery`].
+
+use std::collections::VecDeque;
+use std::future;
+use std::iter;
+
+use bytes::Bytes;
+
+use tokio::net::UdpSocket;
+use tokio::select;
+
+use oddity_rtsp_protocol as rtsp;
+use video_rs as video;
+
+use crate::session::interleaved::InterleavedQueue;
+use crate::session::memory::MemoryAccount;
+use crate::session::pacing;
+use crate::session::repacketize::Repacketizer;
+use crate::session::setup::{RtcpMode, SendInterleaved, SendOverSocket, SendToSink};
+use crate::session::udp::{self, MulticastGroup};
+use crate::session::{rtp, Session, SessionLoop, StopReason};
+
+/// What the client of a session sent, see [`Delivery::receive`].
+pub enum FromClient {
+    /// RTCP for the session.
+    Rtcp(Bytes),
+    /// Something other than RTCP that shows that the client is there.
+    Alive,
+    /// Nothing that concerns the session.
+    Nothing,
+}
+
+/// Delivery of a session, whatever its target.
+pub enum Target {
+    Udp(SendUdp),
+    TcpInterleaved(SendTcpInterleaved),
+    Sink(SendSink),
+}
+
+/// Target of a session.
+pub trait Delivery {
+    /// Rework the output of the muxer before the session handles it.
+    fn prepare(&mut self, packet: Vec<video::RtpBuf>) -> Vec<video::RtpBuf> {
+        packet
+    }
+
+    /// Send muxed packets to the target. Packets wait in line behind those
+    /// that the pacing of the session holds back, see [`SessionLoop::pa-
+    /// ce`]. Returns the reason to stop the session if the target is gone.
+    async fn deliver(
+        &mut self,
+        session: &mut SessionLoop,
+        packet: Vec<video::RtpBuf>,
+    ) -> Result<(), StopReason>;
+
+    /// Send the packet that the pacing of the session held back, and the
+    /// packets queued after it for as long as the pacing allows. Called
+    /// by the session once the held back packet may be sent.
+    async fn flush(&mut self, _session: &mut SessionLoop) -> Result<(), StopReason> {
+        Ok(())
+    }
+
+    /// Wait for the client to send something. Pends forever if the target
+    /// has no way back from the client. Cancel safe.
+    async fn receive(&mut self) -> FromClient {
+        future::pending().await
+    }
+
+    /// Best effort: tell the client that the stream ended with an RTCP
+    /// BYE, if the session sends RTCP.
+    async fn send_bye(&mut self, _bye: Vec<u8>) {}
+
+    /// Release the target once the session stopped.
+    async fn close(&mut self) {}
+}
+
+/// Delivery of RTP and RTCP over UDP, to a client or multicast group.
+pub struct SendUdp {
+    target: SendOverSocket,
+    rtp_socket: UdpSocket,
+    /// There is no RTCP socket if RTCP is disabled. RTCP is only sent if
+    /// the session sends it, but the socket may still be there to listen.
+    rtcp_socket: Option<UdpSocket>,
+    rtcp_mode: RtcpMode,
+    repacketizer: Option<Repacketizer>,
+    /// Packets waiting for the pacing, after the packet it holds back.
+    queue: VecDeque<video::RtpBuf>,
+    /// Packet that the pacing holds back, which it was already paid for.
+    held: Option<video::RtpBuf>,
+    max_send_errors: usize,
+    send_errors: usize,
+    rtp_buf: Vec<u8>,
+    rtcp_buf: Vec<u8>,
+    rtp_remote_learned: bool,
+    rtcp_remote_learned: bool,
+    /// Whether the RTP socket is to be connected to the client before the
+    /// next send, because we did not try yet or learned another port.
+    rtp_connect: bool,
+    rtp_connected: bool,
+}
+
+impl SendUdp {
+    pub fn new(
+        target: SendOverSocket,
+        udp_sockets: (UdpSocket, Option<UdpSocket>),
+        rtcp_mode: RtcpMode,
+        max_send_errors: usize,
+    ) -> Self {
+        let (rtp_socket, rtcp_socket) = udp_sockets;
+        Self {
+            target,
+            rtp_socket,
+            rtcp_socket,
+            rtcp_mode,
+            repacketizer: None,
+            queue: VecDeque::new(),
+            held: None,
+            max_send_errors,
+            send_errors: 0,
+            rtcp_buf: vec![0; Session::MAX_RTCP_LEN],
+            // Only the address that packets come from matters, not what is in
+            // them, and we stop listening once the address is known.
+            rtp_buf: vec![0; Session::MAX_RTCP_LEN],
+            rtp_remote_learned: true,
+            rtcp_remote_learned: true,
+            rtp_connect: true,
+            rtp_connected: false,
+        }
+    }
+
+    /// Learn the ports of the client from the first packets it sends, see
+    /// [`SessionSetup::symmetric_rtp`](crate::session::setup::SessionSe-
+    /// tup::symmetric_rtp).
+    pub fn with_symmetric_rtp(mut self, symmetric_rtp: bool) -> Self {
+        self.rtp_remote_learned = !symmetric_rtp;
+        self.rtcp_remote_learned = !symmetric_rtp;
+        self.rtp_connect = !symmetric_rtp;
+        self
+    }
+
+    /// Fit RTP packets to an MTU with the given repacketizer.
+    pub fn with_repacketizer(mut self, repacketizer: Option<Repacketizer>) -> Self {
+        self.repacketizer = repacketizer;
+        self
+    }
+
+    /// Mark the traffic with the given DSCP value.
+    pub fn with_dscp(self, dscp: Option<u8>) -> Self {
+        if let Some(dscp) = dscp {
+            for socket in self.sockets() {
+                if let Err(err) = udp::set_dscp(socket, dscp) {
+                    tracing::warn!(%err, dscp, "failed to set dscp on socket");
+                }
+            }
+        }
+        self
+    }
+
+    /// Send to a multicast group.
+    pub fn with_multicast(self, multicast_group: &MulticastGroup) -> Self {
+        for socket in self.sockets() {
+            if let Err(err) = udp::set_multicast(socket, multicast_group) {
+                tracing::warn!(%err, ?multicast_group, "failed to configure multicast on socket");
+            }
+        }
+        self
+    }
+
+    fn sockets(&self) -> impl Iterator<Item = &UdpSocket> {
+        iter::once(&self.rtp_socket).chain(&self.rtcp_socket)
+    }
+
+    /// Socket to send RTCP over, if the session sends RTCP at all.
+    fn rtcp_send_socket(&self) -> Option<&UdpSocket> {
+        self.rtcp_socket.as_ref().filter(|_| self.rtcp_mode.sends())
+    }
+
+    /// Send a packet to the client. Returns the reason to stop the session
+    /// if too many sends in a row failed.
+    async fn send(
+        &mut self,
+        session: &mut SessionLoop,
+        item: video::RtpBuf,
+    ) -> Result<(), StopReason> {
+        let sent = match &item {
+            video::RtpBuf::Rtp(buf) if self.rtp_connected => self.rtp_socket.send(buf).await,
+            video::RtpBuf::Rtp(buf) => self.rtp_socket.send_to(buf, self.target.rtp_remote).await,
+            video::RtpBuf::Rtcp(buf) => match self.rtcp_send_socket() {
+                Some(rtcp_socket) => rtcp_socket.send_to(buf, self.target.rtcp_remote).await,
+                None => return Ok(()),
+            },
+        };
+        if let Err(err) = sent {
+            self.send_errors += 1;
+            tracing::debug!(id = %session.id, %err, send_errors = self.send_errors, "failed to send to client");
+            if self.send_errors >= self.max_send_errors {
+                session.fail(&err);
+                return Err(StopReason::TargetClosed);
+            }
+            return Ok(());
+        }
+        self.send_errors = 0;
+        session.record_sent(&item);
+        Ok(())
+    }
+}
+
+impl Delivery for SendUdp {
+    fn prepare(&mut self, packet: Vec<video::RtpBuf>) -> Vec<video::RtpBuf> {
+        match self.repacketizer.as_mut() {
+            Some(repacketizer) => repacketizer.repacketize(packet),
+            None => packet,
+        }
+    }
+
+    async fn deliver(
+        &mut self,
+        session: &mut SessionLoop,
+        packet: Vec<video::RtpBuf>,
+    ) -> Result<(), StopReason> {
+        for item in packet {
+            match item {
+                // RTCP is dropped if the session does not send it.
+                video::RtpBuf::Rtcp(_) if self.rtcp_send_socket().is_none() => {}
+                video::RtpBuf::Rtp(_) if self.queue.len() >= pacing::MAX_HELD_BACK => {
+                    session.counters.record_rtp_dropped();
+                }
+                item => self.queue.push_back(item),
+            }
+        }
+        if session.is_paced() {
+            return Ok(());
+        }
+        self.flush(session).await
+    }
+
+    async fn flush(&mut self, session: &mut SessionLoop) -> Result<(), StopReason> {
+        // Where supported, a connected socket reports ICMP port unreachable as an error on the next
+        // send, which tells us that the client is gone. The RTCP socket is not connected because
+        // it would then ignore RTCP that the client sends from other ports than the one it announced.
+        if self.rtp_connect {
+            self.rtp_connect = false;
+            self.rtp_connected =
+                Session::connect_udp(&session.id, &self.rtp_socket, self.target.rtp_remote).await;
+        }
+
+        if let Some(item) = self.held.take() {
+            self.send(session, item).await?;
+        }
+        while let Some(item) = self.queue.pop_front() {
+            if !session.pace(len(&item)) {
+                self.held = Some(item);
+                break;
+            }
+            self.send(session, item).await?;
+        }
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> FromClient {
+        select! {
+          // CANCEL SAFETY: `Session::recv_rtcp` is cancel safe.
+          received = Session::recv_rtcp(self.rtcp_socket.as_ref(), &mut self.rtcp_buf) => {
+            match received {
+              // Anyone can send datagrams to our socket, so only listen to the client.
+              Ok((len, from)) if from.ip() == self.target.rtcp_remote.ip() => {
+                if !self.rtcp_remote_learned {
+                  self.rtcp_remote_learned = true;
+                  Session::learn_remote("rtcp", &mut self.target.rtcp_remote, from);
+                }
+                FromClient::Rtcp(Bytes::copy_from_slice(&self.rtcp_buf[..len]))
+              },
+              Ok((_, from)) => {
+                tracing::trace!(%from, "ignoring rtcp from unknown address");
+                FromClient::Nothing
+              },
+              Err(err) => {
+                tracing::trace!(%err, "failed to receive rtcp");
+                FromClient::Nothing
+              },
+            }
+          },
+          // CANCEL SAFETY: `UdpSocket::recv_from` is cancel safe.
+          received = self.rtp_socket.recv_from(&mut self.rtp_buf), if !self.rtp_remote_learned => {
+            match received {
+              Ok((_, from)) if from.ip() == self.target.rtp_remote.ip() => {
+                self.rtp_remote_learned = true;
+                Session::learn_remote("rtp", &mut self.target.rtp_remote, from);
+                self.rtp_connect = true;
+                FromClient::Alive
+              },
+              Ok((_, from)) => {
+                tracing::trace!(%from, "ignoring rtp from unknown address");
+                FromClient::Nothing
+              },
+              Err(err) => {
+                tracing::trace!(%err, "failed to receive rtp");
+                FromClient::Nothing
+              },
+            }
+          },
+        }
+    }
+
+    async fn send_bye(&mut self, bye: Vec<u8>) {
+        if let Some(rtcp_socket) = self.rtcp_send_socket() {
+            if let Err(err) = rtcp_socket.send_to(&bye, self.target.rtcp_remote).await {
+                tracing::trace!(%err, "failed to send rtcp bye");
+            }
+        }
+    }
+}
+
+/// Delivery of RTP and RTCP interleaved over the RTSP connection of the
+/// client.
+pub struct SendTcpInterleaved {
+    target: SendInterleaved,
+    rtcp_mode: RtcpMode,
+    drop_disposable_frames_backlog: Option<usize>,
+    memory: MemoryAccount,
+    /// Messages waiting for the pacing, after the message it holds back.
+    queue: InterleavedQueue,
+    /// Message that the pacing holds back, which it was already paid for.
+    held: Option<video::RtpBuf>,
+    /// Number of RTP packets dropped since the client stopped keeping up.
+    throttled: u64,
+}
+
+impl SendTcpInterleaved {
+    pub fn new(target: SendInterleaved, rtcp_mode: RtcpMode, memory: MemoryAccount) -> Self {
+        Self {
+            target,
+            rtcp_mode,
+            drop_disposable_frames_backlog: None,
+            memory,
+            queue: InterleavedQueue::new(),
+            held: None,
+            throttled: 0,
+        }
+    }
+
+    /// See [`SessionSetup::drop_disposable_frames_backlog`](crate::ses-
+    /// sion::setup::SessionSetup::drop_disposable_frames_backlog).
+    pub fn with_drop_disposable_frames_backlog(
+        mut self,
+        drop_disposable_frames_backlog: Option<usize>,
+    ) -> Self {
+        self.drop_disposable_frames_backlog = drop_disposable_frames_backlog;
+        self
+    }
+
+    fn message(&self, channel: u8, payload: Bytes) -> rtsp::ResponseMaybeInterleaved {
+        rtsp::ResponseMaybeInterleaved::Interleaved { channel, payload }
+    }
+
+    /// Send a message to the client. Returns the reason to stop the ses-
+    /// sion if the connection is gone.
+    fn send(&mut self, session: &mut SessionLoop, item: video::RtpBuf) -> Result<(), StopReason> {
+        let (channel, payload, is_rtcp) = match item {
+            video::RtpBuf::Rtp(payload) => (self.target.rtp_channel, payload, false),
+            video::RtpBuf::Rtcp(payload) => (self.target.rtcp_channel, payload, true),
+        };
+        let payload = Bytes::from(payload);
+        let message = self.message(channel, payload.clone());
+        // If the client does not keep up, drop RTP rather than buffer it without bound. RTCP is
+        // small and infrequent, so it is always sent.
+        let sent = if is_rtcp {
+            self.target.sender.send(message).map(|()| true)
+        } else {
+            self.target.sender.send_or_drop(message)
+        };
+        match sent {
+            Ok(true) if is_rtcp => {
+                session.counters.record_rtcp(payload.len());
+            }
+            Ok(true) => {
+                session.record_rtp(&payload);
+                if self.throttled > 0 {
+                    tracing::info!(id = %session.id, dropped = self.throttled, "client caught up");
+                    self.throttled = 0;
+                }
+            }
+            Ok(false) => {
+                session.counters.record_rtp_dropped();
+                if self.throttled == 0 {
+                    tracing::warn!(
+                        id = %session.id, backlog = self.target.sender.backlog(),
+                        "client not keeping up, dropping rtp",
+                    );
+                }
+                self.throttled += 1;
+            }
+            Err(err) => {
+                tracing::trace!(id = %session.id, %err, "underlying connection closed");
+                return Err(StopReason::TargetClosed);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Delivery for SendTcpInterleaved {
+    async fn deliver(
+        &mut self,
+        session: &mut SessionLoop,
+        packet: Vec<video::RtpBuf>,
+    ) -> Result<(), StopReason> {
+        let sender = &self.target.sender;
+
+        // Messages waiting to be written to the client are what a session buffers the most of.
+        self.memory.set(sender.backlog_bytes());
+
+        // Under congestion, or when sessions buffer too much memory in total, drop frames that no
+        // other frame depends on first. A source packet holds a single frame, so either all of its
+        // RTP packets are dropped, or none. RTCP is always sent.
+        let congested = self
+            .drop_disposable_frames_backlog
+            .is_some_and(|max_backlog| sender.backlog() > max_backlog);
+        let drop_rtp = (congested || self.memory.is_budget_exhausted())
+            && packet
+                .iter()
+                .any(|item| matches!(item, video::RtpBuf::Rtp(_)))
+            && packet.iter().all(|item| match item {
+                video::RtpBuf::Rtp(buf) => rtp::is_h264_disposable(buf),
+                video::RtpBuf::Rtcp(_) => true,
+            });
+        if drop_rtp {
+            tracing::trace!(
+                id = %session.id, backlog = sender.backlog(), congested,
+                "dropping disposable frame",
+            );
+        }
+
+        // Order RTP and RTCP by their intended send time so that sender reports never reach the
+        // client before the RTP packets they describe.
+        for item in packet {
+            match item {
+                video::RtpBuf::Rtp(_) if drop_rtp => {}
+                video::RtpBuf::Rtp(_) if self.queue.len() >= pacing::MAX_HELD_BACK => {
+                    session.counters.record_rtp_dropped();
+                }
+                video::RtpBuf::Rtcp(_) if !self.rtcp_mode.sends() => {}
+                item => self.queue.push(item),
+            }
+        }
+        if session.is_paced() {
+            return Ok(());
+        }
+        self.flush(session).await
+    }
+
+    async fn flush(&mut self, session: &mut SessionLoop) -> Result<(), StopReason> {
+        if let Some(item) = self.held.take() {
+            self.send(session, item)?;
+        }
+        while let Some(item) = self.queue.pop() {
+            if !session.pace(len(&item)) {
+                self.held = Some(item);
+                break;
+            }
+            self.send(session, item)?;
+        }
+        session
+            .counters
+            .set_queue_depth(self.target.sender.backlog());
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> FromClient {
+        match self.target.rtcp_rx.recv().await {
+            Some(rtcp) if self.rtcp_mode.receives() => FromClient::Rtcp(rtcp),
+            Some(_) => FromClient::Nothing,
+            None => future::pending().await,
+        }
+    }
+
+    async fn send_bye(&mut self, bye: Vec<u8>) {
+        if self.rtcp_mode.sends() {
+            let message = self.message(self.target.rtcp_channel, bye.into());
+            if let Err(err) = self.target.sender.send(message) {
+                tracing::trace!(%err, "failed to send rtcp bye");
+            }
+        }
+    }
+}
+
+/// Delivery of RTP and RTCP to a sink.
+pub struct SendSink(SendToSink);
+
+impl SendSink {
+    pub fn new(target: SendToSink) -> Self {
+        Self(target)
+    }
+}
+
+impl Delivery for SendSink {
+    async fn deliver(
+        &mut self,
+        session: &mut SessionLoop,
+        packet: Vec<video::RtpBuf>,
+    ) -> Result<(), StopReason> {
+        if let Err(err) = self.0.write(&packet).await {
+            tracing::trace!(id = %session.id, %err, "sink closed");
+            session.fail(&err);
+            return Err(StopReason::TargetClosed);
+        }
+        for item in &packet {
+            session.record_sent(item);
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) {
+        let _ = self.0.shutdown().await;
+    }
+}
+
+/// Size of a packet, which is what the pacing takes from the bucket.
+fn len(item: &video::RtpBuf) -> usize {
+    match item {
+        video::RtpBuf::Rtp(buf) | video::RtpBuf::Rtcp(buf) => buf.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use tokio::net::UdpSocket;
+    use tokio::time::Instant;
+
+    use oddity_rtsp_protocol as rtsp;
+    use video_rs as video;
+
+    use crate::net::connection::{response_channel, InterleavedReceivers, ResponseSenderRx};
+    use crate::session::memory::MemoryBudget;
+    use crate::session::pacing::{Pacing, TokenBucket};
+    use crate::session::setup::{RtcpMode, SendInterleaved, SendOverSocket};
+    use crate::session::tests::playing_session_loop;
+    use crate::session::SessionLoop;
+
+    use super::{Delivery, SendTcpInterleaved, SendUdp};
+
+    const LOCAL: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn rtp(seq: u16, len: usize) -> video::RtpBuf {
+        rtp_at(seq, 0, len)
+    }
+
+    fn rtp_at(seq: u16, timestamp: u32, len: usize) -> video::RtpBuf {
+        let mut packet = vec![0x80, 96];
+        packet.extend(seq.to_be_bytes());
+        packet.extend(timestamp.to_be_bytes());
+        packet.resize(len, 0);
+        video::RtpBuf::Rtp(packet)
+    }
+
+    /// RTP packet carrying a single H.264 NAL unit with the given header.
+    fn h264(seq: u16, nal_header: u8) -> video::RtpBuf {
+        let mut packet = vec![0x80, 96];
+        packet.extend(seq.to_be_bytes());
+        packet.extend([0; 8]);
+        packet.extend([nal_header, 0x88, 0x84]);
+        video::RtpBuf::Rtp(packet)
+    }
+
+    fn sender_report(timestamp: u32) -> video::RtpBuf {
+        let mut packet = vec![0x80, 200, 0x00, 0x06, 0x00, 0x00, 0x00, 0x01];
+        packet.extend([0; 8]);
+        packet.extend(timestamp.to_be_bytes());
+        packet.extend([0; 8]);
+        video::RtpBuf::Rtcp(packet)
+    }
+
+    /// Pace at 10000 bytes per second, so that every packet of 100 bytes
+    /// after the first waits 10ms.
+    fn paced_session_loop() -> SessionLoop {
+        let mut session = playing_session_loop();
+        session.pacing = Some(TokenBucket::new(
+            Pacing::new(80_000).with_burst(100),
+            Instant::now(),
+        ));
+        session
+    }
+
+    /// Send everything that the pacing holds back.
+    async fn flush_paced(delivery: &mut impl Delivery, session: &mut SessionLoop) {
+        while session.is_paced() {
+            SessionLoop::paced(session.paced_until).await;
+            session.paced_until = None;
+            delivery.flush(session).await.unwrap();
+        }
+    }
+
+    fn interleaved() -> (SendTcpInterleaved, ResponseSenderRx) {
+        let (sender, receiver) = response_channel();
+        let target = SendInterleaved {
+            sender,
+            rtp_channel: 0,
+            rtcp_channel: 1,
+            rtcp_rx: InterleavedReceivers::default().claim(0, 1).unwrap(),
+        };
+        let delivery =
+            SendTcpInterleaved::new(target, RtcpMode::Enabled, MemoryBudget::default().account());
+        (delivery, receiver)
+    }
+
+    /// Channel and RTP sequence number (or RTP timestamp of sender reports)
+    /// of the next message written to the client.
+    async fn received(receiver: &mut ResponseSenderRx) -> (u8, u32) {
+        match receiver.recv().await {
+            Some(rtsp::ResponseMaybeInterleaved::Interleaved { channel, payload }) => {
+                let value = match channel {
+                    0 => u16::from_be_bytes([payload[2], payload[3]]) as u32,
+                    _ => u32::from_be_bytes([payload[16], payload[17], payload[18], payload[19]]),
+                };
+                (channel, value)
+            }
+            _ => panic!("no interleaved message"),
+        }
+    }
+
+    #[tokio::test]
+    async fn pacing_holds_packets_back_without_waiting() {
+        let client = UdpSocket::bind((LOCAL, 0)).await.unwrap();
+        let client_addr = client.local_addr().unwrap();
+        let target = SendOverSocket {
+            rtp_remote: client_addr,
+            rtcp_remote: client_addr,
+        };
+        let socket = UdpSocket::bind((LOCAL, 0)).await.unwrap();
+        let mut delivery = SendUdp::new(target, (socket, None), RtcpMode::Enabled, 3);
+        let mut session = paced_session_loop();
+
+        delivery
+            .deliver(&mut session, vec![rtp(1, 100), rtp(2, 100), rtp(3, 100)])
+            .await
+            .unwrap();
+        // The first packet goes out right away, and the session is free to
+        // do other things while the rest waits.
+        assert!(session.is_paced());
+        let mut buf = [0; 200];
+        client.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[2..4], &1_u16.to_be_bytes());
+        assert!(client.try_recv(&mut buf).is_err());
+
+        let mut seqs = Vec::new();
+        while session.is_paced() {
+            SessionLoop::paced(session.paced_until).await;
+            session.paced_until = None;
+            delivery.flush(&mut session).await.unwrap();
+            client.recv(&mut buf).await.unwrap();
+            seqs.push(u16::from_be_bytes([buf[2], buf[3]]));
+        }
+        assert_eq!(seqs, vec![2, 3]);
+        assert_eq!(session.counters.snapshot().rtp_packets, 3);
+    }
+
+    #[tokio::test]
+    async fn sender_report_overtakes_paced_rtp() {
+        let (mut delivery, mut receiver) = interleaved();
+        let mut session = paced_session_loop();
+        delivery
+            .deliver(
+                &mut session,
+                vec![
+                    rtp_at(1, 3000, 100),
+                    rtp_at(2, 6000, 100),
+                    rtp_at(3, 9000, 100),
+                ],
+            )
+            .await
+            .unwrap();
+        assert!(session.is_paced());
+        // The report describes a time between the RTP packet that is held
+        // back and the one queued after it.
+        delivery
+            .deliver(&mut session, vec![sender_report(7500)])
+            .await
+            .unwrap();
+        flush_paced(&mut delivery, &mut session).await;
+
+        let mut order = Vec::new();
+        for _ in 0..4 {
+            order.push(received(&mut receiver).await);
+        }
+        assert_eq!(order, vec![(0, 1), (0, 2), (1, 7500), (0, 3)]);
+    }
+
+    #[tokio::test]
+    async fn backlog_drops_only_disposable_frames() {
+        let (delivery, mut receiver) = interleaved();
+        let mut delivery = delivery.with_drop_disposable_frames_backlog(Some(2));
+        let mut session = playing_session_loop();
+
+        // The client reads nothing for now, so that the backlog of its con-
+        // nection grows with every message.
+        for seq in 1..=3 {
+            delivery
+                .deliver(&mut session, vec![h264(seq, 0x41)])
+                .await
+                .unwrap();
+        }
+        // Over the backlog, non-reference slices are dropped, while refer-
+        // ence slices and IDR pictures still go through.
+        for (seq, nal_header) in [(4, 0x01), (5, 0x41), (6, 0x65), (7, 0x01)] {
+            delivery
+                .deliver(&mut session, vec![h264(seq, nal_header)])
+                .await
+                .unwrap();
+        }
+        let mut seqs = Vec::new();
+        for _ in 0..5 {
+            seqs.push(received(&mut receiver).await.1);
+        }
+        assert_eq!(seqs, vec![1, 2, 3, 5, 6]);
+
+        // Once the client caught up, nothing is dropped.
+        delivery
+            .deliver(&mut session, vec![h264(8, 0x01)])
+            .await
+            .unwrap();
+        assert_eq!(received(&mut receiver).await.1, 8);
+    }
+}
@@ -0,0 +1,206 @@
+//! Ordering of outgoing interleaved RTP and RTCP messages.
+//!
+//! When RTP and RTCP share a single TCP connection, a sender report must
+//! not reach the client before the RTP packets it describes, or the
+//! client will try to synchronize against media it has not received yet.
+//! The queue below orders outgoing messages by their intended send time,
+//! which is derived from the RTP timestamp of the packet (or the RTP
+//! timestamp a sender report refers to).
+//!
+//! Messages stay queued for as long as the pacing of the session holds
+//! them back, so that a sender report that is delivered later is still
+//! ordered against the RTP that has not been sent yet. Without pacing,
+//! the queue is emptied on every delivery, and only orders the output of
+//! the muxer for a single packet, which puts a sender report before the
+//! RTP packet it describes.
+
+use std::collections::BTreeMap;
+
+use video_rs as video;
+
+use crate::session::{rtcp, rtp};
+
+/// Time-ordered queue of outgoing RTP and RTCP buffers.
+///
+/// Buffers are keyed by their RTP timestamp relative to the first RTP
+/// packet pushed since the queue was last empty (using wrapping arith-
+/// metic to cope with timestamp wraparound). The send time of RTP packets never goes
+/// backwards, so RTP is always sent in the order the muxer produced it,
+/// even with B-frames. A sender report is sent after every RTP packet
+/// with the same or an earlier send time, and before the rest.
+#[derive(Default)]
+pub struct InterleavedQueue {
+    bufs: BTreeMap<(i64, Kind, u64), video::RtpBuf>,
+    pushed: u64,
+    base: Option<u32>,
+    last: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Rtp,
+    Rtcp,
+}
+
+impl InterleavedQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, buf: video::RtpBuf) {
+        let (kind, timestamp) = match &buf {
+            video::RtpBuf::Rtp(payload) => (Kind::Rtp, rtp::timestamp(payload)),
+            video::RtpBuf::Rtcp(payload) => (
+                Kind::Rtcp,
+                rtcp::packets(payload).find_map(|packet| packet.sender_report_rtp_timestamp()),
+            ),
+        };
+
+        if kind == Kind::Rtp && self.base.is_none() {
+            self.base = timestamp;
+        }
+
+        // Buffers without a usable timestamp are sent right after the most
+        // recent RTP packet.
+        let time = match (self.base, timestamp) {
+            (Some(base), Some(timestamp)) => timestamp.wrapping_sub(base) as i32 as i64,
+            _ => self.last,
+        };
+        let time = match kind {
+            Kind::Rtp => {
+                self.last = self.last.max(time);
+                self.last
+            }
+            Kind::Rtcp => time,
+        };
+
+        // The number of buffers pushed before doubles as tie-breaker so
+        // buffers with equal send times retain the order in which they were
+        // pushed.
+        self.bufs.insert((time, kind, self.pushed), buf);
+        self.pushed += 1;
+    }
+
+    /// Remove the buffer that should be sent next.
+    pub fn pop(&mut self) -> Option<video::RtpBuf> {
+        let (_, buf) = self.bufs.pop_first()?;
+        if self.bufs.is_empty() {
+            self.base = None;
+            self.last = 0;
+        }
+        Some(buf)
+    }
+
+    /// Number of buffers in the queue.
+    pub fn len(&self) -> usize {
+        self.bufs.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::iter;
+
+    use video_rs as video;
+
+    use super::InterleavedQueue;
+
+    fn rtp(timestamp: u32, marker: u8) -> video::RtpBuf {
+        let ts = timestamp.to_be_bytes();
+        video::RtpBuf::Rtp(vec![
+            0x80, 96, 0x00, marker, ts[0], ts[1], ts[2], ts[3], 0x00, 0x00, 0x00, 0x01,
+        ])
+    }
+
+    fn sender_report(timestamp: u32) -> video::RtpBuf {
+        let ts = timestamp.to_be_bytes();
+        video::RtpBuf::Rtcp(vec![
+            0x80, 200, 0x00, 0x06, 0x00, 0x00, 0x00, 0x01, // header and ssrc
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // ntp timestamp
+            ts[0], ts[1], ts[2], ts[3], // rtp timestamp
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // packet and octet count
+        ])
+    }
+
+    fn order(queue: &mut InterleavedQueue) -> Vec<(bool, u32, u8)> {
+        iter::from_fn(|| order_of_next(queue)).collect()
+    }
+
+    fn order_of_next(queue: &mut InterleavedQueue) -> Option<(bool, u32, u8)> {
+        queue.pop().map(|buf| match buf {
+            video::RtpBuf::Rtp(payload) => (
+                true,
+                u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]),
+                payload[3],
+            ),
+            video::RtpBuf::Rtcp(payload) => (
+                false,
+                u32::from_be_bytes([payload[16], payload[17], payload[18], payload[19]]),
+                0,
+            ),
+        })
+    }
+
+    #[test]
+    fn sender_report_after_rtp_it_describes() {
+        let mut queue = InterleavedQueue::new();
+        queue.push(sender_report(3000));
+        queue.push(rtp(3000, 1));
+        queue.push(rtp(3000, 2));
+        assert_eq!(
+            order(&mut queue),
+            vec![(true, 3000, 1), (true, 3000, 2), (false, 3000, 0)],
+        );
+    }
+
+    #[test]
+    fn sender_report_before_later_rtp() {
+        let mut queue = InterleavedQueue::new();
+        queue.push(rtp(3000, 1));
+        queue.push(rtp(6000, 2));
+        queue.push(sender_report(3000));
+        assert_eq!(
+            order(&mut queue),
+            vec![(true, 3000, 1), (false, 3000, 0), (true, 6000, 2)],
+        );
+    }
+
+    #[test]
+    fn sender_report_for_earlier_rtp_goes_first() {
+        let mut queue = InterleavedQueue::new();
+        queue.push(rtp(6000, 1));
+        queue.push(sender_report(3000));
+        assert_eq!(order(&mut queue), vec![(false, 3000, 0), (true, 6000, 1)]);
+    }
+
+    #[test]
+    fn sender_report_overtakes_rtp_still_queued() {
+        let mut queue = InterleavedQueue::new();
+        queue.push(rtp(3000, 1));
+        queue.push(rtp(6000, 2));
+        queue.push(rtp(9000, 3));
+        assert_eq!(order_of_next(&mut queue), Some((true, 3000, 1)));
+        // The report is pushed after the RTP it precedes in time.
+        queue.push(sender_report(4500));
+        assert_eq!(
+            order(&mut queue),
+            vec![(false, 4500, 0), (true, 6000, 2), (true, 9000, 3)],
+        );
+    }
+
+    #[test]
+    fn rtp_order_is_retained() {
+        let mut queue = InterleavedQueue::new();
+        queue.push(rtp(9000, 1));
+        queue.push(rtp(3000, 2));
+        queue.push(rtp(6000, 3));
+        assert_eq!(
+            order(&mut queue)
+                .into_iter()
+                .map(|(_, _, marker)| marker)
+                .collect::<Vec<_>>(),
+            vec![1, 2, 3],
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! Synthetic keyframe for clients that start playing in the middle of a
+//! long GOP, see [`SessionSetup::synthetic_keyframe_on_join`](crate::ses-
+//! sion::setup::SessionSetup::synthetic_keyframe_on_join).
+//!
+//! The keyframe is sent right before the first RTP packet the client gets.
+//! It is stamped one frame interval before that packet, so that the client
+//! does not see two pictures at the same instant, and the RTP-Info of the
+//! client starts at the keyframe.
+
+use video_rs as video;
+
+use crate::media;
+use crate::media::video::h264;
+use crate::session::rtp;
+
+/// Time between two frames on the 90 kHz clock of H.264 (30 frames per
+/// second), until the session saw two frames of the stream.
+pub const DEFAULT_FRAME_INTERVAL: u32 = 3000;
+
+/// Longest time between two frames that is taken for the frame interval
+/// (one second on the 90 kHz clock). Longer gaps are a stall of the
+/// source, or a jump in its timestamps.
+const MAX_FRAME_INTERVAL: u32 = 90_000;
+
+pub struct SyntheticKeyframe {
+    max_payload_len: usize,
+    /// SPS and PPS of the stream in the muxer, if it is H.264.
+    parameter_sets: Option<(Vec<u8>, Vec<u8>)>,
+    /// Payload type of the RTP stream, once the muxer produced any RTP.
+    payload_type: Option<u8>,
+    last_timestamp: Option<u32>,
+    frame_interval: u32,
+}
+
+impl SyntheticKeyframe {
+    /// Generate keyframes with RTP payloads of up to `max_payload_len`
+    /// bytes.
+    pub fn new(max_payload_len: usize) -> Self {
+        Self {
+            max_payload_len,
+            parameter_sets: None,
+            payload_type: None,
+            last_timestamp: None,
+            frame_interval: DEFAULT_FRAME_INTERVAL,
+        }
+    }
+
+    /// Take the parameter sets of the stream in the muxer. Called again
+    /// whenever the muxer is reinitialized.
+    pub fn update_parameter_sets(&mut self, muxer: &video::RtpMuxer) {
+        let parameter_sets = muxer
+            .parameter_sets_h264()
+            .into_iter()
+            .filter_map(Result::ok)
+            .next()
+            .and_then(|(sps, pps)| Some((sps.to_vec(), pps.first()?.to_vec())));
+        self.parameter_sets = parameter_sets;
+    }
+
+    #[cfg(test)]
+    pub fn with_parameter_sets(mut self, sps: &[u8], pps: &[u8]) -> Self {
+        self.parameter_sets = Some((sps.to_vec(), pps.to_vec()));
+        self
+    }
+
+    /// Keep track of the payload type and the frame interval of the RTP
+    /// stream from the muxed packets.
+    pub fn observe(&mut self, packet: &[video::RtpBuf]) {
+        for buf in packet.iter().filter_map(|buf| match buf {
+            video::RtpBuf::Rtp(buf) => Some(buf),
+            video::RtpBuf::Rtcp(_) => None,
+        }) {
+            if let Some(payload_type) = rtp::payload_type(buf) {
+                self.payload_type = Some(payload_type);
+            }
+            if let Some(timestamp) = rtp::timestamp(buf) {
+                if let Some(last_timestamp) = self.last_timestamp {
+                    let interval = timestamp.wrapping_sub(last_timestamp);
+                    if interval > 0 && interval <= MAX_FRAME_INTERVAL {
+                        self.frame_interval = interval;
+                    }
+                }
+                self.last_timestamp = Some(timestamp);
+            }
+        }
+    }
+
+    /// Generate the RTP packets of a keyframe that directly precedes the
+    /// packet with stream state `stream_state`, which is moved back to the
+    /// first packet of the keyframe.
+    pub fn generate(
+        &self,
+        ssrc: u32,
+        stream_state: &mut media::StreamState,
+    ) -> Result<Vec<Vec<u8>>, h264::SyntheticKeyframeError> {
+        let (sps, pps) = self
+            .parameter_sets
+            .as_ref()
+            .ok_or(h264::SyntheticKeyframeError::CodecNotSupported)?;
+        let payload_type = self
+            .payload_type
+            .ok_or(h264::SyntheticKeyframeError::CodecNotSupported)?;
+        let nal = h264::gray_idr(sps, pps)?;
+
+        let timestamp = stream_state.rtp_timestamp.wrapping_sub(self.frame_interval);
+        let mut packets =
+            rtp::packetize_h264(&nal, payload_type, 0, timestamp, ssrc, self.max_payload_len);
+        let first_seq = stream_state.rtp_seq.wrapping_sub(packets.len() as u16);
+        for (i, packet) in packets.iter_mut().enumerate() {
+            rtp::set_seq(packet, first_seq.wrapping_add(i as u16));
+        }
+        stream_state.rtp_seq = first_seq;
+        stream_state.rtp_timestamp = timestamp;
+        Ok(packets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use video_rs as video;
+
+    use crate::media;
+    use crate::media::video::h264::tests::{PPS, SPS};
+    use crate::session::rtp;
+
+    use super::{SyntheticKeyframe, DEFAULT_FRAME_INTERVAL};
+
+    fn rtp_at(timestamp: u32) -> video::RtpBuf {
+        let mut packet = vec![0x80, 96, 0, 0];
+        packet.extend(timestamp.to_be_bytes());
+        packet.extend([0, 0, 0, 1, 0x41]);
+        video::RtpBuf::Rtp(packet)
+    }
+
+    #[test]
+    fn generate_precedes_stream_state_by_frame_interval() {
+        let mut keyframe = SyntheticKeyframe::new(1400).with_parameter_sets(&SPS, &PPS);
+        keyframe.observe(&[rtp_at(0), rtp_at(3600), rtp_at(3600)]);
+        let mut stream_state = media::StreamState {
+            rtp_seq: 0,
+            rtp_timestamp: 7200,
+        };
+        let packets = keyframe.generate(1, &mut stream_state).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(rtp::seq(&packets[0]), Some(0xffff));
+        assert_eq!(rtp::timestamp(&packets[0]), Some(3600));
+        assert_eq!(rtp::payload_type(&packets[0]), Some(96));
+        assert_eq!(stream_state.rtp_seq, 0xffff);
+        assert_eq!(stream_state.rtp_timestamp, 3600);
+    }
+
+    #[test]
+    fn frame_interval_ignores_jumps() {
+        let mut keyframe = SyntheticKeyframe::new(1400).with_parameter_sets(&SPS, &PPS);
+        keyframe.observe(&[rtp_at(1_000_000), rtp_at(0)]);
+        let mut stream_state = media::StreamState {
+            rtp_seq: 10,
+            rtp_timestamp: 0,
+        };
+        keyframe.generate(1, &mut stream_state).unwrap();
+        assert_eq!(
+            stream_state.rtp_timestamp,
+            0u32.wrapping_sub(DEFAULT_FRAME_INTERVAL),
+        );
+    }
+
+    #[test]
+    fn generate_needs_parameter_sets() {
+        let mut keyframe = SyntheticKeyframe::new(1400);
+        keyframe.observe(&[rtp_at(0)]);
+        let mut stream_state = media::StreamState {
+            rtp_seq: 10,
+            rtp_timestamp: 0,
+        };
+        assert!(keyframe.generate(1, &mut stream_state).is_err());
+        assert_eq!(stream_state.rtp_seq, 10);
+    }
+}
@@ -0,0 +1,117 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Coarse accounting of the memory that sessions hold in buffers, such
+/// as messages queued for a slow client. The accounting is not precise:
+/// it only counts payload bytes, and it lags behind, since sessions
+/// report their usage when they produce more data. It is meant as a
+/// safety valve against many sessions slowly piling up buffers, not as
+/// an allocator.
+#[derive(Clone, Default)]
+pub struct MemoryBudget {
+    limit: Option<usize>,
+    total: Arc<AtomicUsize>,
+}
+
+impl MemoryBudget {
+    /// Create budget with an optional limit on the total number of buff-
+    /// ered bytes. The budget is unlimited if `limit` is `None`.
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            total: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Total number of bytes currently buffered by all sessions.
+    pub fn total(&self) -> usize {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// Whether the buffered bytes have reached the limit.
+    pub fn is_exhausted(&self) -> bool {
+        self.limit.is_some_and(|limit| self.total() >= limit)
+    }
+
+    /// Open an account for a single session.
+    pub fn account(&self) -> MemoryAccount {
+        MemoryAccount {
+            bytes: 0,
+            budget: self.clone(),
+        }
+    }
+}
+
+/// Account of the bytes buffered by a single session. The bytes are
+/// released from the budget when the account is dropped.
+pub struct MemoryAccount {
+    bytes: usize,
+    budget: MemoryBudget,
+}
+
+impl MemoryAccount {
+    /// Report the number of bytes the session currently holds in buff-
+    /// ers.
+    pub fn set(&mut self, bytes: usize) {
+        if bytes > self.bytes {
+            self.budget
+                .total
+                .fetch_add(bytes - self.bytes, Ordering::Relaxed);
+        } else {
+            self.budget
+                .total
+                .fetch_sub(self.bytes - bytes, Ordering::Relaxed);
+        }
+        self.bytes = bytes;
+    }
+
+    /// Whether the budget this account belongs to is exhausted, in which
+    /// case the session should trim its buffers where it can.
+    pub fn is_budget_exhausted(&self) -> bool {
+        self.budget.is_exhausted()
+    }
+}
+
+impl Drop for MemoryAccount {
+    fn drop(&mut self) {
+        self.set(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::MemoryBudget;
+
+    #[test]
+    fn accounts_add_up() {
+        let budget = MemoryBudget::new(Some(1000));
+        let mut first = budget.account();
+        let mut second = budget.account();
+        first.set(300);
+        second.set(500);
+        assert_eq!(budget.total(), 800);
+        first.set(100);
+        assert_eq!(budget.total(), 600);
+        assert!(!budget.is_exhausted());
+    }
+
+    #[test]
+    fn dropped_account_is_released() {
+        let budget = MemoryBudget::new(None);
+        let mut account = budget.account();
+        account.set(1234);
+        drop(account);
+        assert_eq!(budget.total(), 0);
+    }
+
+    #[test]
+    fn exhausted_at_limit() {
+        let budget = MemoryBudget::new(Some(1000));
+        let mut account = budget.account();
+        account.set(1000);
+        assert!(budget.is_exhausted());
+        assert!(account.is_budget_exhausted());
+        assert!(!MemoryBudget::new(None).is_exhausted());
+    }
+}
@@ -1,17 +1,47 @@
+mod cancellation;
+mod delivery;
+mod interleaved;
+mod keyframe;
+mod mux_errors;
+mod repacketize;
+mod rtcp;
+mod rtp;
 mod transport;
 
+pub mod admission;
+pub mod client_limit;
+pub mod memory;
+pub mod observer;
+pub mod pacing;
+pub mod sender_report;
 pub mod session_manager;
 pub mod setup;
+pub mod stats;
+pub mod udp;
 
 use std::error;
 use std::fmt;
+use std::future;
+use std::io;
+use std::iter;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
+use tokio::fs;
+use tokio::net::UdpSocket;
 use tokio::select;
 use tokio::sync::broadcast;
 use tokio::sync::mpsc;
+use tokio::sync::watch;
+use tokio::task;
+use tokio::time::{self, Instant};
 
 use rand::Rng;
 
+use tracing::Instrument;
+
 use oddity_rtsp_protocol as rtsp;
 use video_rs as video;
 
@@ -19,11 +49,30 @@ use crate::media;
 use crate::media::video::rtp_muxer;
 use crate::runtime::task_manager::{Task, TaskContext};
 use crate::runtime::Runtime;
-use crate::session::setup::{SessionSetup, SessionSetupTarget};
-use crate::source::SourceDelegate;
+use crate::session::cancellation::Cancellation;
+use crate::session::delivery::{
+    Delivery, FromClient, SendSink, SendTcpInterleaved, SendUdp, Target,
+};
+use crate::session::keyframe::SyntheticKeyframe;
+use crate::session::memory::MemoryAccount;
+use crate::session::mux_errors::MuxErrors;
+use crate::session::pacing::TokenBucket;
+use crate::session::repacketize::Repacketizer;
+use crate::session::sender_report::SenderReports;
+use crate::session::setup::{
+    BoundTarget, ClientIdentity, SendToSink, SessionSetup, SessionSetupTarget,
+};
+use crate::session::stats::SessionCounters;
+use crate::session::udp::BindError;
+use crate::source::{SourceDelegate, SourcePath};
 
 pub enum SessionState {
-    Stopped(SessionId),
+    Started(SessionId),
+    Playing(SessionId),
+    Paused(SessionId),
+    /// Session failed with the given error. It is followed by `Stopped`.
+    Error(SessionId, String),
+    Stopped(SessionId, StopReason),
 }
 
 pub type SessionStateTx = mpsc::UnboundedSender<SessionState>;
@@ -31,18 +80,96 @@ pub type SessionStateRx = mpsc::UnboundedReceiver<SessionState>;
 
 pub type SessionStreamStateTx = broadcast::Sender<media::StreamState>;
 
+pub type SessionSsrcTx = watch::Sender<Option<u32>>;
+pub type SessionSsrcRx = watch::Receiver<Option<u32>>;
+
 pub enum SessionControlMessage {
+    /// Start playing, and report the stream state of the first RTP packet
+    /// that is sent from then on.
     Play,
-    StreamState,
+    Pause,
+    KeepAlive,
 }
 
 pub type SessionControlTx = mpsc::UnboundedSender<SessionControlMessage>;
 pub type SessionControlRx = mpsc::UnboundedReceiver<SessionControlMessage>;
 
+/// Handle to reset the timeout of a session. Keeping a session alive only
+/// queues a message for its worker, so it is cheap and never waits for
+/// the session.
+#[derive(Clone)]
+pub struct SessionKeepAlive(SessionControlTx);
+
+impl SessionKeepAlive {
+    /// Tell the session that the client is still there, which resets
+    /// its timeout.
+    pub fn keep_alive(&self) -> Result<(), SessionControlError> {
+        self.0
+            .send(SessionControlMessage::KeepAlive)
+            .map_err(|_| SessionControlError::ControlBroken)
+    }
+}
+
+/// Snapshot of session metadata captured at setup time.
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub id: SessionId,
+    pub source_path: SourcePath,
+    /// SDP that the muxer of the session was configured with. Useful to
+    /// debug interop issues with clients.
+    pub sdp: Option<String>,
+    pub client_identity: Option<ClientIdentity>,
+    pub transport: SessionTransport,
+    pub created: SystemTime,
+}
+
+/// How a session delivers its stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionTransport {
+    Udp {
+        rtp_remote: SocketAddr,
+        rtcp_remote: SocketAddr,
+    },
+    /// Over UDP to a multicast group, with RTP sent to the port of the
+    /// group and RTCP to the port that follows it.
+    UdpMulticast {
+        group: SocketAddr,
+    },
+    /// Interleaved over the RTSP connection of the client, on the given
+    /// channels.
+    TcpInterleaved {
+        rtp_channel: u8,
+        rtcp_channel: u8,
+    },
+    Sink,
+}
+
+impl From<&SessionSetupTarget> for SessionTransport {
+    fn from(target: &SessionSetupTarget) -> Self {
+        match target {
+            SessionSetupTarget::RtpUdp(target) => SessionTransport::Udp {
+                rtp_remote: target.rtp_remote,
+                rtcp_remote: target.rtcp_remote,
+            },
+            SessionSetupTarget::RtpUdpMulticast(group) => {
+                SessionTransport::UdpMulticast { group: group.group }
+            }
+            SessionSetupTarget::RtpTcp(target) => SessionTransport::TcpInterleaved {
+                rtp_channel: target.rtp_channel,
+                rtcp_channel: target.rtcp_channel,
+            },
+            SessionSetupTarget::Sink(_) => SessionTransport::Sink,
+        }
+    }
+}
+
 pub struct Session {
     worker: Task,
+    cancellation: Cancellation,
     control_tx: SessionControlTx,
     stream_state_tx: SessionStreamStateTx,
+    ssrc_rx: SessionSsrcRx,
+    counters: Arc<SessionCounters>,
 }
 
 impl Session {
@@ -50,42 +177,199 @@ impl Session {
     /// something is really wrong and the server is overloaded.
     const MAX_QUEUED_INFO: usize = 16;
 
+    /// Size of the buffer for receiving RTCP over UDP. Larger compound
+    /// packets are truncated, which at worst means that we miss a BYE.
+    const MAX_RTCP_LEN: usize = 2048;
+
+    /// Set up the session and start its worker. Fails if the sockets of a
+    /// session over UDP cannot be bound, in which case no worker is start-
+    /// ed.
     pub async fn setup_and_start(
         id: SessionId,
         source_delegate: SourceDelegate,
         setup: SessionSetup,
+        memory: MemoryAccount,
         state_tx: SessionStateTx,
         runtime: &Runtime,
-    ) -> Self {
+    ) -> Result<Self, BindError> {
+        let transport = SessionTransport::from(&setup.rtp_target);
+        let SessionSetup {
+            rtp_muxer,
+            rtp_target,
+            mut teardown_on_bye,
+            synthetic_keyframe_on_join,
+            sdp,
+            drop_disposable_frames_backlog,
+            mut record_dir,
+            max_rtp_payload,
+            udp_sockets,
+            rtcp_mode,
+            timeout,
+            sender_report_interval,
+            symmetric_rtp,
+            max_send_errors,
+            dscp,
+            max_mux_errors,
+            pacing,
+            mtu,
+            udp_bind,
+            ..
+        } = setup;
+        // Bind before the worker starts, so that the client is told that the setup failed rather
+        // than getting a session that never sends anything.
+        let target = setup::bind_target(rtp_target, udp_sockets, &udp_bind, rtcp_mode).await?;
+
         let (control_tx, control_rx) = mpsc::unbounded_channel();
         let (stream_state_tx, _) = broadcast::channel(Self::MAX_QUEUED_INFO);
+        let (ssrc_tx, ssrc_rx) = watch::channel(None);
+        let counters = Arc::new(SessionCounters::default());
+        let cancellation = Cancellation::new();
 
         tracing::trace!(%id, "starting session");
+        // Everything the worker logs carries the session ID and transport, so
+        // that the logs of one session can be told apart from the rest.
+        let span = tracing::info_span!(
+            "session",
+            %id,
+            transport = ?transport,
+        );
+
+        let mut timeout = Some(timeout);
+        let mut state = SessionMediaState::Ready;
+        let mut sender_reports = sender_report_interval
+            .filter(|_| rtcp_mode.sends())
+            .and(sdp.as_deref())
+            .and_then(SenderReports::from_sdp);
+        let repacketizer = match (mtu, sdp.as_deref()) {
+            (Some(mtu), Some(sdp)) if sdp.contains("H264/") => Some(Repacketizer::new(mtu)),
+            (Some(mtu), _) => {
+                tracing::warn!(%id, mtu, "can only fit h264 to mtu, sending packets as is");
+                None
+            }
+            (None, _) => None,
+        };
+        // The synthetic keyframe is packetized like the muxer output after the repacketizer, and
+        // a multicast group has members that are well past their first keyframe.
+        let mut synthetic_keyframe = synthetic_keyframe_on_join.then(|| {
+            let max_payload_len = match mtu.filter(|_| repacketizer.is_some()) {
+                Some(mtu) => max_rtp_payload.min(repacketize::max_payload_for_mtu(mtu)),
+                None => max_rtp_payload,
+            };
+            SyntheticKeyframe::new(max_payload_len)
+        });
+
+        let target = match target {
+            BoundTarget::RtpUdp(target, udp_sockets) => Target::Udp(
+                SendUdp::new(target, udp_sockets, rtcp_mode, max_send_errors)
+                    .with_symmetric_rtp(symmetric_rtp)
+                    .with_repacketizer(repacketizer)
+                    .with_dscp(dscp),
+            ),
+            BoundTarget::RtpUdpMulticast(group, udp_sockets) => {
+                // Receivers of the group do not announce ports of their own,
+                // and one of them leaving does not end the stream for the rest.
+                teardown_on_bye = false;
+                synthetic_keyframe = None;
+                if let Some(interval) = sender_report_interval
+                    .filter(|&interval| interval < sender_report::RFC_3550_MIN_INTERVAL)
+                {
+                    tracing::warn!(
+                        %id, ?interval,
+                        "sending sender reports to multicast group below rfc 3550 minimum interval",
+                    );
+                }
+                let target = setup::SendOverSocket {
+                    rtp_remote: group.rtp_addr(),
+                    rtcp_remote: group.rtcp_addr(),
+                };
+                Target::Udp(
+                    SendUdp::new(target, udp_sockets, rtcp_mode, max_send_errors)
+                        .with_repacketizer(repacketizer)
+                        .with_dscp(dscp)
+                        .with_multicast(&group),
+                )
+            }
+            BoundTarget::RtpTcp(target) => Target::TcpInterleaved(
+                SendTcpInterleaved::new(target, rtcp_mode, memory)
+                    .with_drop_disposable_frames_backlog(drop_disposable_frames_backlog),
+            ),
+            BoundTarget::Sink(target) => {
+                // Nobody is there to send PLAY, to be kept alive or to receive reports.
+                state = SessionMediaState::Playing;
+                timeout = None;
+                sender_reports = None;
+                synthetic_keyframe = None;
+                record_dir = None;
+                Target::Sink(SendSink::new(target))
+            }
+        };
+        let secondary_sink = match record_dir {
+            Some(record_dir) => {
+                Self::open_recording(&id, record_dir.join(format!("{id}.rtp"))).await
+            }
+            None => None,
+        };
+
+        let session_loop = SessionLoop {
+            id: id.clone(),
+            mux_errors: MuxErrors::new(max_mux_errors),
+            pacing: pacing.map(|pacing| TokenBucket::new(pacing, Instant::now())),
+            teardown_on_bye,
+            synthetic_keyframe,
+            secondary_sink,
+            timeout,
+            sender_reports,
+            sender_report_interval: sender_report_interval
+                .unwrap_or(sender_report::DEFAULT_INTERVAL),
+            control_rx,
+            state_tx,
+            stream_state_tx: stream_state_tx.clone(),
+            ssrc_tx,
+            counters: counters.clone(),
+            cancellation: cancellation.clone(),
+            paced_until: None,
+            state,
+            need_stream_state: false,
+            last_activity: Instant::now(),
+            client_ssrc: None,
+        };
         let worker = runtime
             .task()
-            .spawn({
-                let id = id.clone();
-                let stream_state_tx = stream_state_tx.clone();
-                |task_context| {
-                    Self::run(
-                        id,
-                        source_delegate,
-                        setup,
-                        control_rx,
-                        state_tx,
-                        stream_state_tx,
-                        task_context,
-                    )
-                }
+            .spawn(|task_context| {
+                Self::run(
+                    session_loop,
+                    source_delegate,
+                    rtp_muxer,
+                    target,
+                    task_context,
+                )
+                .instrument(span)
             })
             .await;
         tracing::trace!(%id, "started session");
 
-        Self {
+        Ok(Self {
             worker,
+            cancellation,
             control_tx,
             stream_state_tx,
-        }
+            ssrc_rx,
+            counters,
+        })
+    }
+
+    /// Receiver of the SSRC of the RTP stream of this session, which is
+    /// `None` until the session has sent any RTP. Reading it does not wait
+    /// for the session.
+    pub fn ssrc_rx(&self) -> SessionSsrcRx {
+        self.ssrc_rx.clone()
+    }
+
+    /// Counters of what the session has sent so far, which the worker of
+    /// the session keeps up to date. Reading them does not wait for the
+    /// session.
+    pub fn counters(&self) -> Arc<SessionCounters> {
+        self.counters.clone()
     }
 
     pub async fn play(
@@ -100,115 +384,298 @@ impl Session {
             }
         }
 
+        // The session reports the stream state once it sends the first RTP packet after starting
+        // to play. If nothing was muxed yet, this waits for the source to produce a packet.
         let mut stream_state_rx = self.stream_state_tx.subscribe();
-        tracing::trace!("querying session for stream state");
+        tracing::trace!("sending play signal to session");
         self.control_tx
-            .send(SessionControlMessage::StreamState)
+            .send(SessionControlMessage::Play)
             .map_err(|_| PlaySessionError::ControlBroken)?;
 
         let stream_state = stream_state_rx
             .recv()
             .await
             .map_err(|_| PlaySessionError::ControlBroken)?;
-        tracing::trace!("received stream state");
-
-        tracing::trace!("sending play signal to session");
-        self.control_tx
-            .send(SessionControlMessage::Play)
-            .map_err(|_| PlaySessionError::ControlBroken)?;
         tracing::trace!("session playing");
 
         Ok(stream_state)
     }
 
+    /// Stop sending media to the client, until it plays the session
+    /// again. The session keeps receiving and muxing packets from the
+    /// source while paused, so that it can resume right away and does not
+    /// hold up other sessions of the same source. Pausing a session that
+    /// is not playing has no effect.
+    pub fn pause(&self) -> Result<(), SessionControlError> {
+        self.control_tx
+            .send(SessionControlMessage::Pause)
+            .map_err(|_| SessionControlError::ControlBroken)
+    }
+
+    /// Handle that keeps the session alive without access to the session
+    /// itself.
+    pub fn keep_alive_handle(&self) -> SessionKeepAlive {
+        SessionKeepAlive(self.control_tx.clone())
+    }
+
     pub async fn teardown(&mut self) {
-        tracing::trace!("sending teardown signal to session");
+        self.stop(StopReason::Teardown).await;
+    }
+
+    /// Handle to abort the worker of the session with if it does not stop
+    /// in time.
+    pub fn abort_handle(&self) -> Option<task::AbortHandle> {
+        self.worker.abort_handle()
+    }
+
+    /// Stop the session for the given reason, and wait for it to stop.
+    pub async fn stop(&mut self, reason: StopReason) {
+        tracing::trace!(%reason, "sending stop signal to session");
+        self.cancellation.cancel(reason);
         let _ = self.worker.stop().await;
-        tracing::trace!("session torn down");
+        tracing::trace!("session stopped");
     }
 
     async fn run(
-        id: SessionId,
+        session_loop: SessionLoop,
         source_delegate: SourceDelegate,
-        setup: SessionSetup,
-        control_rx: SessionControlRx,
-        state_tx: SessionStateTx,
-        stream_state_tx: SessionStreamStateTx,
-        task_context: TaskContext,
+        muxer: video::RtpMuxer,
+        target: Target,
+        mut task_context: TaskContext,
     ) {
-        let muxer = setup.rtp_muxer;
+        let id = session_loop.id.clone();
+        let state_tx = session_loop.state_tx.clone();
+        let cancellation = session_loop.cancellation.clone();
+        let _ = state_tx.send(SessionState::Started(id.clone()));
 
-        match setup.rtp_target {
-            SessionSetupTarget::RtpUdp(_) => {
-                tracing::error!(%id, "started session with unsupported transport");
-            }
-            SessionSetupTarget::RtpTcp(target) => {
-                tracing::trace!(%id, "starting rtp over tcp (interleaved) loop");
-                Self::run_tcp_interleaved(
-                    id.clone(),
-                    source_delegate,
-                    muxer,
-                    target,
-                    control_rx,
-                    stream_state_tx,
-                    task_context,
-                )
-                .await;
+        let session_loop = {
+            let id = id.clone();
+            async move {
+                match target {
+                    Target::Udp(target) => {
+                        tracing::trace!(%id, "starting rtp over udp loop");
+                        session_loop.run(source_delegate, muxer, target).await
+                    }
+                    Target::TcpInterleaved(target) => {
+                        tracing::trace!(%id, "starting rtp over tcp (interleaved) loop");
+                        session_loop.run(source_delegate, muxer, target).await
+                    }
+                    Target::Sink(target) => {
+                        tracing::trace!(%id, "starting rtp to sink loop");
+                        session_loop.run(source_delegate, muxer, target).await
+                    }
+                }
             }
         };
+        tokio::pin!(session_loop);
 
-        let _ = state_tx.send(SessionState::Stopped(id));
+        let stop_reason = select! {
+          stop_reason = &mut session_loop => stop_reason,
+          // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
+          _ = task_context.wait_for_stop() => {
+            // Route the stop request through the cancellation, so that the loop can clean up
+            // before stopping.
+            cancellation.cancel(StopReason::Teardown);
+            session_loop.await
+          },
+        };
+        tracing::debug!(%id, %stop_reason, "session stopped");
+
+        let _ = state_tx.send(SessionState::Stopped(id, stop_reason));
     }
 
-    async fn run_tcp_interleaved(
-        id: SessionId,
+    /// Open the file to record the session to. The session goes ahead with-
+    /// out a recording if the file cannot be created.
+    async fn open_recording(id: &SessionId, path: PathBuf) -> Option<SendToSink> {
+        match fs::File::create(&path).await {
+            Ok(file) => {
+                tracing::debug!(%id, path = %path.display(), "recording session");
+                Some(SendToSink::new(file))
+            }
+            Err(err) => {
+                tracing::warn!(%id, path = %path.display(), %err, "failed to create recording of session");
+                None
+            }
+        }
+    }
+
+    /// Keep track of the SSRC the muxer uses for its RTP stream. The SSRC
+    /// is chosen by the muxer, so we learn it from the packets it produ-
+    /// ces. It changes when the muxer is reinitialized.
+    fn update_ssrc(ssrc_tx: &SessionSsrcTx, packet: &[video::RtpBuf]) {
+        let ssrc = packet.iter().find_map(|buf| match buf {
+            video::RtpBuf::Rtp(buf) => rtp::ssrc(buf),
+            video::RtpBuf::Rtcp(_) => None,
+        });
+        if let Some(ssrc) = ssrc {
+            ssrc_tx.send_if_modified(|current| {
+                if *current != Some(ssrc) {
+                    *current = Some(ssrc);
+                    true
+                } else {
+                    false
+                }
+            });
+        }
+    }
+
+    /// Stream state of the first RTP packet among the muxed packets, if
+    /// there is any RTP packet among them.
+    fn stream_state_of(bufs: &[video::RtpBuf]) -> Option<media::StreamState> {
+        bufs.iter().find_map(|buf| match buf {
+            video::RtpBuf::Rtp(buf) => Some(media::StreamState {
+                rtp_seq: rtp::seq(buf)?,
+                rtp_timestamp: rtp::timestamp(buf)?,
+            }),
+            video::RtpBuf::Rtcp(_) => None,
+        })
+    }
+
+    /// Connect a UDP socket to the client. Returns whether the socket is
+    /// connected, otherwise the session has to send to the client with
+    /// `send_to`.
+    async fn connect_udp(id: &SessionId, socket: &UdpSocket, remote: SocketAddr) -> bool {
+        match socket.connect(remote).await {
+            Ok(()) => true,
+            Err(err) => {
+                tracing::debug!(%id, %err, %remote, "failed to connect udp socket");
+                false
+            }
+        }
+    }
+
+    /// Receive RTCP from the client on the RTCP socket. Pends forever if
+    /// there is no RTCP socket, because RTCP is disabled. Cancel safe.
+    async fn recv_rtcp(
+        socket: Option<&UdpSocket>,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr)> {
+        match socket {
+            Some(socket) => socket.recv_from(buf).await,
+            None => future::pending().await,
+        }
+    }
+
+    /// Switch to sending to the address that the first packet of the
+    /// client came from (symmetric RTP).
+    fn learn_remote(kind: &str, remote: &mut SocketAddr, from: SocketAddr) {
+        if *remote != from {
+            tracing::debug!(kind, advertised = %remote, observed = %from, "learned client port");
+            *remote = from;
+        }
+    }
+
+    /// Check whether incoming RTCP contains a BYE from the client. The
+    /// SSRC of the client is learned from the first report it sends, so
+    /// that a BYE is only accepted for the SSRC that belongs to the cli-
+    /// ent of this session. If we have not seen any report yet, any BYE
+    /// on the session's RTCP channel is accepted.
+    fn is_client_bye(buf: &[u8], client_ssrc: &mut Option<u32>) -> bool {
+        rtcp::packets(buf).any(|packet| {
+            if packet.packet_type == rtcp::PACKET_TYPE_BYE {
+                match client_ssrc {
+                    Some(client_ssrc) => packet.bye_ssrcs().any(|ssrc| ssrc == *client_ssrc),
+                    None => true,
+                }
+            } else {
+                if client_ssrc.is_none() {
+                    *client_ssrc = packet.ssrc();
+                }
+                false
+            }
+        })
+    }
+
+    async fn reinitialize_muxer(
+        media_info: media::MediaInfo,
+    ) -> Result<video::RtpMuxer, video::Error> {
+        rtp_muxer::make_rtp_muxer().await.and_then(|mut rtp_muxer| {
+            for stream_info in media_info.streams {
+                tracing::trace!(
+                    stream_index = stream_info.index,
+                    "reinitializing muxer: adding stream to muxer",
+                );
+                rtp_muxer = rtp_muxer.with_stream(stream_info)?;
+            }
+            Ok(rtp_muxer)
+        })
+    }
+
+    fn is_range_supported(range: &rtsp::Range) -> bool {
+        match (range.start.as_ref(), range.end.as_ref()) {
+            (Some(rtsp::NptTime::Now), None) => true,
+            (Some(rtsp::NptTime::Time(start)), None) if *start <= 0.0 => true,
+            _ => false,
+        }
+    }
+}
+
+/// Worker of a session. Sessions run the same loop whatever their target,
+/// and leave what depends on the target to a [`Delivery`].
+struct SessionLoop {
+    id: SessionId,
+    mux_errors: MuxErrors,
+    pacing: Option<TokenBucket>,
+    teardown_on_bye: bool,
+    /// Keyframe to send to the client once it starts playing, if the ses-
+    /// sion sends one.
+    synthetic_keyframe: Option<SyntheticKeyframe>,
+    /// Recording of the session, if it is recorded, see [`SessionSetup::
+    /// record_dir`].
+    secondary_sink: Option<SendToSink>,
+    /// Sessions that write to a sink do not time out.
+    timeout: Option<Duration>,
+    sender_reports: Option<SenderReports>,
+    sender_report_interval: Duration,
+    control_rx: SessionControlRx,
+    state_tx: SessionStateTx,
+    stream_state_tx: SessionStreamStateTx,
+    ssrc_tx: SessionSsrcTx,
+    counters: Arc<SessionCounters>,
+    cancellation: Cancellation,
+    /// Until when the pacing holds back the packet that the delivery is to
+    /// send next, if it holds back any.
+    paced_until: Option<Instant>,
+    state: SessionMediaState,
+    need_stream_state: bool,
+    last_activity: Instant,
+    client_ssrc: Option<u32>,
+}
+
+impl SessionLoop {
+    async fn run(
+        mut self,
         source_delegate: SourceDelegate,
         mut muxer: video::RtpMuxer,
-        target: setup::SendInterleaved,
-        mut control_rx: SessionControlRx,
-        stream_state_tx: SessionStreamStateTx,
-        mut task_context: TaskContext,
-    ) {
-        let mut state = SessionMediaState::Ready;
-        let mut need_stream_state = false;
-
+        mut delivery: impl Delivery,
+    ) -> StopReason {
+        let mut sender_report_interval = time::interval(self.sender_report_interval);
         let (mut source_reset_rx, mut source_packet_rx) = source_delegate.into_parts();
+        if let Some(synthetic_keyframe) = self.synthetic_keyframe.as_mut() {
+            synthetic_keyframe.update_parameter_sets(&muxer);
+        }
 
-        'main: loop {
-            select! {
+        let stop_reason = loop {
+            let handled = select! {
               // CANCEL SAFETY: `broadcast::Receiver::recv` is cancel safe.
               reset = source_reset_rx.recv() => {
-                // If the source reader had an error and reinitialized its reader, then regained
-                // the connection, we must reinitialize our muxer as well to cope.
                 match reset {
+                  // If the source reader had an error and reinitialized its reader, then regained
+                  // the connection, we must reinitialize our muxer as well to cope.
                   Ok(media_info) => {
                     tracing::trace!("reinitializing muxer");
-                    let new_muxer = rtp_muxer::make_rtp_muxer()
-                      .await
-                      .and_then(|mut rtp_muxer| {
-                        for stream_info in media_info.streams {
-                          tracing::trace!(
-                            stream_index=stream_info.index,
-                            "reinitializing muxer: adding stream to muxer",
-                          );
-                          rtp_muxer = rtp_muxer.with_stream(stream_info)?;
-                        }
-                        Ok(rtp_muxer)
-                      });
-
-                    match new_muxer {
+                    match Session::reinitialize_muxer(media_info).await {
                       Ok(new_muxer) => {
                         muxer = new_muxer;
+                        if let Some(synthetic_keyframe) = self.synthetic_keyframe.as_mut() {
+                          synthetic_keyframe.update_parameter_sets(&muxer);
+                        }
                       },
-                      Err(err) => {
-                        tracing::error!(%err, %id, "failed to reinitialize muxer");
-                      },
+                      Err(err) => tracing::error!(%err, id = %self.id, "failed to reinitialize muxer"),
                     };
+                    Ok(())
                   },
-                  Err(_) => {
-                    tracing::error!(%id, "source broken");
-                    break;
-                  },
+                  Err(err) => self.handle_source_error(err, "resets"),
                 }
               },
               // CANCEL SAFETY: `broadcast::Receiver::recv` is cancel safe.
@@ -217,104 +684,315 @@ impl Session {
                   Ok(packet) => {
                     let (muxed, packet) = rtp_muxer::muxed(muxer, packet).await;
                     muxer = muxed;
-
-                    if need_stream_state {
-                      tracing::trace!(%id, "fetching stream state");
-                      let (rtp_seq, rtp_timestamp) = muxer.seq_and_timestamp();
-                      let stream_state = media::StreamState {
-                        rtp_seq,
-                        rtp_timestamp,
-                      };
-                      tracing::trace!(%id, rtp_seq, rtp_timestamp, "fetched stream state");
-                      let _ = stream_state_tx.send(stream_state);
-
-                      need_stream_state = false;
-                    }
-
-                    let packet = match packet {
-                      Ok(packet) => packet,
-                      Err(err) => {
-                        tracing::error!(%id, %err, "failed to mux packet");
-                        break;
-                      },
-                    };
-
-                    if state == SessionMediaState::Playing {
-                      let messages = packet
-                        .into_iter()
-                        .map(|item| {
-                          match item {
-                            video::RtpBuf::Rtp(payload) => {
-                              rtsp::ResponseMaybeInterleaved::Interleaved {
-                                channel: target.rtp_channel,
-                                payload: payload.into(),
-                              }
-                            },
-                            video::RtpBuf::Rtcp(payload) => {
-                              rtsp::ResponseMaybeInterleaved::Interleaved {
-                                channel: target.rtcp_channel,
-                                payload: payload.into(),
-                              }
-                            },
-                          }
-                        });
-
-                      for message in messages {
-                        if let Err(err) = target.sender.send(message) {
-                          tracing::trace!(%id, %err, "underlying connection closed");
-                          break 'main;
-                        }
-                      }
-                    }
-                  }
-                  Err(_) => {
-                    tracing::error!(%id, "source broken");
-                    break;
+                    self.handle_muxed(&mut delivery, packet).await
                   },
+                  Err(err) => self.handle_source_error(err, "packets"),
                 }
               },
+              // CANCEL SAFETY: `Delivery::receive` is cancel safe.
+              received = delivery.receive() => self.handle_received(received),
               // CANCEL SAFETY: `mpsc::UnboundedReceiver::recv` is cancel safe.
-              message = control_rx.recv() => {
-                match message {
-                  Some(SessionControlMessage::Play) => {
-                    state = SessionMediaState::Playing;
-                    tracing::info!(%id, "session now playing");
-                  },
-                  Some(SessionControlMessage::StreamState) => {
-                    need_stream_state = true;
-                    tracing::trace!(%id, "set need stream state flag");
-                  },
-                  None => {
-                    tracing::error!(%id, "session control channel broke unexpectedly");
-                    break;
-                  },
-                };
+              message = self.control_rx.recv() => self.handle_control(message),
+              // CANCEL SAFETY: `Cancellation::cancelled` is cancel safe.
+              stop_reason = self.cancellation.cancelled() => {
+                tracing::trace!(id = %self.id, %stop_reason, "session cancelled");
+                Err(stop_reason)
               },
-              // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
-              _ = task_context.wait_for_stop() => {
-                tracing::trace!("tearing down session");
-                break;
+              // CANCEL SAFETY: `SessionLoop::paced` is cancel safe.
+              _ = Self::paced(self.paced_until) => {
+                self.paced_until = None;
+                delivery.flush(&mut self).await
               },
+              // CANCEL SAFETY: `Interval::tick` is cancel safe.
+              _ = sender_report_interval.tick(), if self.sender_reports.is_some() && self.state == SessionMediaState::Playing => {
+                self.send_sender_report(&mut delivery).await
+              },
+              // CANCEL SAFETY: `SessionLoop::timed_out` is cancel safe.
+              _ = Self::timed_out(self.last_activity, self.timeout) => {
+                tracing::info!(id = %self.id, timeout = ?self.timeout, "session timed out");
+                Err(StopReason::TimedOut)
+              },
+            };
+            if let Err(stop_reason) = handled {
+                break stop_reason;
+            }
+        };
+
+        if stop_reason.is_intentional() {
+            // Best effort: let the client know that the stream ended, rather than have it find out
+            // through silence.
+            let ssrc = *self.ssrc_tx.borrow();
+            if let Some(ssrc) = ssrc {
+                delivery.send_bye(rtcp::bye(ssrc)).await;
             }
         }
 
-        tracing::trace!(%id, "finishing muxer");
+        delivery.close().await;
+        if let Some(mut secondary_sink) = self.secondary_sink {
+            let _ = secondary_sink.shutdown().await;
+        }
+
+        tracing::trace!(id = %self.id, "finishing muxer");
         // Throw away possible last RTP buffer (we don't care about
         // it since this is real-time and there's no "trailer".
         let _ = rtp_muxer::finish(muxer).await;
-        tracing::trace!(%id, "finished muxer");
+        tracing::trace!(id = %self.id, "finished muxer");
+
+        stop_reason
     }
 
-    fn is_range_supported(range: &rtsp::Range) -> bool {
-        match (range.start.as_ref(), range.end.as_ref()) {
-            (Some(rtsp::NptTime::Now), None) => true,
-            (Some(rtsp::NptTime::Time(start)), None) if *start <= 0.0 => true,
-            _ => false,
+    /// Handle what the muxer made of a packet from the source, and deliver
+    /// it if the session is playing. Packets that fail to mux are dropped
+    /// until too many fail in a row.
+    async fn handle_muxed(
+        &mut self,
+        delivery: &mut impl Delivery,
+        muxed: Result<Vec<video::RtpBuf>, video::Error>,
+    ) -> Result<(), StopReason> {
+        let packet = match muxed {
+            Ok(packet) => {
+                self.mux_errors.reset();
+                packet
+            }
+            Err(err) => {
+                self.counters.record_mux_error();
+                if self.mux_errors.tolerate() {
+                    tracing::warn!(
+                        id = %self.id, %err, consecutive = self.mux_errors.consecutive(),
+                        "failed to mux packet, dropping it",
+                    );
+                    self.counters.record_mux_dropped();
+                    return Ok(());
+                }
+                tracing::error!(id = %self.id, %err, "failed to mux packet");
+                self.fail(&err);
+                return Err(StopReason::MuxFailed);
+            }
+        };
+
+        let mut packet = delivery.prepare(packet);
+        Session::update_ssrc(&self.ssrc_tx, &packet);
+        if let Some(synthetic_keyframe) = self.synthetic_keyframe.as_mut() {
+            synthetic_keyframe.observe(&packet);
+        }
+        if self.state != SessionMediaState::Playing {
+            return Ok(());
+        }
+
+        // Report the stream state of the first RTP packet that is sent after PLAY, so that the
+        // RTP-Info the client receives matches it exactly.
+        if self.need_stream_state {
+            if let Some(mut stream_state) = Session::stream_state_of(&packet) {
+                let mut join = self.join(&mut stream_state);
+                tracing::trace!(
+                    id = %self.id, rtp_seq = stream_state.rtp_seq, rtp_timestamp = stream_state.rtp_timestamp,
+                    "fetched stream state",
+                );
+                let _ = self.stream_state_tx.send(stream_state);
+                self.need_stream_state = false;
+
+                join.append(&mut packet);
+                packet = join;
+            }
+        }
+
+        self.write_secondary_sink(&packet).await;
+        delivery.deliver(self, packet).await
+    }
+
+    /// Write muxed packets to the recording of the session, if any. The
+    /// recording is dropped on the first error so that it cannot affect
+    /// delivery to the client.
+    async fn write_secondary_sink(&mut self, packet: &[video::RtpBuf]) {
+        if let Some(secondary_sink) = self.secondary_sink.as_mut() {
+            if let Err(err) = secondary_sink.write(packet).await {
+                tracing::warn!(id = %self.id, %err, "failed to write recording of session (dropping it)");
+                self.secondary_sink = None;
+            }
+        }
+    }
+
+    /// Packets to send right before the first packet the client gets after
+    /// it starts playing, which has stream state `stream_state`. The stream
+    /// state is moved back to match the first packet that is returned.
+    fn join(&self, stream_state: &mut media::StreamState) -> Vec<video::RtpBuf> {
+        let synthetic_keyframe = match self.synthetic_keyframe.as_ref() {
+            Some(synthetic_keyframe) => synthetic_keyframe,
+            None => return Vec::new(),
+        };
+        let ssrc = match *self.ssrc_tx.borrow() {
+            Some(ssrc) => ssrc,
+            None => return Vec::new(),
+        };
+        match synthetic_keyframe.generate(ssrc, stream_state) {
+            Ok(packets) => {
+                tracing::trace!(id = %self.id, "generated synthetic keyframe");
+                packets.into_iter().map(video::RtpBuf::Rtp).collect()
+            }
+            Err(err) => {
+                tracing::warn!(id = %self.id, %err, "failed to generate synthetic keyframe");
+                Vec::new()
+            }
+        }
+    }
+
+    /// Handle an error receiving `what` from the source. The source does
+    /// not wait for sessions that cannot keep up, so that one slow client
+    /// cannot hold up the others. Whatever the session missed is lost.
+    fn handle_source_error(
+        &self,
+        err: broadcast::error::RecvError,
+        what: &str,
+    ) -> Result<(), StopReason> {
+        match err {
+            broadcast::error::RecvError::Lagged(skipped) => {
+                tracing::warn!(id = %self.id, skipped, "session lagging behind source, skipped {what}");
+                Ok(())
+            }
+            broadcast::error::RecvError::Closed => {
+                tracing::error!(id = %self.id, "source broken");
+                self.fail("source broken");
+                Err(StopReason::SourceBroken)
+            }
+        }
+    }
+
+    fn handle_received(&mut self, received: FromClient) -> Result<(), StopReason> {
+        match received {
+            FromClient::Rtcp(rtcp) => {
+                self.last_activity = Instant::now();
+                if Session::is_client_bye(&rtcp, &mut self.client_ssrc) {
+                    if self.teardown_on_bye {
+                        tracing::info!(id = %self.id, client_ssrc = ?self.client_ssrc, "client sent rtcp bye");
+                        return Err(StopReason::ClientByeReceived);
+                    }
+                    tracing::trace!(id = %self.id, client_ssrc = ?self.client_ssrc, "ignoring rtcp bye from client");
+                }
+            }
+            FromClient::Alive => {
+                self.last_activity = Instant::now();
+            }
+            FromClient::Nothing => {}
+        }
+        Ok(())
+    }
+
+    fn handle_control(&mut self, message: Option<SessionControlMessage>) -> Result<(), StopReason> {
+        match message {
+            Some(SessionControlMessage::Play) => {
+                self.state = SessionMediaState::Playing;
+                tracing::info!(id = %self.id, "session now playing");
+                let _ = self.state_tx.send(SessionState::Playing(self.id.clone()));
+                self.need_stream_state = true;
+            }
+            Some(SessionControlMessage::Pause) => {
+                if self.state == SessionMediaState::Playing {
+                    self.state = SessionMediaState::Paused;
+                    tracing::info!(id = %self.id, "session paused");
+                    let _ = self.state_tx.send(SessionState::Paused(self.id.clone()));
+                }
+            }
+            Some(SessionControlMessage::KeepAlive) => {
+                self.last_activity = Instant::now();
+                tracing::trace!(id = %self.id, "session kept alive");
+            }
+            None => {
+                tracing::error!(id = %self.id, "session control channel broke unexpectedly");
+                return Err(StopReason::ControlBroken);
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a sender report of our own, in addition to those of the muxer.
+    /// It is delivered like the RTCP of the muxer, so that over interleav-
+    /// ed connections it never overtakes the RTP packets it describes.
+    async fn send_sender_report(&mut self, delivery: &mut impl Delivery) -> Result<(), StopReason> {
+        let report = self
+            .sender_reports
+            .as_ref()
+            .and_then(|sender_reports| sender_reports.report(Instant::now(), SystemTime::now()));
+        match report {
+            Some(report) => {
+                delivery
+                    .deliver(self, vec![video::RtpBuf::Rtcp(report)])
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Take `len` bytes from the pacing for the packet that the delivery is
+    /// to send next. Returns `false` if the pacing holds the packet back,
+    /// in which case the delivery keeps it and sends it, and whatever it
+    /// queued after it, when the session calls [`Delivery::flush`]. The
+    /// session keeps handling control messages, the client and the source
+    /// in the meantime.
+    fn pace(&mut self, len: usize) -> bool {
+        let now = Instant::now();
+        let delay = match self.pacing.as_mut() {
+            Some(pacing) => pacing.take(len, now),
+            None => return true,
+        };
+        if delay.is_zero() {
+            return true;
+        }
+        self.paced_until = Some(now + delay);
+        false
+    }
+
+    /// Whether the pacing holds back a packet, so that the delivery must
+    /// queue packets rather than send them.
+    fn is_paced(&self) -> bool {
+        self.paced_until.is_some()
+    }
+
+    /// Count a packet that was sent to the client.
+    fn record_sent(&mut self, buf: &video::RtpBuf) {
+        self.counters.record_sent(buf);
+        if let (Some(sender_reports), video::RtpBuf::Rtp(buf)) = (self.sender_reports.as_mut(), buf)
+        {
+            sender_reports.record(buf, Instant::now());
+        }
+    }
+
+    /// Count an RTP packet that was sent to the client, and keep track of
+    /// it for the sender reports.
+    fn record_rtp(&mut self, buf: &[u8]) {
+        self.counters.record_rtp(buf.len());
+        if let Some(sender_reports) = self.sender_reports.as_mut() {
+            sender_reports.record(buf, Instant::now());
+        }
+    }
+
+    /// Report that the session failed with `err`.
+    fn fail(&self, err: impl fmt::Display) {
+        let _ = self
+            .state_tx
+            .send(SessionState::Error(self.id.clone(), err.to_string()));
+    }
+
+    /// Wait until the pacing lets the delivery send the packet it holds
+    /// back. Pends forever if it holds back none. Cancel safe.
+    async fn paced(paced_until: Option<Instant>) {
+        match paced_until {
+            Some(paced_until) => time::sleep_until(paced_until).await,
+            None => future::pending().await,
+        }
+    }
+
+    /// Wait until the session times out for lack of signs of life from the
+    /// client since `last_activity`. Pends forever if the session does not
+    /// time out. Cancel safe.
+    async fn timed_out(last_activity: Instant, timeout: Option<Duration>) {
+        match timeout {
+            Some(timeout) => time::sleep_until(last_activity + timeout).await,
+            None => future::pending().await,
         }
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct SessionId(String);
 
 impl SessionId {
@@ -343,6 +1021,67 @@ impl From<&str> for SessionId {
     }
 }
 
+/// Generates the IDs of new sessions. IDs need not be unique: the ses-
+/// sion manager generates another one if an ID is already in use.
+pub trait SessionIdGenerator: Send + Sync {
+    fn generate(&self) -> SessionId;
+}
+
+impl<F> SessionIdGenerator for F
+where
+    F: Fn() -> SessionId + Send + Sync,
+{
+    fn generate(&self) -> SessionId {
+        self()
+    }
+}
+
+/// Generates random session IDs of `len` characters from `alphabet`.
+/// Session IDs may only contain letters, digits and the characters
+/// `$-_.+` (RFC 2326 section 3.4). Without an alphabet, IDs are decimal
+/// numbers without leading zeros, so that the default configuration
+/// generates the same IDs as [`SessionId::generate`]. If `len` is zero
+/// or `alphabet` is empty, [`SessionId::generate`] is used instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionIdConfig {
+    pub len: usize,
+    pub alphabet: Option<String>,
+}
+
+impl Default for SessionIdConfig {
+    fn default() -> Self {
+        Self {
+            len: SessionId::SESSION_ID_LEN as usize,
+            alphabet: None,
+        }
+    }
+}
+
+impl SessionIdGenerator for SessionIdConfig {
+    fn generate(&self) -> SessionId {
+        let mut rng = rand::thread_rng();
+        match self.alphabet.as_deref() {
+            _ if self.len == 0 => SessionId::generate(),
+            Some("") => SessionId::generate(),
+            Some(alphabet) => {
+                let alphabet = alphabet.chars().collect::<Vec<_>>();
+                SessionId(
+                    (0..self.len)
+                        .map(|_| alphabet[rng.gen_range(0..alphabet.len())])
+                        .collect(),
+                )
+            }
+            None => SessionId(
+                iter::once(rng.gen_range(1..10))
+                    .chain(iter::repeat_with(|| rng.gen_range(0..10)))
+                    .take(self.len)
+                    .map(|digit| char::from(b'0' + digit))
+                    .collect(),
+            ),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum PlaySessionError {
     RangeNotSupported,
@@ -360,8 +1099,340 @@ impl fmt::Display for PlaySessionError {
 
 impl error::Error for PlaySessionError {}
 
+#[derive(Debug)]
+pub enum SessionControlError {
+    ControlBroken,
+}
+
+impl fmt::Display for SessionControlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionControlError::ControlBroken => write!(f, "failed to control session"),
+        }
+    }
+}
+
+impl error::Error for SessionControlError {}
+
+/// Reason for a session to stop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Teardown,
+    SourceBroken,
+    MuxFailed,
+    TargetClosed,
+    ControlBroken,
+    ClientByeReceived,
+    SourceDrained,
+    Evicted,
+    TimedOut,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Teardown => write!(f, "teardown"),
+            StopReason::SourceBroken => write!(f, "source broken"),
+            StopReason::MuxFailed => write!(f, "failed to mux packet"),
+            StopReason::TargetClosed => write!(f, "target closed"),
+            StopReason::ControlBroken => write!(f, "control channel broken"),
+            StopReason::ClientByeReceived => write!(f, "client sent rtcp bye"),
+            StopReason::SourceDrained => write!(f, "source drained"),
+            StopReason::Evicted => write!(f, "evicted by newer session of same client"),
+            StopReason::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+impl StopReason {
+    /// Whether the session was stopped on purpose, rather than because
+    /// something broke. The client is told about intentional stops with
+    /// an RTCP BYE.
+    fn is_intentional(&self) -> bool {
+        matches!(
+            self,
+            StopReason::Teardown
+                | StopReason::SourceDrained
+                | StopReason::Evicted
+                | StopReason::TimedOut
+        )
+    }
+}
+
 #[derive(PartialEq)]
 enum SessionMediaState {
     Ready,
     Playing,
+    Paused,
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+
+    use tokio::io::AsyncReadExt;
+    use tokio::sync::{broadcast, mpsc, watch};
+    use tokio::time::Instant;
+
+    use video_rs as video;
+
+    use crate::media::video::h264::tests::{PPS, SPS};
+    use crate::net::connection::{response_channel, InterleavedReceivers};
+    use crate::session::cancellation::Cancellation;
+    use crate::session::delivery::Delivery;
+    use crate::session::keyframe::SyntheticKeyframe;
+    use crate::session::mux_errors::{self, MuxErrors};
+    use crate::session::sender_report;
+    use crate::session::setup::{SendInterleaved, SendOverSocket, SendToSink, SessionSetupTarget};
+
+    use super::{
+        rtp, Session, SessionId, SessionIdConfig, SessionIdGenerator, SessionLoop,
+        SessionMediaState, SessionTransport, StopReason,
+    };
+
+    /// Worker of a session that is playing, without a source or a client,
+    /// to hand packets to directly.
+    pub(super) fn playing_session_loop() -> SessionLoop {
+        let (_, control_rx) = mpsc::unbounded_channel();
+        let (state_tx, _) = mpsc::unbounded_channel();
+        let (stream_state_tx, _) = broadcast::channel(Session::MAX_QUEUED_INFO);
+        let (ssrc_tx, _) = watch::channel(None);
+        SessionLoop {
+            id: SessionId::from("test"),
+            mux_errors: MuxErrors::new(mux_errors::DEFAULT_MAX_CONSECUTIVE),
+            pacing: None,
+            teardown_on_bye: true,
+            synthetic_keyframe: None,
+            secondary_sink: None,
+            timeout: None,
+            sender_reports: None,
+            sender_report_interval: sender_report::DEFAULT_INTERVAL,
+            control_rx,
+            state_tx,
+            stream_state_tx,
+            ssrc_tx,
+            counters: Arc::default(),
+            cancellation: Cancellation::new(),
+            paced_until: None,
+            state: SessionMediaState::Playing,
+            need_stream_state: false,
+            last_activity: Instant::now(),
+            client_ssrc: None,
+        }
+    }
+
+    /// Delivery that keeps what it is handed.
+    #[derive(Default)]
+    pub(super) struct Recorded(Vec<video::RtpBuf>);
+
+    impl Delivery for Recorded {
+        async fn deliver(
+            &mut self,
+            _session: &mut SessionLoop,
+            packet: Vec<video::RtpBuf>,
+        ) -> Result<(), StopReason> {
+            self.0.extend(packet);
+            Ok(())
+        }
+    }
+
+    impl Recorded {
+        /// Sequence numbers and timestamps of the RTP packets handed over.
+        fn rtp(&self) -> Vec<(u16, u32)> {
+            self.0
+                .iter()
+                .filter_map(|buf| match buf {
+                    video::RtpBuf::Rtp(buf) => Some((rtp::seq(buf)?, rtp::timestamp(buf)?)),
+                    video::RtpBuf::Rtcp(_) => None,
+                })
+                .collect()
+        }
+    }
+
+    fn rtp_at(seq: u16, timestamp: u32) -> video::RtpBuf {
+        rtp_from(1, seq, timestamp)
+    }
+
+    /// RTP packet of the stream with SSRC `ssrc`.
+    pub(super) fn rtp_from(ssrc: u32, seq: u16, timestamp: u32) -> video::RtpBuf {
+        let mut packet = vec![0x80, 96];
+        packet.extend(seq.to_be_bytes());
+        packet.extend(timestamp.to_be_bytes());
+        packet.extend(ssrc.to_be_bytes());
+        packet.push(0x41);
+        video::RtpBuf::Rtp(packet)
+    }
+
+    #[tokio::test]
+    async fn synthetic_keyframe_precedes_first_rtp_packet() {
+        let mut session = playing_session_loop();
+        session.synthetic_keyframe =
+            Some(SyntheticKeyframe::new(1400).with_parameter_sets(&SPS, &PPS));
+        let mut stream_state_rx = session.stream_state_tx.subscribe();
+        let mut delivery = Recorded::default();
+
+        // The session learns the frame interval before the client plays.
+        session.state = SessionMediaState::Ready;
+        for packet in [rtp_at(10, 0), rtp_at(11, 3600)] {
+            session
+                .handle_muxed(&mut delivery, Ok(vec![packet]))
+                .await
+                .unwrap();
+        }
+        assert!(delivery.0.is_empty());
+
+        session.state = SessionMediaState::Playing;
+        session.need_stream_state = true;
+        session
+            .handle_muxed(&mut delivery, Ok(vec![rtp_at(12, 7200), rtp_at(13, 7200)]))
+            .await
+            .unwrap();
+        assert_eq!(delivery.rtp(), vec![(11, 3600), (12, 7200), (13, 7200)]);
+        let stream_state = stream_state_rx.try_recv().unwrap();
+        assert_eq!(
+            (stream_state.rtp_seq, stream_state.rtp_timestamp),
+            (11, 3600),
+        );
+
+        // Only the first packet after PLAY is preceded by a keyframe.
+        session
+            .handle_muxed(&mut delivery, Ok(vec![rtp_at(14, 10800)]))
+            .await
+            .unwrap();
+        assert_eq!(delivery.rtp().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn packets_that_fail_to_mux_are_dropped_until_too_many_in_a_row() {
+        let mut session = playing_session_loop();
+        session.mux_errors = MuxErrors::new(2);
+        let mut delivery = Recorded::default();
+
+        // Every third packet fails to mux.
+        for seq in 0..30 {
+            let muxed = match seq % 3 {
+                2 => Err(video::Error::InvalidFrameFormat),
+                _ => Ok(vec![rtp_at(seq, 0)]),
+            };
+            assert!(session.handle_muxed(&mut delivery, muxed).await.is_ok());
+        }
+        assert_eq!(delivery.rtp().len(), 20);
+        let stats = session.counters.snapshot();
+        assert_eq!((stats.mux_errors, stats.mux_dropped), (10, 10));
+
+        // The last packet failed too, so one more failure is tolerated.
+        assert!(session
+            .handle_muxed(&mut delivery, Err(video::Error::InvalidFrameFormat))
+            .await
+            .is_ok());
+        assert!(matches!(
+            session
+                .handle_muxed(&mut delivery, Err(video::Error::InvalidFrameFormat))
+                .await,
+            Err(StopReason::MuxFailed),
+        ));
+        let stats = session.counters.snapshot();
+        assert_eq!((stats.mux_errors, stats.mux_dropped), (12, 11));
+    }
+
+    #[tokio::test]
+    async fn failing_recording_does_not_affect_delivery() {
+        let mut session = playing_session_loop();
+        let (recording, mut recording_reader) = tokio::io::duplex(64);
+        session.secondary_sink = Some(SendToSink::new(recording));
+        let mut delivery = Recorded::default();
+
+        session
+            .handle_muxed(&mut delivery, Ok(vec![rtp_at(0, 0)]))
+            .await
+            .unwrap();
+        let mut recorded = [0; 2 + 13];
+        recording_reader.read_exact(&mut recorded).await.unwrap();
+        assert_eq!(recorded[..2], [0, 13]);
+
+        // Nothing reads the recording anymore, so writing it fails.
+        drop(recording_reader);
+        for seq in 1..3 {
+            session
+                .handle_muxed(&mut delivery, Ok(vec![rtp_at(seq, 0)]))
+                .await
+                .unwrap();
+        }
+        assert!(session.secondary_sink.is_none());
+        assert_eq!(delivery.rtp(), vec![(0, 0), (1, 0), (2, 0)]);
+    }
+
+    #[test]
+    fn session_transport_of_target() {
+        let udp = SessionSetupTarget::RtpUdp(SendOverSocket {
+            rtp_remote: "10.0.0.1:5000".parse().unwrap(),
+            rtcp_remote: "10.0.0.1:5001".parse().unwrap(),
+        });
+        let (sender, _) = response_channel();
+        let tcp = SessionSetupTarget::RtpTcp(SendInterleaved {
+            sender,
+            rtp_channel: 2,
+            rtcp_channel: 3,
+            rtcp_rx: InterleavedReceivers::default().claim(2, 3).unwrap(),
+        });
+        assert_eq!(
+            SessionTransport::from(&udp),
+            SessionTransport::Udp {
+                rtp_remote: "10.0.0.1:5000".parse().unwrap(),
+                rtcp_remote: "10.0.0.1:5001".parse().unwrap(),
+            },
+        );
+        assert_eq!(
+            SessionTransport::from(&tcp),
+            SessionTransport::TcpInterleaved {
+                rtp_channel: 2,
+                rtcp_channel: 3,
+            },
+        );
+    }
+
+    #[test]
+    fn session_id_config_alphabet() {
+        let config = SessionIdConfig {
+            len: 24,
+            alphabet: Some("ab".to_string()),
+        };
+        let id = config.generate().to_string();
+        assert_eq!(id.len(), 24);
+        assert!(id.chars().all(|c| c == 'a' || c == 'b'));
+    }
+
+    #[test]
+    fn session_id_config_default_matches_generate() {
+        let range =
+            10_u32.pow(SessionId::SESSION_ID_LEN - 1)..10_u32.pow(SessionId::SESSION_ID_LEN);
+        for _ in 0..1000 {
+            for id in [SessionIdConfig::default().generate(), SessionId::generate()] {
+                let id = id.to_string();
+                assert_eq!(id.len(), SessionId::SESSION_ID_LEN as usize);
+                assert!(range.contains(&id.parse::<u32>().unwrap()), "{}", id);
+            }
+        }
+    }
+
+    #[test]
+    fn stream_state_of_first_rtp_packet() {
+        let rtp = |seq: u16, timestamp: u32| {
+            let mut packet = vec![0x80, 96];
+            packet.extend(seq.to_be_bytes());
+            packet.extend(timestamp.to_be_bytes());
+            packet.extend([0; 4]);
+            video::RtpBuf::Rtp(packet)
+        };
+        let sender_report = || video::RtpBuf::Rtcp(vec![0x80, 200, 0x00, 0x06]);
+        let stream_state =
+            Session::stream_state_of(&[sender_report(), rtp(1000, 90000), rtp(1001, 93000)])
+                .unwrap();
+        assert_eq!(stream_state.rtp_seq, 1000);
+        assert_eq!(stream_state.rtp_timestamp, 90000);
+        // Nothing to report until the muxer produces RTP.
+        assert!(Session::stream_state_of(&[sender_report()]).is_none());
+        assert!(Session::stream_state_of(&[]).is_none());
+    }
 }
@@ -0,0 +1,105 @@
+//! Tolerance for packets that fail to mux. Live sources occasionally pro-
+//! duce a bad frame, such as a corrupt NAL unit, which the muxer refuses.
+//! Such a packet is dropped and the session carries on. Only when packets
+//! keep failing in a row is the source considered broken beyond repair,
+//! and the session is stopped.
+
+/// Number of packets in a row that may fail to mux before the session is
+/// stopped, unless configured otherwise.
+pub const DEFAULT_MAX_CONSECUTIVE: usize = 10;
+
+#[derive(Debug)]
+pub struct MuxErrors {
+    max_consecutive: usize,
+    consecutive: usize,
+}
+
+impl MuxErrors {
+    /// Tolerate up to `max_consecutive` packets in a row that fail to mux.
+    /// With zero, the first failure is fatal.
+    pub fn new(max_consecutive: usize) -> Self {
+        Self {
+            max_consecutive,
+            consecutive: 0,
+        }
+    }
+
+    /// Keep track of a packet that failed to mux. Returns `true` if the
+    /// packet can be dropped and the session carry on, or `false` if too
+    /// many packets in a row failed.
+    pub fn tolerate(&mut self) -> bool {
+        self.consecutive += 1;
+        self.consecutive <= self.max_consecutive
+    }
+
+    /// Keep track of a packet that was muxed, which ends any run of fail-
+    /// ures.
+    pub fn reset(&mut self) {
+        self.consecutive = 0;
+    }
+
+    /// Number of packets in a row that failed to mux so far.
+    pub fn consecutive(&self) -> usize {
+        self.consecutive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::MuxErrors;
+
+    /// Stand-in for the muxer that fails on every `n`-th packet.
+    struct FlakyMuxer {
+        n: usize,
+        until_failure: usize,
+    }
+
+    impl FlakyMuxer {
+        fn new(n: usize) -> Self {
+            Self {
+                n,
+                until_failure: n,
+            }
+        }
+
+        fn mux(&mut self) -> Result<(), ()> {
+            self.until_failure -= 1;
+            if self.until_failure == 0 {
+                self.until_failure = self.n;
+                Err(())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn survives_occasional_errors() {
+        let mut muxer = FlakyMuxer::new(3);
+        let mut mux_errors = MuxErrors::new(1);
+        let mut dropped = 0;
+        for _ in 0..300 {
+            match muxer.mux() {
+                Ok(()) => mux_errors.reset(),
+                Err(()) => {
+                    assert!(mux_errors.tolerate());
+                    dropped += 1;
+                }
+            }
+        }
+        assert_eq!(dropped, 100);
+    }
+
+    #[test]
+    fn gives_up_after_consecutive_errors() {
+        let mut mux_errors = MuxErrors::new(2);
+        assert!(mux_errors.tolerate());
+        assert!(mux_errors.tolerate());
+        assert!(!mux_errors.tolerate());
+        assert_eq!(mux_errors.consecutive(), 3);
+        mux_errors.reset();
+        assert!(mux_errors.tolerate());
+        assert!(!MuxErrors::new(0).tolerate());
+    }
+}
@@ -0,0 +1,32 @@
+use crate::session::{SessionId, StopReason};
+
+/// Observer of the lifecycle of sessions, for applications that embed
+/// the server and need to react to sessions coming and going, for exam-
+/// ple for billing or to keep an external registry up to date.
+///
+/// The session manager calls the observer from its own worker, which also
+/// handles the state of all other sessions, and without holding any lock,
+/// so the observer may call back into the manager. Callbacks must return
+/// quickly though: they run on the async runtime, and a callback that
+/// blocks holds up the calls for all other sessions. Offload anything
+/// slow, such as requests to another service, to a task or channel of
+/// your own.
+///
+/// All callbacks do nothing by default.
+pub trait SessionObserver: Send + Sync {
+    /// Session was set up and started. Called once per session.
+    fn on_started(&self, _id: &SessionId) {}
+
+    /// Session started or resumed playing.
+    fn on_playing(&self, _id: &SessionId) {}
+
+    /// Session was paused.
+    fn on_paused(&self, _id: &SessionId) {}
+
+    /// Session failed. It is stopped right after.
+    fn on_error(&self, _id: &SessionId, _error: &str) {}
+
+    /// Session stopped for the given reason. Called once per session,
+    /// also for sessions that are still running when the manager stops.
+    fn on_stopped(&self, _id: &SessionId, _reason: StopReason) {}
+}
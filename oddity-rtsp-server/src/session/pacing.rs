@@ -0,0 +1,137 @@
+//! Pacing of the packets a session sends, so that bursts of muxer output
+//! do not saturate the uplink when many high-bitrate sessions share it.
+//! Sends are limited with a token bucket: the bucket fills up at the max-
+//! imum bitrate, up to the burst size, and every packet takes its size
+//! from the bucket. A packet that takes more than there is waits until the
+//! bucket has refilled. The packets that follow queue up behind it, while
+//! the session carries on handling its client and source.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Maximum bitrate of a session and the burst it may send at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pacing {
+    /// Maximum bitrate in bits per second.
+    pub max_bitrate: u64,
+    /// Number of bytes that may be sent at once after the session has
+    /// been idle.
+    pub burst: usize,
+}
+
+impl Pacing {
+    /// Pace at the given maximum bitrate, with a burst of what the bitrate
+    /// allows in [`Pacing::DEFAULT_BURST_DURATION`], but at least one
+    /// full-sized packet.
+    pub fn new(max_bitrate: u64) -> Self {
+        let burst =
+            (max_bitrate as f64 / 8.0 * Self::DEFAULT_BURST_DURATION.as_secs_f64()) as usize;
+        Self {
+            max_bitrate,
+            burst: burst.max(Self::MIN_BURST),
+        }
+    }
+
+    pub fn with_burst(mut self, burst: usize) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    /// Burst unless configured otherwise, as the time it takes to send
+    /// it at the maximum bitrate.
+    pub const DEFAULT_BURST_DURATION: Duration = Duration::from_millis(100);
+
+    /// Smallest default burst, which fits a packet of a regular Ethernet
+    /// MTU.
+    const MIN_BURST: usize = 1500;
+}
+
+/// Most packets that wait for the pacing of a session. RTP beyond that
+/// is dropped, so that a source that keeps exceeding the maximum bitrate
+/// does not make the session buffer without bound.
+pub const MAX_HELD_BACK: usize = 1024;
+
+pub struct TokenBucket {
+    /// Bytes added to the bucket per second.
+    rate: f64,
+    burst: f64,
+    /// Bytes left in the bucket. Negative if packets were sent on credit
+    /// that has not been refilled yet.
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// Create a bucket that starts out full.
+    pub fn new(pacing: Pacing, now: Instant) -> Self {
+        Self {
+            rate: pacing.max_bitrate as f64 / 8.0,
+            burst: pacing.burst as f64,
+            tokens: pacing.burst as f64,
+            refilled_at: now,
+        }
+    }
+
+    /// Take `len` bytes from the bucket at `now`. Returns how long to wait
+    /// before sending them, which is zero if the bucket holds enough.
+    ///
+    /// The bytes are taken even if the bucket holds fewer, so that a
+    /// packet larger than the burst does not wait forever. The packets
+    /// that follow wait for the bucket to refill instead.
+    pub fn take(&mut self, len: usize, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        self.refilled_at = now;
+        self.tokens -= len as f64;
+        if self.tokens >= 0.0 || self.rate <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use tokio::time::{self, Instant};
+
+    use super::{Pacing, TokenBucket};
+
+    #[test]
+    fn burst_is_free() {
+        let now = Instant::now();
+        // 80 kbit/s is 10000 bytes per second.
+        let mut bucket = TokenBucket::new(Pacing::new(80_000).with_burst(3000), now);
+        assert_eq!(bucket.take(1000, now), Duration::ZERO);
+        assert_eq!(bucket.take(2000, now), Duration::ZERO);
+        assert_eq!(bucket.take(1000, now), Duration::from_millis(100));
+        // The bucket refills while idle, but not beyond the burst.
+        let later = now + Duration::from_secs(10);
+        assert_eq!(bucket.take(3000, later), Duration::ZERO);
+        assert_eq!(bucket.take(500, later), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn default_burst_fits_packet() {
+        assert_eq!(Pacing::new(8_000_000).burst, 100_000);
+        assert_eq!(Pacing::new(8_000).burst, 1500);
+    }
+
+    #[tokio::test]
+    async fn sends_at_capped_rate() {
+        // 800 kbit/s is 100000 bytes per second. After the burst of 1000
+        // bytes, the remaining 19000 bytes take 190ms.
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(Pacing::new(800_000).with_burst(1000), start);
+        for _ in 0..20 {
+            time::sleep(bucket.take(1000, Instant::now())).await;
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(180), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(400), "{:?}", elapsed);
+    }
+}
@@ -0,0 +1,158 @@
+//! Fitting the RTP output of the muxer to a path MTU. The muxer produces
+//! packets of up to 1024 bytes and offers no way to make them smaller. On
+//! links with a smaller MTU, such as some VPNs and cellular links, those
+//! packets would be fragmented at the IP layer, and losing any fragment
+//! loses the whole packet. Packets that do not fit are split up here in-
+//! stead, which only works for H.264 (RFC 6184).
+
+use video_rs as video;
+
+use crate::session::rtp;
+
+/// Smallest MTU we accept. Every IPv4 host must be able to receive data-
+/// grams of this size (RFC 791), and sizing packets for anything smaller
+/// leaves too little room for media.
+pub const MIN_MTU: usize = 576;
+
+/// Bytes of every packet that are taken by headers rather than payload:
+/// IPv6 (the larger of the two) plus UDP plus RTP.
+const OVERHEAD: usize = 40 + 8 + rtp::HEADER_LEN;
+
+/// Largest RTP payload that fits in a packet of `mtu` bytes.
+pub fn max_payload_for_mtu(mtu: usize) -> usize {
+    mtu.saturating_sub(OVERHEAD)
+}
+
+/// Splits RTP packets that do not fit the MTU. Splitting a packet adds
+/// packets to the stream, so the sequence numbers of all packets that
+/// follow are shifted to keep the stream contiguous.
+pub struct Repacketizer {
+    max_payload_len: usize,
+    seq_offset: u16,
+}
+
+impl Repacketizer {
+    pub fn new(mtu: usize) -> Self {
+        Self {
+            max_payload_len: max_payload_for_mtu(mtu),
+            seq_offset: 0,
+        }
+    }
+
+    /// Split the RTP packets among `bufs` that do not fit. RTCP packets
+    /// are passed through as they are.
+    pub fn repacketize(&mut self, bufs: Vec<video::RtpBuf>) -> Vec<video::RtpBuf> {
+        let mut repacketized = Vec::with_capacity(bufs.len());
+        for buf in bufs {
+            match buf {
+                video::RtpBuf::Rtp(mut buf) => match rtp::split_h264(&buf, self.max_payload_len) {
+                    Some(packets) => {
+                        // The first of the packets takes the place of the original packet,
+                        // the others shift all that follow.
+                        let added = packets.len() as u16 - 1;
+                        for mut packet in packets {
+                            self.shift_seq(&mut packet);
+                            repacketized.push(video::RtpBuf::Rtp(packet));
+                        }
+                        self.seq_offset = self.seq_offset.wrapping_add(added);
+                    }
+                    None => {
+                        self.shift_seq(&mut buf);
+                        repacketized.push(video::RtpBuf::Rtp(buf));
+                    }
+                },
+                rtcp => repacketized.push(rtcp),
+            }
+        }
+        repacketized
+    }
+
+    fn shift_seq(&self, buf: &mut [u8]) {
+        if let Some(seq) = rtp::seq(buf) {
+            rtp::set_seq(buf, seq.wrapping_add(self.seq_offset));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use video_rs as video;
+
+    use crate::session::rtp;
+
+    use super::{max_payload_for_mtu, Repacketizer};
+
+    /// RTP packets of a frame as the muxer produces them, with payloads of
+    /// at most 1012 bytes.
+    fn muxed_frame(nal: &[u8], seq: u16) -> Vec<video::RtpBuf> {
+        rtp::packetize_h264(nal, 96, seq, 9000, 0xdeadbeef, 1012)
+            .into_iter()
+            .map(video::RtpBuf::Rtp)
+            .collect()
+    }
+
+    fn rtp_packets(bufs: &[video::RtpBuf]) -> Vec<&[u8]> {
+        bufs.iter()
+            .filter_map(|buf| match buf {
+                video::RtpBuf::Rtp(buf) => Some(buf.as_slice()),
+                video::RtpBuf::Rtcp(_) => None,
+            })
+            .collect()
+    }
+
+    /// Reassemble the NAL unit from its FU-A fragments.
+    fn reassemble(packets: &[&[u8]]) -> Vec<u8> {
+        let mut nal = Vec::new();
+        for packet in packets {
+            let payload = rtp::payload(packet).unwrap();
+            if nal.is_empty() {
+                nal.push((payload[0] & 0xe0) | (payload[1] & 0x1f));
+            }
+            nal.extend(&payload[2..]);
+        }
+        nal
+    }
+
+    #[test]
+    fn small_mtu_splits_large_frame() {
+        let mut nal = vec![0x65];
+        nal.extend((0..9000).map(|i| (i % 251) as u8));
+        let muxed = muxed_frame(&nal, 100).len();
+        let mut repacketizer = Repacketizer::new(576);
+
+        let bufs = repacketizer.repacketize(muxed_frame(&nal, 100));
+        let packets = rtp_packets(&bufs);
+        assert!(packets.len() > muxed);
+        assert!(packets
+            .iter()
+            .all(|packet| rtp::payload(packet).unwrap().len() <= max_payload_for_mtu(576)));
+        for (i, packet) in packets.iter().enumerate() {
+            assert_eq!(rtp::seq(packet), Some(100 + i as u16));
+            assert_eq!(packet[1] & 0x80 != 0, i == packets.len() - 1);
+        }
+        assert_eq!(packets[0][13] & 0xc0, 0x80);
+        assert_eq!(packets[packets.len() - 1][13] & 0xc0, 0x40);
+        assert_eq!(reassemble(&packets), nal);
+
+        // Packets of the next frame are shifted to follow on.
+        let bufs = repacketizer.repacketize(muxed_frame(&[0x41, 0x00], 100 + muxed as u16));
+        assert_eq!(
+            rtp::seq(rtp_packets(&bufs)[0]),
+            Some(100 + packets.len() as u16)
+        );
+    }
+
+    #[test]
+    fn packets_that_fit_are_unchanged() {
+        let nal = [0x41; 500];
+        let mut repacketizer = Repacketizer::new(576);
+        let bufs = repacketizer.repacketize(muxed_frame(&nal, 7));
+        let packets = rtp_packets(&bufs);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(
+            packets[0],
+            rtp::packetize_h264(&nal, 96, 7, 9000, 0xdeadbeef, 1012)[0]
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! Minimal parsing of incoming (compound) RTCP packets (RFC 3550 sec-
+//! tion 6). We only parse as much as we need to learn about the state
+//! of the client. RTCP that we send is mostly produced by the muxer,
+//! except for the BYE we send when a session ends.
+
+pub const PACKET_TYPE_SENDER_REPORT: u8 = 200;
+pub const PACKET_TYPE_RECEIVER_REPORT: u8 = 201;
+pub const PACKET_TYPE_BYE: u8 = 203;
+
+const VERSION: u8 = 2;
+const HEADER_LEN: usize = 4;
+
+/// Single RTCP packet inside a compound RTCP packet.
+pub struct RtcpPacket<'buf> {
+    pub packet_type: u8,
+    /// Report count or source count, depending on the packet type.
+    pub count: u8,
+    /// Packet contents after the common header.
+    pub body: &'buf [u8],
+}
+
+impl RtcpPacket<'_> {
+    /// SSRC of the sender of this packet, for packet types that start
+    /// with one (sender and receiver reports, BYE).
+    pub fn ssrc(&self) -> Option<u32> {
+        match self.packet_type {
+            PACKET_TYPE_SENDER_REPORT | PACKET_TYPE_RECEIVER_REPORT => read_u32(self.body, 0),
+            PACKET_TYPE_BYE if self.count > 0 => read_u32(self.body, 0),
+            _ => None,
+        }
+    }
+
+    /// RTP timestamp that the NTP timestamp of a sender report corresponds
+    /// to, if this is a sender report.
+    pub fn sender_report_rtp_timestamp(&self) -> Option<u32> {
+        match self.packet_type {
+            PACKET_TYPE_SENDER_REPORT => read_u32(self.body, 12),
+            _ => None,
+        }
+    }
+
+    /// SSRCs that are leaving, if this is a BYE packet.
+    pub fn bye_ssrcs(&self) -> impl Iterator<Item = u32> + '_ {
+        let count = if self.packet_type == PACKET_TYPE_BYE {
+            self.count as usize
+        } else {
+            0
+        };
+        (0..count).filter_map(|i| read_u32(self.body, i * 4))
+    }
+}
+
+/// Iterate over the packets in a compound RTCP packet. Iteration stops
+/// at the first malformed packet.
+pub fn packets(buf: &[u8]) -> impl Iterator<Item = RtcpPacket<'_>> {
+    let mut rest = buf;
+    std::iter::from_fn(move || {
+        if rest.len() < HEADER_LEN || rest[0] >> 6 != VERSION {
+            return None;
+        }
+        let count = rest[0] & 0x1f;
+        let packet_type = rest[1];
+        let len = (u16::from_be_bytes([rest[2], rest[3]]) as usize + 1) * 4;
+        if rest.len() < len {
+            return None;
+        }
+        let packet = RtcpPacket {
+            packet_type,
+            count,
+            body: &rest[HEADER_LEN..len],
+        };
+        rest = &rest[len..];
+        Some(packet)
+    })
+}
+
+/// Build a compound RTCP packet announcing that `ssrc` is leaving. A
+/// compound packet must start with a report, so the BYE is preceded by
+/// a receiver report without report blocks.
+pub fn bye(ssrc: u32) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(2 * (HEADER_LEN + 4));
+    for (count, packet_type) in [(0, PACKET_TYPE_RECEIVER_REPORT), (1, PACKET_TYPE_BYE)] {
+        buf.push((VERSION << 6) | count);
+        buf.push(packet_type);
+        // Length in 32-bit words minus one.
+        buf.extend(1_u16.to_be_bytes());
+        buf.extend(ssrc.to_be_bytes());
+    }
+    buf
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    buf.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{bye, packets, PACKET_TYPE_BYE, PACKET_TYPE_RECEIVER_REPORT};
+
+    // Receiver report without report blocks followed by BYE, both from
+    // SSRC `0x01020304`.
+    const RR_AND_BYE: [u8; 16] = [
+        0x80, 201, 0x00, 0x01, 0x01, 0x02, 0x03, 0x04, //
+        0x81, 203, 0x00, 0x01, 0x01, 0x02, 0x03, 0x04, //
+    ];
+
+    #[test]
+    fn parse_compound_receiver_report_and_bye() {
+        let parsed = packets(&RR_AND_BYE).collect::<Vec<_>>();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].packet_type, PACKET_TYPE_RECEIVER_REPORT);
+        assert_eq!(parsed[0].ssrc(), Some(0x01020304));
+        assert_eq!(parsed[0].bye_ssrcs().count(), 0);
+        assert_eq!(parsed[1].packet_type, PACKET_TYPE_BYE);
+        assert_eq!(parsed[1].bye_ssrcs().collect::<Vec<_>>(), vec![0x01020304]);
+    }
+
+    #[test]
+    fn build_bye() {
+        assert_eq!(bye(0x01020304), RR_AND_BYE);
+    }
+
+    #[test]
+    fn parse_truncated() {
+        assert_eq!(packets(&RR_AND_BYE[..12]).count(), 1);
+    }
+
+    #[test]
+    fn parse_invalid_version() {
+        assert_eq!(packets(&[0x00, 201, 0x00, 0x00]).count(), 0);
+    }
+}
@@ -0,0 +1,325 @@
+//! Helpers for inspecting RTP packets (RFC 3550 section 5.1).
+
+const VERSION: u8 = 2;
+pub const HEADER_LEN: usize = 12;
+
+const MARKER: u8 = 0x80;
+const H264_NAL_UNIT_TYPE_STAP_A: u8 = 24;
+const H264_NAL_UNIT_TYPE_FU_A: u8 = 28;
+const H264_FU_START: u8 = 0x80;
+const H264_FU_END: u8 = 0x40;
+
+/// Read the SSRC from an RTP packet header.
+pub fn ssrc(buf: &[u8]) -> Option<u32> {
+    read_header_u32(buf, 8)
+}
+
+/// Read the sequence number from an RTP packet header.
+pub fn seq(buf: &[u8]) -> Option<u16> {
+    if buf.len() >= HEADER_LEN && buf[0] >> 6 == VERSION {
+        Some(u16::from_be_bytes([buf[2], buf[3]]))
+    } else {
+        None
+    }
+}
+
+/// Read the RTP timestamp from an RTP packet header.
+pub fn timestamp(buf: &[u8]) -> Option<u32> {
+    read_header_u32(buf, 4)
+}
+
+/// Read the payload type from an RTP packet header.
+pub fn payload_type(buf: &[u8]) -> Option<u8> {
+    if buf.len() >= HEADER_LEN && buf[0] >> 6 == VERSION {
+        Some(buf[1] & 0x7f)
+    } else {
+        None
+    }
+}
+
+/// Payload of an RTP packet, after the header, CSRC list and header ex-
+/// tension.
+pub fn payload(buf: &[u8]) -> Option<&[u8]> {
+    if buf.len() < HEADER_LEN || buf[0] >> 6 != VERSION {
+        return None;
+    }
+    let csrc_count = (buf[0] & 0x0f) as usize;
+    let mut offset = HEADER_LEN + csrc_count * 4;
+    if buf[0] & 0x10 != 0 {
+        let extension = buf.get(offset..offset + 4)?;
+        let extension_len = u16::from_be_bytes([extension[2], extension[3]]) as usize;
+        offset += 4 + extension_len * 4;
+    }
+    buf.get(offset..)
+}
+
+/// Whether an RTP packet carries (part of) an H.264 NAL unit that no other
+/// picture refers to, such as a non-reference B-frame. Such packets can be
+/// dropped with the least visual impact.
+///
+/// For every packetization (single NAL unit, STAP-A and FU-A) the NRI
+/// bits of the first payload byte are zero only if the NAL unit(s) are
+/// disposable (RFC 6184 section 5.3).
+pub fn is_h264_disposable(buf: &[u8]) -> bool {
+    match payload(buf) {
+        Some(payload) => payload.first().map(|b| b & 0x60 == 0).unwrap_or(false),
+        None => false,
+    }
+}
+
+/// Overwrite the sequence number in an RTP packet header.
+pub fn set_seq(buf: &mut [u8], seq: u16) {
+    if buf.len() >= HEADER_LEN {
+        buf[2..4].copy_from_slice(&seq.to_be_bytes());
+    }
+}
+
+/// Packetize a single H.264 NAL unit (RFC 6184). The NAL unit is sent as
+/// is if it fits in `max_payload_len`, and fragmented into FU-A packets
+/// otherwise. The marker bit is set on the last packet and sequence num-
+/// bers count up from `seq`.
+pub fn packetize_h264(
+    nal: &[u8],
+    payload_type: u8,
+    seq: u16,
+    timestamp: u32,
+    ssrc: u32,
+    max_payload_len: usize,
+) -> Vec<Vec<u8>> {
+    let new_packet = |seq: u16, marker: bool| {
+        header(payload_type, seq, timestamp, ssrc, marker, max_payload_len)
+    };
+
+    if nal.len() <= max_payload_len {
+        let mut packet = new_packet(seq, true);
+        packet.extend(nal);
+        return vec![packet];
+    }
+
+    let (nal_header, nal_payload) = match nal.split_first() {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+    let fu_indicator = (nal_header & 0xe0) | H264_NAL_UNIT_TYPE_FU_A;
+    let fragments = nal_payload.chunks(max_payload_len.saturating_sub(2).max(1));
+    let last = fragments.len() - 1;
+    fragments
+        .enumerate()
+        .map(|(i, fragment)| {
+            let mut fu_header = nal_header & 0x1f;
+            if i == 0 {
+                fu_header |= H264_FU_START;
+            }
+            if i == last {
+                fu_header |= H264_FU_END;
+            }
+            let mut packet = new_packet(seq.wrapping_add(i as u16), i == last);
+            packet.push(fu_indicator);
+            packet.push(fu_header);
+            packet.extend(fragment);
+            packet
+        })
+        .collect()
+}
+
+/// Split an H.264 RTP packet with a payload larger than `max_payload_len`
+/// into packets that fit (RFC 6184). Single NAL units and the NAL units of
+/// STAP-A packets are packetized with [`packetize_h264`], FU-A fragments
+/// are split into smaller fragments. Sequence numbers count up from that
+/// of the packet, and only the last packet keeps its marker bit. Header
+/// extensions and CSRCs are not carried over.
+///
+/// Returns `None` if the packet fits already, or if it is not an H.264
+/// packet we know how to split, in which case it is best sent as is.
+pub fn split_h264(buf: &[u8], max_payload_len: usize) -> Option<Vec<Vec<u8>>> {
+    let payload = payload(buf)?;
+    if payload.len() <= max_payload_len {
+        return None;
+    }
+    let payload_type = payload_type(buf)?;
+    let seq = seq(buf)?;
+    let timestamp = timestamp(buf)?;
+    let ssrc = ssrc(buf)?;
+    let marker = buf[1] & MARKER != 0;
+
+    let mut packets = match payload[0] & 0x1f {
+        1..=23 => packetize_h264(payload, payload_type, seq, timestamp, ssrc, max_payload_len),
+        H264_NAL_UNIT_TYPE_STAP_A => {
+            let mut packets = Vec::new();
+            let mut aggregated = &payload[1..];
+            while aggregated.len() >= 2 {
+                let len = u16::from_be_bytes([aggregated[0], aggregated[1]]) as usize;
+                let nal = aggregated.get(2..2 + len)?;
+                packets.extend(packetize_h264(
+                    nal,
+                    payload_type,
+                    seq.wrapping_add(packets.len() as u16),
+                    timestamp,
+                    ssrc,
+                    max_payload_len,
+                ));
+                aggregated = &aggregated[2 + len..];
+            }
+            packets
+        }
+        H264_NAL_UNIT_TYPE_FU_A => {
+            let fu_indicator = payload[0];
+            let fu_header = *payload.get(1)?;
+            let fragments = payload[2..].chunks(max_payload_len.saturating_sub(2).max(1));
+            let last = fragments.len().checked_sub(1)?;
+            fragments
+                .enumerate()
+                .map(|(i, fragment)| {
+                    // Only the first and last of the smaller fragments keep the start and end
+                    // bits of the original fragment.
+                    let mut header_byte = fu_header & 0x1f;
+                    if i == 0 {
+                        header_byte |= fu_header & H264_FU_START;
+                    }
+                    if i == last {
+                        header_byte |= fu_header & H264_FU_END;
+                    }
+                    let mut packet = header(
+                        payload_type,
+                        seq.wrapping_add(i as u16),
+                        timestamp,
+                        ssrc,
+                        false,
+                        max_payload_len,
+                    );
+                    packet.push(fu_indicator);
+                    packet.push(header_byte);
+                    packet.extend(fragment);
+                    packet
+                })
+                .collect()
+        }
+        _ => return None,
+    };
+    if packets.is_empty() {
+        return None;
+    }
+
+    for packet in &mut packets {
+        packet[1] &= !MARKER;
+    }
+    if marker {
+        if let Some(last) = packets.last_mut() {
+            last[1] |= MARKER;
+        }
+    }
+    Some(packets)
+}
+
+/// Fixed RTP header without CSRCs or header extension, with room for a
+/// payload of `payload_capacity` bytes.
+fn header(
+    payload_type: u8,
+    seq: u16,
+    timestamp: u32,
+    ssrc: u32,
+    marker: bool,
+    payload_capacity: usize,
+) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_LEN + payload_capacity);
+    packet.push(VERSION << 6);
+    packet.push(if marker { MARKER } else { 0 } | payload_type);
+    packet.extend(seq.to_be_bytes());
+    packet.extend(timestamp.to_be_bytes());
+    packet.extend(ssrc.to_be_bytes());
+    packet
+}
+
+fn read_header_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    if buf.len() >= HEADER_LEN && buf[0] >> 6 == VERSION {
+        Some(u32::from_be_bytes([
+            buf[offset],
+            buf[offset + 1],
+            buf[offset + 2],
+            buf[offset + 3],
+        ]))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{is_h264_disposable, packetize_h264, payload, payload_type, ssrc, timestamp};
+
+    #[test]
+    fn parse_ssrc() {
+        let packet = [
+            0x80, 96, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0xde, 0xad, 0xbe, 0xef, 0x00,
+        ];
+        assert_eq!(ssrc(&packet), Some(0xdeadbeef));
+    }
+
+    #[test]
+    fn parse_timestamp() {
+        let packet = [
+            0x80, 96, 0x00, 0x01, 0x00, 0x01, 0x02, 0x03, 0xde, 0xad, 0xbe, 0xef,
+        ];
+        assert_eq!(timestamp(&packet), Some(0x00010203));
+    }
+
+    #[test]
+    fn parse_ssrc_truncated() {
+        assert_eq!(ssrc(&[0x80, 96, 0x00, 0x01]), None);
+    }
+
+    #[test]
+    fn parse_payload_with_csrc_and_extension() {
+        let packet = [
+            0x91, 96, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // header
+            0x00, 0x00, 0x00, 0x02, // csrc
+            0xbe, 0xde, 0x00, 0x01, 0x10, 0xff, 0x00, 0x00, // extension
+            0x01, 0x9a, // payload
+        ];
+        assert_eq!(payload(&packet), Some(&[0x01, 0x9a][..]));
+        assert!(is_h264_disposable(&packet));
+    }
+
+    #[test]
+    fn h264_reference_frames_are_not_disposable() {
+        let idr = packetize_h264(&[0x65, 0x88], 96, 0, 0, 1, 1400);
+        let fu_a = packetize_h264(&[0x41, 0x9a, 0x01, 0x02], 96, 0, 0, 1, 3);
+        let b = packetize_h264(&[0x01, 0x9e], 96, 0, 0, 1, 1400);
+        assert!(!is_h264_disposable(&idr[0]));
+        assert!(fu_a.iter().all(|packet| !is_h264_disposable(packet)));
+        assert!(is_h264_disposable(&b[0]));
+    }
+
+    #[test]
+    fn packetize_h264_single_nal_unit() {
+        let packets = packetize_h264(&[0x65, 0x88, 0x84], 96, 10, 3000, 0xdeadbeef, 1400);
+        assert_eq!(
+            packets,
+            vec![vec![
+                0x80, 0xe0, 0x00, 0x0a, 0x00, 0x00, 0x0b, 0xb8, 0xde, 0xad, 0xbe, 0xef, 0x65, 0x88,
+                0x84,
+            ]],
+        );
+        assert_eq!(payload_type(&packets[0]), Some(96));
+        assert_eq!(timestamp(&packets[0]), Some(3000));
+    }
+
+    #[test]
+    fn packetize_h264_fu_a() {
+        let packets = packetize_h264(&[0x65, 0x01, 0x02, 0x03, 0x04], 96, 0xffff, 0, 1, 4);
+        assert_eq!(
+            packets,
+            vec![
+                vec![
+                    0x80, 0x60, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x7c,
+                    0x85, 0x01, 0x02,
+                ],
+                vec![
+                    0x80, 0xe0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x7c,
+                    0x45, 0x03, 0x04,
+                ],
+            ],
+        );
+    }
+}
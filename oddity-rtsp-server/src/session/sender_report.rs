@@ -0,0 +1,183 @@
+//! Periodic RTCP sender reports (RFC 3550 section 6.4.1). The muxer sends
+//! sender reports of its own accord, but only as it produces packets. A
+//! session generates additional reports on a fixed interval, so that
+//! clients can synchronize their clocks regardless of the stream.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::time::Instant;
+
+use crate::session::{rtcp, rtp};
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970).
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Interval between sender reports unless configured otherwise.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Minimum interval between RTCP reports of RFC 3550 section 6.2. Shorter
+/// intervals are fine for unicast sessions with a single receiver, but
+/// may flood multicast groups with many of them.
+pub const RFC_3550_MIN_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct SenderReports {
+    payload_type: u8,
+    clock_rate: u32,
+    /// Last RTP packet sent: its SSRC and timestamp, and when it was sent.
+    last: Option<(u32, u32, Instant)>,
+    packet_count: u32,
+    octet_count: u32,
+}
+
+impl SenderReports {
+    /// Generate reports for the RTP stream with the given payload type,
+    /// of which the timestamps run at `clock_rate`.
+    pub fn new(payload_type: u8, clock_rate: u32) -> Self {
+        Self {
+            payload_type,
+            clock_rate,
+            last: None,
+            packet_count: 0,
+            octet_count: 0,
+        }
+    }
+
+    /// Generate reports for the first stream in the SDP of the muxer.
+    /// Returns `None` if the SDP does not describe the clock rate of any
+    /// stream.
+    pub fn from_sdp(sdp: &str) -> Option<Self> {
+        let (payload_type, clock_rate) = sdp.lines().find_map(parse_rtpmap)?;
+        Some(Self::new(payload_type, clock_rate))
+    }
+
+    /// Keep track of an RTP packet that was sent at `sent_at`. Packets of
+    /// other streams are ignored.
+    pub fn record(&mut self, buf: &[u8], sent_at: Instant) {
+        if rtp::payload_type(buf) != Some(self.payload_type) {
+            return;
+        }
+        let (ssrc, timestamp) = match (rtp::ssrc(buf), rtp::timestamp(buf)) {
+            (Some(ssrc), Some(timestamp)) => (ssrc, timestamp),
+            _ => return,
+        };
+        // The SSRC changes when the muxer is reinitialized, which starts a
+        // new stream as far as the counts are concerned.
+        if self.last.is_some_and(|(last_ssrc, _, _)| last_ssrc != ssrc) {
+            self.packet_count = 0;
+            self.octet_count = 0;
+        }
+        self.last = Some((ssrc, timestamp, sent_at));
+        self.packet_count = self.packet_count.wrapping_add(1);
+        self.octet_count = self
+            .octet_count
+            .wrapping_add(rtp::payload(buf).map(<[u8]>::len).unwrap_or(0) as u32);
+    }
+
+    /// Generate a sender report for the current time. The RTP timestamp
+    /// is extrapolated from the last packet that was sent, so that it
+    /// matches the NTP timestamp. Returns `None` if no RTP was sent yet.
+    pub fn report(&self, now: Instant, wallclock: SystemTime) -> Option<Vec<u8>> {
+        let (ssrc, last_timestamp, last_sent_at) = self.last?;
+        let elapsed = now.saturating_duration_since(last_sent_at).as_secs_f64();
+        let rtp_timestamp =
+            last_timestamp.wrapping_add((elapsed * self.clock_rate as f64).round() as u32);
+        let ntp_timestamp = ntp_timestamp(wallclock);
+
+        let mut report = Vec::with_capacity(28);
+        report.push(2 << 6);
+        report.push(rtcp::PACKET_TYPE_SENDER_REPORT);
+        // Length in 32-bit words minus one.
+        report.extend(6_u16.to_be_bytes());
+        report.extend(ssrc.to_be_bytes());
+        report.extend(ntp_timestamp.to_be_bytes());
+        report.extend(rtp_timestamp.to_be_bytes());
+        report.extend(self.packet_count.to_be_bytes());
+        report.extend(self.octet_count.to_be_bytes());
+        Some(report)
+    }
+}
+
+/// Convert wallclock time to a 64-bit NTP timestamp: seconds since 1900
+/// in the upper half, and the fraction of a second in the lower half.
+fn ntp_timestamp(wallclock: SystemTime) -> u64 {
+    let since_unix_epoch = wallclock.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_unix_epoch.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = ((since_unix_epoch.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    (secs << 32) | fraction
+}
+
+/// Parse the payload type and clock rate from an SDP `rtpmap` attribute
+/// such as `a=rtpmap:96 H264/90000`.
+fn parse_rtpmap(line: &str) -> Option<(u8, u32)> {
+    let (payload_type, encoding) = line.trim().strip_prefix("a=rtpmap:")?.split_once(' ')?;
+    let clock_rate = encoding.split('/').nth(1)?;
+    Some((payload_type.parse().ok()?, clock_rate.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use tokio::time::Instant;
+
+    use crate::session::rtcp;
+
+    use super::{parse_rtpmap, SenderReports};
+
+    fn rtp_packet(payload_type: u8, ssrc: u32, timestamp: u32, payload_len: usize) -> Vec<u8> {
+        let mut packet = vec![0x80, payload_type, 0x00, 0x01];
+        packet.extend(timestamp.to_be_bytes());
+        packet.extend(ssrc.to_be_bytes());
+        packet.extend(vec![0; payload_len]);
+        packet
+    }
+
+    #[test]
+    fn parse_rtpmap_h264() {
+        assert_eq!(parse_rtpmap("a=rtpmap:96 H264/90000"), Some((96, 90000)));
+        assert_eq!(parse_rtpmap("a=fmtp:96 packetization-mode=1"), None);
+    }
+
+    #[test]
+    fn report_requires_rtp() {
+        let reports = SenderReports::new(96, 90000);
+        assert!(reports.report(Instant::now(), UNIX_EPOCH).is_none());
+    }
+
+    #[test]
+    fn report_extrapolates_rtp_timestamp() {
+        let sent_at = Instant::now();
+        let mut reports = SenderReports::new(96, 90000);
+        reports.record(&rtp_packet(96, 0xaabbccdd, 1000, 100), sent_at);
+        reports.record(&rtp_packet(96, 0xaabbccdd, 4000, 50), sent_at);
+        // Packets of other streams do not count.
+        reports.record(&rtp_packet(97, 0x01020304, 0, 10), sent_at);
+
+        let wallclock = UNIX_EPOCH + Duration::from_millis(1500);
+        let report = reports
+            .report(sent_at + Duration::from_secs(2), wallclock)
+            .unwrap();
+        let packet = rtcp::packets(&report).next().unwrap();
+        assert_eq!(packet.packet_type, rtcp::PACKET_TYPE_SENDER_REPORT);
+        assert_eq!(packet.ssrc(), Some(0xaabbccdd));
+        assert_eq!(packet.sender_report_rtp_timestamp(), Some(4000 + 2 * 90000));
+        assert_eq!(
+            report[8..16],
+            ((2_208_988_801_u64 << 32) | (1 << 31)).to_be_bytes(),
+        );
+        assert_eq!(report[20..24], 2_u32.to_be_bytes());
+        assert_eq!(report[24..28], 150_u32.to_be_bytes());
+    }
+
+    #[test]
+    fn counts_restart_with_new_ssrc() {
+        let sent_at = Instant::now();
+        let mut reports = SenderReports::new(96, 90000);
+        reports.record(&rtp_packet(96, 1, 0, 100), sent_at);
+        reports.record(&rtp_packet(96, 2, 0, 10), sent_at);
+        let report = reports.report(sent_at, UNIX_EPOCH).unwrap();
+        assert_eq!(report[20..24], 1_u32.to_be_bytes());
+        assert_eq!(report[24..28], 10_u32.to_be_bytes());
+    }
+}
@@ -1,36 +1,104 @@
-use std::collections::{hash_map::Entry, HashMap};
+use std::collections::{HashMap, HashSet};
 use std::error;
 use std::fmt;
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use tokio::select;
 use tokio::sync::mpsc;
 use tokio::sync::{Mutex, RwLock};
+use tokio::task;
+use tokio::time::{self, Instant};
+
+use tracing::Instrument;
 
 use oddity_rtsp_protocol as rtsp;
 
 use crate::media;
 use crate::runtime::task_manager::{Task, TaskContext};
 use crate::runtime::Runtime;
+use crate::session::admission::{Admission, AdmissionError, AlwaysAdmit};
+use crate::session::client_limit::{ClientLimits, SetupHistory};
+use crate::session::memory::MemoryBudget;
+use crate::session::observer::SessionObserver;
 use crate::session::setup::SessionSetup;
+use crate::session::stats::{SessionCounters, SessionStats};
+use crate::session::udp::BindError;
 use crate::session::{
-    PlaySessionError, Session, SessionId, SessionState, SessionStateRx, SessionStateTx,
+    PlaySessionError, Session, SessionControlError, SessionId, SessionIdGenerator, SessionInfo,
+    SessionKeepAlive, SessionSsrcRx, SessionState, SessionStateRx, SessionStateTx,
+    SessionTransport, StopReason,
 };
-use crate::source::SourceDelegate;
+use crate::source::{self, SourceDelegate, SourcePath, SourcePathRef};
 
 type SessionShared = Arc<Mutex<Session>>;
-type SessionMap = Arc<RwLock<HashMap<SessionId, SessionShared>>>;
+type SessionMap = Arc<RwLock<HashMap<SessionId, SessionEntry>>>;
+type ReservationMap = Arc<Mutex<HashMap<SessionId, Reservation>>>;
+
+#[derive(Clone)]
+struct SessionEntry {
+    session: SessionShared,
+    keep_alive: SessionKeepAlive,
+    abort: Option<task::AbortHandle>,
+    info: SessionInfo,
+    client_ip: Option<IpAddr>,
+    ssrc_rx: SessionSsrcRx,
+    counters: Arc<SessionCounters>,
+    /// Number of clients that share the session. Only multicast sessions
+    /// are shared: clients that ask for multicast delivery of the same
+    /// source join the session that already sends to the group.
+    members: usize,
+}
+
+/// Session that is being started. It counts towards the limits as if it
+/// were registered already, so that the sessions need not be locked while
+/// it starts.
+struct Reservation {
+    source_path: SourcePath,
+    client_ip: Option<IpAddr>,
+    transport: SessionTransport,
+    members: usize,
+    /// Whether the session stopped before it was registered.
+    stopped: bool,
+}
+
+/// Outcome of reserving a session for a setup.
+enum Reserved {
+    /// A new session is to be started under this ID.
+    New(SessionId),
+    /// The client joined the multicast session with this ID.
+    Joined(SessionId),
+}
 
 pub struct SessionManager {
     sessions: SessionMap,
+    reservations: ReservationMap,
     session_state_tx: SessionStateTx,
+    admission: Box<dyn Admission>,
+    memory: MemoryBudget,
+    max_sessions: Option<usize>,
+    client_limits: ClientLimits,
+    setup_history: Mutex<SetupHistory>,
+    session_ids: Box<dyn SessionIdGenerator>,
+    observer: Option<Arc<dyn SessionObserver>>,
+    /// Sources that refuse new sessions, and the task of each that tears
+    /// down its sessions once the deadline has passed.
+    draining: Mutex<HashMap<SourcePath, Task>>,
     worker: Task,
     runtime: Arc<Runtime>,
 }
 
 impl SessionManager {
-    pub async fn start(runtime: Arc<Runtime>) -> Self {
+    /// Time that sessions get to stop when the manager stops, unless con-
+    /// figured otherwise.
+    pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Start the session manager. The `observer`, if any, is told about
+    /// sessions starting, playing and stopping, see [`SessionObserver`].
+    pub async fn start(runtime: Arc<Runtime>, observer: Option<Arc<dyn SessionObserver>>) -> Self {
         let sessions = Arc::new(RwLock::new(HashMap::new()));
+        let reservations = Arc::new(Mutex::new(HashMap::new()));
         let (session_state_tx, session_state_rx) = mpsc::unbounded_channel();
 
         tracing::trace!("starting session manager");
@@ -38,26 +106,119 @@ impl SessionManager {
             .task()
             .spawn({
                 let sessions = sessions.clone();
-                move |task_context| Self::run(sessions.clone(), session_state_rx, task_context)
+                let reservations = reservations.clone();
+                let observer = observer.clone();
+                move |task_context| {
+                    Self::run(
+                        sessions.clone(),
+                        reservations.clone(),
+                        observer,
+                        session_state_rx,
+                        task_context,
+                    )
+                    .instrument(tracing::info_span!("session_manager"))
+                }
             })
             .await;
         tracing::trace!("started session manager");
 
         Self {
             sessions,
+            reservations,
             session_state_tx,
+            admission: Box::new(AlwaysAdmit),
+            memory: MemoryBudget::default(),
+            max_sessions: None,
+            client_limits: ClientLimits::default(),
+            setup_history: Mutex::new(SetupHistory::default()),
+            session_ids: Box::new(SessionId::generate),
+            observer,
+            draining: Mutex::new(HashMap::new()),
             runtime,
             worker,
         }
     }
 
-    pub async fn stop(&mut self) {
+    /// Limit the total number of bytes that sessions may buffer. When the
+    /// limit is reached, new sessions are refused and existing sessions
+    /// drop disposable frames. Unlimited if `None`.
+    pub fn with_memory_limit(mut self, limit: Option<usize>) -> Self {
+        self.memory = MemoryBudget::new(limit);
+        self
+    }
+
+    /// Limit the number of sessions that may exist at the same time. Un-
+    /// limited if `None`.
+    pub fn with_max_sessions(mut self, max_sessions: Option<usize>) -> Self {
+        self.max_sessions = max_sessions;
+        self
+    }
+
+    /// Limit the number of sessions that each client may have, and how
+    /// many it may set up within a while. Sessions of a client count to-
+    /// wards the limits until they stop. Clients are not limited by de-
+    /// fault.
+    pub fn with_client_limits(mut self, client_limits: ClientLimits) -> Self {
+        self.client_limits = client_limits;
+        self
+    }
+
+    /// Replace the admission policy that is consulted before setting up
+    /// new sessions. By default, all sessions are admitted.
+    pub fn with_admission(mut self, admission: impl Admission + 'static) -> Self {
+        self.admission = Box::new(admission);
+        self
+    }
+
+    /// Replace the generator of the IDs of new sessions, for example to
+    /// configure their length with a [`SessionIdConfig`].
+    ///
+    /// [`SessionIdConfig`]: crate::session::SessionIdConfig
+    pub fn with_session_ids(mut self, session_ids: impl SessionIdGenerator + 'static) -> Self {
+        self.session_ids = Box::new(session_ids);
+        self
+    }
+
+    /// Stop the manager and tear down all sessions that are left. Sessions
+    /// are torn down concurrently. Those that have not stopped by the time
+    /// `drain_timeout` has passed are aborted, so that a session that hangs
+    /// cannot hold up shutting down the server.
+    pub async fn stop(&mut self, drain_timeout: Duration) {
         tracing::trace!("sending stop signal to session manager");
         self.worker.stop().await;
-        tracing::trace!("session manager stopped");
-        for (_, session) in self.sessions.write().await.drain() {
-            session.lock().await.teardown().await;
+        for (_, mut drain) in self.draining.lock().await.drain() {
+            drain.stop().await;
         }
+        tracing::trace!("session manager stopped");
+        // The worker no longer reports sessions that stop, so we report the
+        // ones that were left ourselves.
+        let entries = self.sessions.write().await.drain().collect::<Vec<_>>();
+        let deadline = Instant::now() + drain_timeout;
+        let observer = self.observer.as_deref();
+        let teardowns = entries.into_iter().map(|(session_id, entry)| async move {
+            let drained = time::timeout_at(deadline, async {
+                entry.session.lock().await.teardown().await;
+            })
+            .await
+            .is_ok();
+            if !drained {
+                tracing::warn!(%session_id, "session did not stop in time, aborting");
+                if let Some(abort) = entry.abort {
+                    abort.abort();
+                }
+            }
+            if let Some(observer) = observer {
+                observer.on_stopped(&session_id, StopReason::Teardown);
+            }
+            drained
+        });
+        let drained = futures::future::join_all(teardowns).await;
+        let aborted = drained.iter().filter(|drained| !**drained).count();
+        tracing::info!(
+            drained = drained.len() - aborted,
+            aborted,
+            "tore down remaining sessions",
+        );
     }
 
     pub async fn setup(
@@ -65,24 +226,245 @@ impl SessionManager {
         source_delegate: SourceDelegate,
         setup: SessionSetup,
     ) -> Result<SessionId, RegisterSessionError> {
-        let session_id = SessionId::generate();
-        let session = Session::setup_and_start(
+        let source_path = source_delegate.path().to_string();
+        let max_viewers = source_delegate.max_viewers();
+
+        if let Err(err) = self.admission.admit(&source_path, &setup).await {
+            tracing::debug!(%source_path, %err, "session not admitted");
+            return Err(RegisterSessionError::NotAdmitted(err));
+        }
+
+        if self.memory.is_exhausted() {
+            tracing::warn!(
+                buffered_bytes = self.memory.total(),
+                "sessions buffer too much memory, refusing new session",
+            );
+            return Err(RegisterSessionError::MemoryExhausted);
+        }
+
+        // Hold the write lock from checking the number of sessions and vi-
+        // ewers until the session is reserved so that concurrent setups
+        // cannot both slip past the limits. The session is started after
+        // the lock is released, so that starting it does not hold up the
+        // other sessions, and counts towards the limits until then.
+        let mut sessions = self.sessions.write().await;
+        let mut reservations = self.reservations.lock().await;
+
+        // Earlier sessions of the same client, for example when it recon-
+        // nected before its previous session timed out, are evicted once the
+        // new session is registered. Until then they do not count towards
+        // the limits, and if the new session fails they are kept.
+        let evicted_ids = match &setup.client_identity {
+            Some(client_identity) => sessions
+                .iter()
+                .filter(|(_, entry)| entry.info.client_identity.as_ref() == Some(client_identity))
+                .map(|(id, _)| id.clone())
+                .collect::<HashSet<_>>(),
+            None => HashSet::new(),
+        };
+        let kept = |id: &SessionId| !evicted_ids.contains(id);
+
+        // Clients that join a multicast session are held to the same limits
+        // as clients that set up a session of their own: they are viewers
+        // of the source all the same.
+        let reserved = 'reserve: {
+            if self.draining.lock().await.contains_key(&source_path) {
+                tracing::debug!(%source_path, "source is draining");
+                break 'reserve Err(RegisterSessionError::SourceDraining);
+            }
+
+            let num_sessions = sessions.len() - evicted_ids.len() + reservations.len();
+            if let Err(err) = check_capacity(num_sessions, self.max_sessions) {
+                tracing::warn!(
+                    sessions = num_sessions,
+                    "too many sessions, refusing new session"
+                );
+                break 'reserve Err(err);
+            }
+
+            if let Some(max_viewers) = max_viewers {
+                let viewers = sessions
+                    .iter()
+                    .filter(|(id, entry)| kept(id) && entry.info.source_path == source_path)
+                    .map(|(_, entry)| entry.members)
+                    .chain(
+                        reservations
+                            .values()
+                            .filter(|reservation| reservation.source_path == source_path)
+                            .map(|reservation| reservation.members),
+                    )
+                    .sum::<usize>();
+                if viewers >= max_viewers {
+                    tracing::debug!(%source_path, viewers, max_viewers, "source has too many viewers");
+                    break 'reserve Err(RegisterSessionError::TooManyViewers);
+                }
+            }
+
+            if let Some(client_ip) = setup.client_ip {
+                let client_sessions = sessions
+                    .iter()
+                    .filter(|(id, entry)| kept(id) && entry.client_ip == Some(client_ip))
+                    .count()
+                    + reservations
+                        .values()
+                        .filter(|reservation| reservation.client_ip == Some(client_ip))
+                        .count();
+                if let Err(err) = check_client_limits(
+                    &self.client_limits,
+                    &mut *self.setup_history.lock().await,
+                    client_ip,
+                    client_sessions,
+                    Instant::now(),
+                ) {
+                    tracing::debug!(%client_ip, client_sessions, "client is rate limited");
+                    break 'reserve Err(err);
+                }
+            }
+
+            let transport = SessionTransport::from(&setup.rtp_target);
+            if let SessionTransport::UdpMulticast { .. } = transport {
+                let joined = sessions.iter_mut().find(|(id, entry)| {
+                    kept(id)
+                        && entry.info.source_path == source_path
+                        && entry.info.transport == transport
+                });
+                if let Some((session_id, entry)) = joined {
+                    entry.members += 1;
+                    tracing::trace!(%session_id, members = entry.members, "joined multicast session");
+                    break 'reserve Ok(Reserved::Joined(session_id.clone()));
+                }
+                // A session that sends to the group may still be starting.
+                // If it fails to start, the clients that joined it find out
+                // when they try to play it.
+                let joined = reservations.iter_mut().find(|(_, reservation)| {
+                    reservation.source_path == source_path && reservation.transport == transport
+                });
+                if let Some((session_id, reservation)) = joined {
+                    reservation.members += 1;
+                    tracing::trace!(
+                        %session_id, members = reservation.members,
+                        "joined multicast session that is starting",
+                    );
+                    break 'reserve Ok(Reserved::Joined(session_id.clone()));
+                }
+            }
+
+            let session_id = match generate_unique_id(self.session_ids.as_ref(), |id| {
+                sessions.contains_key(id) || reservations.contains_key(id)
+            }) {
+                Some(session_id) => session_id,
+                None => {
+                    tracing::error!("failed to generate unique session id");
+                    break 'reserve Err(RegisterSessionError::NoUniqueId);
+                }
+            };
+            reservations.insert(
+                session_id.clone(),
+                Reservation {
+                    source_path: source_path.clone(),
+                    client_ip: setup.client_ip,
+                    transport,
+                    members: 1,
+                    stopped: false,
+                },
+            );
+            Ok(Reserved::New(session_id))
+        };
+        drop(reservations);
+        drop(sessions);
+
+        let result = match reserved {
+            Ok(Reserved::New(session_id)) => {
+                self.start_reserved(session_id, source_path, source_delegate, setup)
+                    .await
+            }
+            Ok(Reserved::Joined(session_id)) => Ok(session_id),
+            Err(err) => Err(err),
+        };
+
+        // The evicted sessions are torn down after the lock is released.
+        let evicted = match &result {
+            Ok(_) => {
+                let mut sessions = self.sessions.write().await;
+                evicted_ids
+                    .into_iter()
+                    .filter_map(|id| sessions.remove(&id).map(|entry| (id, entry.session)))
+                    .collect::<Vec<_>>()
+            }
+            Err(_) => Vec::new(),
+        };
+
+        for (session_id, session) in evicted {
+            tracing::info!(%session_id, "evicting session of reconnected client");
+            session.lock().await.stop(StopReason::Evicted).await;
+        }
+
+        result
+    }
+
+    /// Start the session that was reserved as `session_id`, and register it
+    /// in place of the reservation. The reservation is released if the
+    /// session fails to start.
+    async fn start_reserved(
+        &self,
+        session_id: SessionId,
+        source_path: SourcePath,
+        source_delegate: SourceDelegate,
+        setup: SessionSetup,
+    ) -> Result<SessionId, RegisterSessionError> {
+        let client_ip = setup.client_ip;
+        let info = SessionInfo {
+            id: session_id.clone(),
+            source_path,
+            sdp: setup.sdp.clone(),
+            client_identity: setup.client_identity.clone(),
+            transport: SessionTransport::from(&setup.rtp_target),
+            created: SystemTime::now(),
+        };
+        let started = Session::setup_and_start(
             session_id.clone(),
             source_delegate,
             setup,
+            self.memory.account(),
             self.session_state_tx.clone(),
             self.runtime.as_ref(),
         )
         .await;
 
-        if let Entry::Vacant(entry) = self.sessions.write().await.entry(session_id.clone()) {
-            let _ = entry.insert(Arc::new(Mutex::new(session)));
-            tracing::trace!(%session_id, "registered new session");
-            Ok(session_id)
-        } else {
-            tracing::error!(%session_id, "session with this ID already exists");
-            Err(RegisterSessionError::AlreadyRegistered)
-        }
+        let mut sessions = self.sessions.write().await;
+        let reservation = self.reservations.lock().await.remove(&session_id);
+        let session = match started {
+            Ok(session) => session,
+            Err(err) => {
+                tracing::warn!(%session_id, %err, "failed to start session");
+                return Err(RegisterSessionError::Bind(err));
+            }
+        };
+        let members = match reservation {
+            // The session stopped before it could be registered, and the
+            // observer was already told.
+            Some(Reservation { stopped: true, .. }) => {
+                tracing::debug!(%session_id, "session stopped while starting");
+                return Ok(session_id);
+            }
+            Some(reservation) => reservation.members,
+            None => 1,
+        };
+        sessions.insert(
+            session_id.clone(),
+            SessionEntry {
+                keep_alive: session.keep_alive_handle(),
+                abort: session.abort_handle(),
+                ssrc_rx: session.ssrc_rx(),
+                counters: session.counters(),
+                session: Arc::new(Mutex::new(session)),
+                info,
+                client_ip,
+                members,
+            },
+        );
+        tracing::trace!(%session_id, "registered new session");
+        Ok(session_id)
     }
 
     pub async fn play(
@@ -90,7 +472,12 @@ impl SessionManager {
         id: &SessionId,
         range: Option<rtsp::Range>,
     ) -> Option<Result<media::StreamState, PlaySessionError>> {
-        let session = self.sessions.read().await.get(id).cloned();
+        let session = self
+            .sessions
+            .read()
+            .await
+            .get(id)
+            .map(|entry| entry.session.clone());
         if let Some(session) = session {
             tracing::trace!(session_id=%id, "start playing");
             Some(session.lock().await.play(range).await)
@@ -103,24 +490,239 @@ impl SessionManager {
         }
     }
 
-    pub async fn teardown(&self, id: &SessionId) -> bool {
-        let session = self.sessions.read().await.get(id).cloned();
+    /// Pause the session. A shared session keeps playing for as long as
+    /// other clients are tuned in to it.
+    pub async fn pause(&self, id: &SessionId) -> Option<Result<(), SessionControlError>> {
+        let entry = self.sessions.read().await.get(id).cloned();
+        if let Some(entry) = entry {
+            if entry.members > 1 {
+                tracing::trace!(session_id=%id, members = entry.members, "not pausing shared session");
+                return Some(Ok(()));
+            }
+            tracing::trace!(session_id=%id, "pausing session");
+            Some(entry.session.lock().await.pause())
+        } else {
+            tracing::trace!(
+              session_id=%id,
+              "caller tried to pause session that does not exist",
+            );
+            None
+        }
+    }
+
+    /// Reset the timeout of the session, because the client showed that it
+    /// is still there. Clients may do this every few seconds, so this does
+    /// not wait for the session itself, which may be busy playing or being
+    /// torn down.
+    pub async fn keep_alive(&self, id: &SessionId) -> Result<(), KeepAliveError> {
+        match self.sessions.read().await.get(id) {
+            Some(entry) => {
+                tracing::trace!(session_id=%id, "keeping session alive");
+                entry
+                    .keep_alive
+                    .keep_alive()
+                    .map_err(|_| KeepAliveError::ControlBroken)
+            }
+            None => {
+                tracing::trace!(
+                  session_id=%id,
+                  "caller tried to keep alive session that does not exist",
+                );
+                Err(KeepAliveError::NotFound)
+            }
+        }
+    }
+
+    /// Tear down the session. A shared session is only torn down when the
+    /// last of its clients leaves. Fails if there is no such session.
+    pub async fn teardown(&self, id: &SessionId) -> Result<(), TeardownError> {
+        let session = match self.sessions.write().await.get_mut(id) {
+            Some(entry) if entry.members > 1 => {
+                entry.members -= 1;
+                tracing::trace!(session_id=%id, members = entry.members, "left shared session");
+                return Ok(());
+            }
+            Some(entry) => Some(entry.session.clone()),
+            None => None,
+        };
         if let Some(session) = session {
             tracing::trace!(session_id=%id, "tearing down session");
             session.lock().await.teardown().await;
             tracing::trace!(session_id=%id, "torn down session");
-            true
+            Ok(())
         } else {
             tracing::trace!(
               session_id=%id,
               "caller tried to tear down session that does not exist",
             );
-            false
+            Err(TeardownError::NotFound)
+        }
+    }
+
+    /// Tear down all sessions of a source at once, for example when the
+    /// source goes offline or is taken out of rotation. Shared sessions are
+    /// torn down for all of their clients. Returns the number of sessions
+    /// that were torn down.
+    pub async fn teardown_all_for_source(&self, path: &SourcePathRef) -> usize {
+        let path = source::normalize_path(path.to_string());
+        let torn_down = Self::stop_sessions_of(&self.sessions, &path, StopReason::Teardown).await;
+        tracing::info!(%path, sessions = torn_down, "tore down all sessions of source");
+        torn_down
+    }
+
+    /// Drain a source for maintenance. New sessions for the source are
+    /// refused from now on, and the sessions that still play the source
+    /// once `deadline` has passed are torn down. Draining a source that
+    /// is draining already does nothing.
+    ///
+    /// Note: Clients are not told why their session ends, since the ser-
+    /// ver cannot send requests such as `ANNOUNCE` to clients (yet). No-
+    /// thing in the server drains a source on its own either.
+    #[allow(dead_code)]
+    pub async fn drain_source(&self, path: &SourcePathRef, deadline: Duration) {
+        let path = source::normalize_path(path.to_string());
+        let mut draining = self.draining.lock().await;
+        if draining.contains_key(&path) {
+            tracing::debug!(%path, "source already draining");
+            return;
+        }
+        tracing::info!(%path, ?deadline, "draining source");
+
+        let sessions = self.sessions.clone();
+        let drain = self
+            .runtime
+            .task()
+            .spawn({
+                let path = path.clone();
+                move |mut task_context| async move {
+                    select! {
+                      // CANCEL SAFETY: `time::sleep` is cancel safe.
+                      _ = time::sleep(deadline) => {},
+                      // CANCEL SAFETY: `TaskContext::wait_for_stop` is cancel safe.
+                      _ = task_context.wait_for_stop() => return,
+                    }
+                    let drained =
+                        Self::stop_sessions_of(&sessions, &path, StopReason::SourceDrained).await;
+                    tracing::info!(%path, sessions = drained, "drained source");
+                }
+            })
+            .await;
+        // The task lives for as long as the source drains: resuming the
+        // source stops it, which cancels the teardown if it is still due.
+        draining.insert(path, drain);
+    }
+
+    /// Admit new sessions for a source that was drained again. Sessions of
+    /// the source are no longer torn down if the deadline has not passed.
+    #[allow(dead_code)]
+    pub async fn resume_source(&self, path: &SourcePathRef) {
+        let path = source::normalize_path(path.to_string());
+        let drain = self.draining.lock().await.remove(&path);
+        if let Some(mut drain) = drain {
+            drain.stop().await;
+            tracing::info!(%path, "resumed source");
         }
     }
 
+    /// Stop all sessions of the source at `path` for `reason`. Returns the
+    /// number of sessions that were stopped.
+    async fn stop_sessions_of(
+        sessions: &SessionMap,
+        path: &SourcePathRef,
+        reason: StopReason,
+    ) -> usize {
+        // Only collect the sessions under the lock: stopping them waits for
+        // the sessions to stop, and their state updates need the lock.
+        let sessions = sessions
+            .read()
+            .await
+            .iter()
+            .filter(|(_, entry)| entry.info.source_path == path)
+            .map(|(id, entry)| (id.clone(), entry.session.clone()))
+            .collect::<Vec<_>>();
+        for (session_id, session) in &sessions {
+            tracing::trace!(%session_id, %path, %reason, "stopping session of source");
+            session.lock().await.stop(reason).await;
+        }
+        sessions.len()
+    }
+
+    /// Get the metadata of a session, if it exists.
+    pub async fn session_info(&self, id: &SessionId) -> Option<SessionInfo> {
+        self.sessions
+            .read()
+            .await
+            .get(id)
+            .map(|entry| entry.info.clone())
+    }
+
+    /// List the metadata of all sessions.
+    pub async fn list_sessions(&self) -> Vec<SessionInfo> {
+        self.sessions
+            .read()
+            .await
+            .values()
+            .map(|entry| entry.info.clone())
+            .collect()
+    }
+
+    /// Get the counters of what a session has sent, if it exists. Does not
+    /// wait for the session.
+    pub async fn session_stats(&self, id: &SessionId) -> Option<SessionStats> {
+        self.sessions
+            .read()
+            .await
+            .get(id)
+            .map(|entry| entry.counters.snapshot())
+    }
+
+    /// Check whether the manager and the workers of all sessions it tracks
+    /// are still running. A session whose worker finished without the
+    /// manager hearing about it, for example because it panicked, shows
+    /// up as dead until it is torn down. Does not wait for any session.
+    pub async fn health(&self) -> Health {
+        let sessions = self.sessions.read().await;
+        Health::of(
+            !self.worker.is_finished(),
+            sessions
+                .iter()
+                .map(|(session_id, entry)| (session_id.clone(), entry.abort.as_ref())),
+        )
+    }
+
+    /// Total number of bytes buffered by sessions, accounted coarsely.
+    pub fn buffered_bytes(&self) -> usize {
+        self.memory.total()
+    }
+
+    /// Count the sessions of each source. Sources without sessions are not
+    /// included.
+    pub async fn num_sessions_per_source(&self) -> HashMap<SourcePath, usize> {
+        let mut num_sessions = HashMap::new();
+        for entry in self.sessions.read().await.values() {
+            *num_sessions
+                .entry(entry.info.source_path.clone())
+                .or_insert(0) += 1;
+        }
+        num_sessions
+    }
+
+    /// Query the SSRCs currently in use by sessions, and the sessions that
+    /// use each of them, see [`ssrcs_of`]. Does not wait for any session.
+    pub async fn ssrcs(&self) -> HashMap<u32, Vec<SessionId>> {
+        ssrcs_of(
+            self.sessions
+                .read()
+                .await
+                .iter()
+                .map(|(session_id, entry)| (session_id, &entry.ssrc_rx)),
+        )
+    }
+
     async fn run(
         sessions: SessionMap,
+        reservations: ReservationMap,
+        observer: Option<Arc<dyn SessionObserver>>,
         mut session_state_rx: SessionStateRx,
         mut task_context: TaskContext,
     ) {
@@ -129,10 +731,7 @@ impl SessionManager {
               // CANCEL SAFETY: `mpsc::UnboundedReceiver::recv` is cancel safe.
               state = session_state_rx.recv() => {
                 match state {
-                  Some(SessionState::Stopped(session_id)) => {
-                    let _ = sessions.write().await.remove(&session_id);
-                    tracing::trace!(%session_id, "session manager: received stopped");
-                  },
+                  Some(state) => Self::handle_state(&sessions, &reservations, observer.as_deref(), state).await,
                   None => {
                     tracing::error!("session state channel broke unexpectedly");
                     break;
@@ -147,19 +746,778 @@ impl SessionManager {
             }
         }
     }
+
+    /// Handle a state change reported by a session. The observer is only
+    /// called after the lock on the sessions is released, so that it can
+    /// call back into the manager.
+    async fn handle_state(
+        sessions: &SessionMap,
+        reservations: &ReservationMap,
+        observer: Option<&dyn SessionObserver>,
+        state: SessionState,
+    ) {
+        match state {
+            SessionState::Started(session_id) => {
+                tracing::trace!(%session_id, "session manager: received started");
+                if let Some(observer) = observer {
+                    observer.on_started(&session_id);
+                }
+            }
+            SessionState::Playing(session_id) => {
+                tracing::trace!(%session_id, "session manager: received playing");
+                if let Some(observer) = observer {
+                    observer.on_playing(&session_id);
+                }
+            }
+            SessionState::Paused(session_id) => {
+                tracing::trace!(%session_id, "session manager: received paused");
+                if let Some(observer) = observer {
+                    observer.on_paused(&session_id);
+                }
+            }
+            SessionState::Error(session_id, error) => {
+                tracing::warn!(%session_id, %error, "session manager: received error");
+                if let Some(observer) = observer {
+                    observer.on_error(&session_id, &error);
+                }
+            }
+            SessionState::Stopped(session_id, reason) => {
+                let mut sessions = sessions.write().await;
+                if sessions.remove(&session_id).is_none() {
+                    // The session stopped before it was registered, so it must
+                    // not be registered at all.
+                    if let Some(reservation) = reservations.lock().await.get_mut(&session_id) {
+                        reservation.stopped = true;
+                    }
+                }
+                drop(sessions);
+                tracing::trace!(%session_id, %reason, "session manager: received stopped");
+                if let Some(observer) = observer {
+                    observer.on_stopped(&session_id, reason);
+                }
+            }
+        }
+    }
+}
+
+/// Check whether there is room for another session besides the given
+/// number of existing sessions.
+fn check_capacity(
+    num_sessions: usize,
+    max_sessions: Option<usize>,
+) -> Result<(), RegisterSessionError> {
+    match max_sessions {
+        Some(max_sessions) if num_sessions >= max_sessions => {
+            Err(RegisterSessionError::CapacityExceeded { max_sessions })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check whether a client with the given number of existing sessions may
+/// set up another session at `now`. The setup is recorded in `history`
+/// if it may. The sessions of a client stop counting towards the limit
+/// when they are removed, which the manager does when they stop.
+fn check_client_limits(
+    limits: &ClientLimits,
+    history: &mut SetupHistory,
+    client_ip: IpAddr,
+    client_sessions: usize,
+    now: Instant,
+) -> Result<(), RegisterSessionError> {
+    if let Some(max_sessions) = limits.max_sessions {
+        if client_sessions >= max_sessions {
+            return Err(RegisterSessionError::RateLimited { client_ip });
+        }
+    }
+    if let Some(setup_rate) = &limits.setup_rate {
+        if !history.admit(client_ip, setup_rate, now) {
+            return Err(RegisterSessionError::RateLimited { client_ip });
+        }
+    }
+    Ok(())
+}
+
+/// Group sessions by the SSRC of their RTP stream. More than one session
+/// per SSRC is a collision, which confuses clients that receive both
+/// streams, such as the members of overlapping multicast groups. Ses-
+/// sions that have not sent any RTP yet do not have an SSRC.
+fn ssrcs_of<'a>(
+    sessions: impl Iterator<Item = (&'a SessionId, &'a SessionSsrcRx)>,
+) -> HashMap<u32, Vec<SessionId>> {
+    let mut ssrcs = HashMap::<u32, Vec<SessionId>>::new();
+    for (session_id, ssrc_rx) in sessions {
+        let ssrc = *ssrc_rx.borrow();
+        if let Some(ssrc) = ssrc {
+            ssrcs.entry(ssrc).or_default().push(session_id.clone());
+        }
+    }
+    for (ssrc, session_ids) in &ssrcs {
+        if session_ids.len() > 1 {
+            tracing::warn!(ssrc, ?session_ids, "ssrc collision between sessions");
+        }
+    }
+    ssrcs
+}
+
+/// Number of IDs to try for a new session before giving up, if they are
+/// all in use already.
+const MAX_ID_ATTEMPTS: usize = 16;
+
+/// Generate an ID that is not in use yet.
+fn generate_unique_id(
+    generator: &dyn SessionIdGenerator,
+    in_use: impl Fn(&SessionId) -> bool,
+) -> Option<SessionId> {
+    (0..MAX_ID_ATTEMPTS)
+        .map(|_| generator.generate())
+        .find(|session_id| {
+            let collision = in_use(session_id);
+            if collision {
+                tracing::debug!(%session_id, "session id already in use, retrying");
+            }
+            !collision
+        })
+}
+
+/// Snapshot of the health of the session manager, see [`SessionManag-
+/// er::health`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Health {
+    /// Whether the worker of the manager itself is running.
+    pub manager_running: bool,
+    /// Status of the worker of each tracked session.
+    pub sessions: HashMap<SessionId, WorkerStatus>,
+    /// Number of tracked sessions whose worker is no longer running.
+    pub dead: usize,
+}
+
+impl Health {
+    fn of<'a>(
+        manager_running: bool,
+        workers: impl IntoIterator<Item = (SessionId, Option<&'a task::AbortHandle>)>,
+    ) -> Self {
+        let sessions = workers
+            .into_iter()
+            .map(|(session_id, abort)| {
+                let status = match abort {
+                    Some(abort) if !abort.is_finished() => WorkerStatus::Running,
+                    _ => WorkerStatus::Finished,
+                };
+                (session_id, status)
+            })
+            .collect::<HashMap<_, _>>();
+        let dead = sessions
+            .values()
+            .filter(|status| **status == WorkerStatus::Finished)
+            .count();
+        Self {
+            manager_running,
+            sessions,
+            dead,
+        }
+    }
+
+    /// Whether the manager is running and no tracked session is dead.
+    pub fn is_healthy(&self) -> bool {
+        self.manager_running && self.dead == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerStatus {
+    Running,
+    /// Returned, panicked or was aborted.
+    Finished,
 }
 
 #[derive(Debug)]
 pub enum RegisterSessionError {
-    AlreadyRegistered,
+    NoUniqueId,
+    NotAdmitted(AdmissionError),
+    TooManyViewers,
+    SourceDraining,
+    MemoryExhausted,
+    CapacityExceeded { max_sessions: usize },
+    Bind(BindError),
+    RateLimited { client_ip: IpAddr },
 }
 
 impl fmt::Display for RegisterSessionError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            RegisterSessionError::AlreadyRegistered => write!(f, "already registered"),
+            RegisterSessionError::NoUniqueId => write!(f, "failed to generate unique session id"),
+            RegisterSessionError::NotAdmitted(err) => write!(f, "not admitted: {}", err),
+            RegisterSessionError::TooManyViewers => write!(f, "too many viewers"),
+            RegisterSessionError::SourceDraining => write!(f, "source is draining"),
+            RegisterSessionError::MemoryExhausted => write!(f, "memory exhausted"),
+            RegisterSessionError::CapacityExceeded { max_sessions } => {
+                write!(f, "maximum number of sessions ({}) reached", max_sessions)
+            }
+            RegisterSessionError::Bind(err) => write!(f, "{}", err),
+            RegisterSessionError::RateLimited { client_ip } => {
+                write!(f, "client {} exceeded its session limits", client_ip)
+            }
         }
     }
 }
 
 impl error::Error for RegisterSessionError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TeardownError {
+    NotFound,
+}
+
+impl fmt::Display for TeardownError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TeardownError::NotFound => write!(f, "session not found"),
+        }
+    }
+}
+
+impl error::Error for TeardownError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeepAliveError {
+    NotFound,
+    ControlBroken,
+}
+
+impl fmt::Display for KeepAliveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeepAliveError::NotFound => write!(f, "session not found"),
+            KeepAliveError::ControlBroken => write!(f, "failed to control session"),
+        }
+    }
+}
+
+impl error::Error for KeepAliveError {}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::net::UdpSocket;
+    use tokio::sync::{Mutex, RwLock};
+    use tokio::task;
+    use tokio::time::{self, Instant};
+
+    use crate::media::MediaInfo;
+    use crate::runtime::Runtime;
+    use crate::session::client_limit::{ClientLimits, SetupHistory, SetupRate};
+    use crate::session::observer::SessionObserver;
+    use crate::session::setup::{
+        ClientIdentity, SendOverSocket, SendToSink, SessionSetup, SessionSetupTarget,
+    };
+    use crate::session::tests::{playing_session_loop, rtp_from, Recorded};
+    use crate::session::udp::{BindError, MulticastGroup, UdpBind};
+    use crate::session::{SessionId, SessionState, StopReason};
+    use crate::source::IdleSource;
+
+    use super::{
+        check_capacity, check_client_limits, generate_unique_id, ssrcs_of, Health,
+        RegisterSessionError, SessionManager, WorkerStatus,
+    };
+
+    #[test]
+    fn check_capacity_at_limit() {
+        assert!(check_capacity(0, Some(1)).is_ok());
+        assert!(matches!(
+            check_capacity(1, Some(1)),
+            Err(RegisterSessionError::CapacityExceeded { max_sessions: 1 }),
+        ));
+        assert!(check_capacity(1000, None).is_ok());
+    }
+
+    #[test]
+    fn check_client_limits_rate_limits_setups() {
+        let client_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let limits = ClientLimits {
+            max_sessions: None,
+            setup_rate: Some(SetupRate {
+                max_setups: 3,
+                window: Duration::from_secs(60),
+            }),
+        };
+        let mut history = SetupHistory::default();
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(check_client_limits(&limits, &mut history, client_ip, 0, now).is_ok());
+        }
+        assert!(matches!(
+            check_client_limits(&limits, &mut history, client_ip, 0, now),
+            Err(RegisterSessionError::RateLimited { client_ip: limited }) if limited == client_ip,
+        ));
+        // Other clients are not affected, and the client may set up again
+        // once the window has passed.
+        let other_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        assert!(check_client_limits(&limits, &mut history, other_ip, 0, now).is_ok());
+        let later = now + Duration::from_secs(60);
+        assert!(check_client_limits(&limits, &mut history, client_ip, 0, later).is_ok());
+    }
+
+    #[test]
+    fn check_client_limits_caps_sessions() {
+        let client_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let limits = ClientLimits {
+            max_sessions: Some(2),
+            setup_rate: None,
+        };
+        let mut history = SetupHistory::default();
+        let now = Instant::now();
+        assert!(check_client_limits(&limits, &mut history, client_ip, 1, now).is_ok());
+        assert!(matches!(
+            check_client_limits(&limits, &mut history, client_ip, 2, now),
+            Err(RegisterSessionError::RateLimited { .. }),
+        ));
+        assert!(
+            check_client_limits(&ClientLimits::default(), &mut history, client_ip, 100, now)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn generate_unique_id_retries() {
+        let next = AtomicUsize::new(0);
+        let generator =
+            || SessionId::from(next.fetch_add(1, Ordering::Relaxed).to_string().as_str());
+        let in_use = [SessionId::from("0"), SessionId::from("1")];
+        assert_eq!(
+            generate_unique_id(&generator, |id| in_use.contains(id)),
+            Some(SessionId::from("2")),
+        );
+        assert_eq!(generate_unique_id(&generator, |_| true), None);
+    }
+
+    #[tokio::test]
+    async fn health_counts_dead_workers() {
+        let running = task::spawn(std::future::pending::<()>());
+        let returned = task::spawn(async {});
+        let returned_abort = returned.abort_handle();
+        returned.await.unwrap();
+        let aborted = task::spawn(std::future::pending::<()>());
+        let aborted_abort = aborted.abort_handle();
+        aborted.abort();
+        assert!(aborted.await.unwrap_err().is_cancelled());
+
+        let running_abort = running.abort_handle();
+        let workers = [
+            (SessionId::from("running"), Some(&running_abort)),
+            (SessionId::from("returned"), Some(&returned_abort)),
+            (SessionId::from("aborted"), Some(&aborted_abort)),
+            (SessionId::from("never-started"), None),
+        ];
+        let health = Health::of(true, workers);
+        assert_eq!(
+            health.sessions[&SessionId::from("running")],
+            WorkerStatus::Running
+        );
+        assert_eq!(
+            health.sessions[&SessionId::from("returned")],
+            WorkerStatus::Finished
+        );
+        assert_eq!(health.dead, 3);
+        assert!(!health.is_healthy());
+
+        assert!(
+            Health::of(true, [(SessionId::from("running"), Some(&running_abort))]).is_healthy()
+        );
+        assert!(!Health::of(false, []).is_healthy());
+        running.abort();
+    }
+
+    #[derive(Default)]
+    struct CountingObserver {
+        started: AtomicUsize,
+        stopped: AtomicUsize,
+    }
+
+    impl SessionObserver for CountingObserver {
+        fn on_started(&self, _id: &SessionId) {
+            self.started.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_stopped(&self, _id: &SessionId, _reason: StopReason) {
+            self.stopped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[tokio::test]
+    async fn observer_sees_one_start_and_stop_per_session() {
+        let sessions = Arc::new(RwLock::new(HashMap::new()));
+        let reservations = Arc::new(Mutex::new(HashMap::new()));
+        let observer = CountingObserver::default();
+        for id in [SessionId::generate(), SessionId::generate()] {
+            for state in [
+                SessionState::Started(id.clone()),
+                SessionState::Playing(id.clone()),
+                SessionState::Paused(id.clone()),
+                SessionState::Error(id.clone(), "broken".to_string()),
+                SessionState::Stopped(id, StopReason::MuxFailed),
+            ] {
+                SessionManager::handle_state(&sessions, &reservations, Some(&observer), state)
+                    .await;
+            }
+        }
+        assert_eq!(observer.started.load(Ordering::Relaxed), 2);
+        assert_eq!(observer.stopped.load(Ordering::Relaxed), 2);
+    }
+
+    async fn sink_setup() -> SessionSetup {
+        SessionSetup::from_sink(
+            SendToSink::new(tokio::io::sink()),
+            MediaInfo {
+                streams: Vec::new(),
+            },
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn ssrcs_group_sessions_by_their_rtp() {
+        let mut sessions = [
+            (SessionId::from("a"), playing_session_loop()),
+            (SessionId::from("b"), playing_session_loop()),
+            (SessionId::from("c"), playing_session_loop()),
+            (SessionId::from("d"), playing_session_loop()),
+        ];
+        let ssrc_rxs = sessions
+            .iter()
+            .map(|(_, session)| session.ssrc_tx.subscribe())
+            .collect::<Vec<_>>();
+        // Sessions `a` and `c` collide, `d` did not send any RTP yet.
+        for ((_, session), ssrc) in sessions.iter_mut().zip([1, 2, 1]) {
+            session
+                .handle_muxed(&mut Recorded::default(), Ok(vec![rtp_from(ssrc, 0, 0)]))
+                .await
+                .unwrap();
+        }
+
+        let ssrcs = ssrcs_of(
+            sessions
+                .iter()
+                .map(|(session_id, _)| session_id)
+                .zip(&ssrc_rxs),
+        );
+        assert_eq!(
+            ssrcs,
+            HashMap::from([
+                (1, vec![SessionId::from("a"), SessionId::from("c")]),
+                (2, vec![SessionId::from("b")]),
+            ]),
+        );
+
+        // A session that picks another SSRC, such as after its muxer was
+        // reinitialized, no longer collides.
+        sessions[2]
+            .1
+            .handle_muxed(&mut Recorded::default(), Ok(vec![rtp_from(3, 1, 0)]))
+            .await
+            .unwrap();
+        let ssrcs = ssrcs_of(
+            sessions
+                .iter()
+                .map(|(session_id, _)| session_id)
+                .zip(&ssrc_rxs),
+        );
+        assert!(ssrcs.values().all(|session_ids| session_ids.len() == 1));
+        assert_eq!(ssrcs.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn multicast_joiners_count_as_viewers() {
+        let runtime = Arc::new(Runtime::new());
+        let session_manager = SessionManager::start(runtime.clone(), None).await;
+        let source = IdleSource::new("/multicast");
+        let multicast_setup = || async {
+            let mut setup = sink_setup().await;
+            setup.rtp_target = SessionSetupTarget::RtpUdpMulticast(MulticastGroup::new(
+                "239.0.0.1:5000".parse().unwrap(),
+            ));
+            setup
+        };
+
+        let session_id = session_manager
+            .setup(source.delegate(Some(2)), multicast_setup().await)
+            .await
+            .unwrap();
+        assert_eq!(
+            session_manager
+                .setup(source.delegate(Some(2)), multicast_setup().await)
+                .await
+                .unwrap(),
+            session_id,
+        );
+        assert!(matches!(
+            session_manager
+                .setup(source.delegate(Some(2)), multicast_setup().await)
+                .await,
+            Err(RegisterSessionError::TooManyViewers),
+        ));
+        runtime.stop().await;
+    }
+
+    #[tokio::test]
+    async fn evict_only_after_replacement_registers() {
+        let runtime = Arc::new(Runtime::new());
+        let client_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let session_manager = SessionManager::start(runtime.clone(), None)
+            .await
+            .with_client_limits(ClientLimits {
+                max_sessions: Some(1),
+                setup_rate: Some(SetupRate {
+                    max_setups: 2,
+                    window: Duration::from_secs(60),
+                }),
+            });
+        let source = IdleSource::new("/evict");
+        let reconnect_setup = || async {
+            let mut setup = sink_setup().await;
+            setup.client_ip = Some(client_ip);
+            setup.client_identity = Some(ClientIdentity::Address(client_ip));
+            setup
+        };
+
+        // The session being evicted does not count towards the limit of one
+        // session per client.
+        let first = session_manager
+            .setup(source.delegate(None), reconnect_setup().await)
+            .await
+            .unwrap();
+        let second = session_manager
+            .setup(source.delegate(None), reconnect_setup().await)
+            .await
+            .unwrap();
+        assert!(session_manager.session_info(&first).await.is_none());
+        assert!(session_manager.session_info(&second).await.is_some());
+
+        // The replacement is rate limited, so the session it would have
+        // evicted is kept.
+        assert!(matches!(
+            session_manager
+                .setup(source.delegate(None), reconnect_setup().await)
+                .await,
+            Err(RegisterSessionError::RateLimited { .. }),
+        ));
+        assert!(session_manager.session_info(&second).await.is_some());
+        runtime.stop().await;
+    }
+
+    #[tokio::test]
+    async fn setup_beyond_max_sessions_is_refused() {
+        let runtime = Arc::new(Runtime::new());
+        let session_manager = SessionManager::start(runtime.clone(), None)
+            .await
+            .with_max_sessions(Some(1));
+        let source = IdleSource::new("/capacity");
+
+        let session_id = session_manager
+            .setup(source.delegate(None), sink_setup().await)
+            .await
+            .unwrap();
+        assert!(matches!(
+            session_manager
+                .setup(source.delegate(None), sink_setup().await)
+                .await,
+            Err(RegisterSessionError::CapacityExceeded { max_sessions: 1 }),
+        ));
+
+        // There is room again once the session is gone.
+        session_manager.teardown(&session_id).await.unwrap();
+        time::timeout(Duration::from_secs(5), async {
+            while session_manager.session_info(&session_id).await.is_some() {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(session_manager
+            .setup(source.delegate(None), sink_setup().await)
+            .await
+            .is_ok());
+        runtime.stop().await;
+    }
+
+    #[tokio::test]
+    async fn drained_source_refuses_sessions_and_stops_them_by_the_deadline() {
+        let runtime = Arc::new(Runtime::new());
+        let session_manager = SessionManager::start(runtime.clone(), None).await;
+        let source = IdleSource::new("/drain");
+        let other_source = IdleSource::new("/other");
+        let wait_until_gone = |session_id: SessionId| {
+            let session_manager = &session_manager;
+            async move {
+                time::timeout(Duration::from_secs(5), async {
+                    while session_manager.session_info(&session_id).await.is_some() {
+                        time::sleep(Duration::from_millis(10)).await;
+                    }
+                })
+                .await
+                .unwrap();
+            }
+        };
+
+        let session_id = session_manager
+            .setup(source.delegate(None), sink_setup().await)
+            .await
+            .unwrap();
+        let other_session_id = session_manager
+            .setup(other_source.delegate(None), sink_setup().await)
+            .await
+            .unwrap();
+        session_manager
+            .drain_source("/drain", Duration::from_millis(50))
+            .await;
+        assert!(matches!(
+            session_manager
+                .setup(source.delegate(None), sink_setup().await)
+                .await,
+            Err(RegisterSessionError::SourceDraining),
+        ));
+        // The session plays on until the deadline.
+        assert!(session_manager.session_info(&session_id).await.is_some());
+        wait_until_gone(session_id).await;
+        assert!(session_manager
+            .session_info(&other_session_id)
+            .await
+            .is_some());
+
+        // A source that is resumed admits sessions again, and a drain that
+        // is resumed before its deadline leaves them be.
+        session_manager.resume_source("/drain").await;
+        let session_id = session_manager
+            .setup(source.delegate(None), sink_setup().await)
+            .await
+            .unwrap();
+        session_manager
+            .drain_source("/drain", Duration::from_millis(50))
+            .await;
+        session_manager.resume_source("/drain").await;
+        time::sleep(Duration::from_millis(100)).await;
+        assert!(session_manager.session_info(&session_id).await.is_some());
+        runtime.stop().await;
+    }
+
+    #[tokio::test]
+    async fn repeated_setups_of_client_are_limited() {
+        let runtime = Arc::new(Runtime::new());
+        let client_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let session_manager = SessionManager::start(runtime.clone(), None)
+            .await
+            .with_client_limits(ClientLimits {
+                max_sessions: Some(2),
+                setup_rate: None,
+            });
+        let source = IdleSource::new("/client");
+        let client_setup = |client_ip| async move {
+            let mut setup = sink_setup().await;
+            setup.client_ip = Some(client_ip);
+            setup
+        };
+
+        for _ in 0..2 {
+            assert!(session_manager
+                .setup(source.delegate(None), client_setup(client_ip).await)
+                .await
+                .is_ok());
+        }
+        assert!(matches!(
+            session_manager
+                .setup(source.delegate(None), client_setup(client_ip).await)
+                .await,
+            Err(RegisterSessionError::RateLimited { client_ip: limited }) if limited == client_ip,
+        ));
+        // Other clients are not affected.
+        let other_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        assert!(session_manager
+            .setup(source.delegate(None), client_setup(other_ip).await)
+            .await
+            .is_ok());
+        runtime.stop().await;
+    }
+
+    #[tokio::test]
+    async fn teardown_all_for_source_leaves_other_sources() {
+        let runtime = Arc::new(Runtime::new());
+        let session_manager = SessionManager::start(runtime.clone(), None).await;
+        let source = IdleSource::new("/removed");
+        let other_source = IdleSource::new("/other");
+
+        let mut session_ids = Vec::new();
+        for _ in 0..2 {
+            session_ids.push(
+                session_manager
+                    .setup(source.delegate(None), sink_setup().await)
+                    .await
+                    .unwrap(),
+            );
+        }
+        let other_session_id = session_manager
+            .setup(other_source.delegate(None), sink_setup().await)
+            .await
+            .unwrap();
+
+        assert_eq!(session_manager.teardown_all_for_source("removed").await, 2);
+        time::timeout(Duration::from_secs(5), async {
+            while session_manager.num_sessions_per_source().await.len() > 1 {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        for session_id in &session_ids {
+            assert!(session_manager.session_info(session_id).await.is_none());
+        }
+        assert!(session_manager
+            .session_info(&other_session_id)
+            .await
+            .is_some());
+        runtime.stop().await;
+    }
+
+    #[tokio::test]
+    async fn setup_fails_if_sockets_cannot_be_bound() {
+        let runtime = Arc::new(Runtime::new());
+        let session_manager = SessionManager::start(runtime.clone(), None)
+            .await
+            .with_max_sessions(Some(1));
+        let source = IdleSource::new("/bind");
+        let local = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        // Occupy one port of the only pair in the range.
+        let occupied = UdpSocket::bind((local, 0)).await.unwrap();
+        let rtp_port = occupied.local_addr().unwrap().port() & !1;
+        let mut setup = sink_setup().await;
+        setup.rtp_target = SessionSetupTarget::RtpUdp(SendOverSocket {
+            rtp_remote: (local, 5000).into(),
+            rtcp_remote: (local, 5001).into(),
+        });
+        setup.udp_bind = UdpBind {
+            ip: local,
+            port_range: Some((rtp_port, rtp_port + 1)),
+        };
+
+        assert!(matches!(
+            session_manager.setup(source.delegate(None), setup).await,
+            Err(RegisterSessionError::Bind(
+                BindError::NoPortsAvailable { .. }
+            )),
+        ));
+        assert!(session_manager.num_sessions_per_source().await.is_empty());
+        // The session that failed to start no longer takes up room.
+        assert!(session_manager
+            .setup(source.delegate(None), sink_setup().await)
+            .await
+            .is_ok());
+        runtime.stop().await;
+    }
+}
@@ -1,19 +1,106 @@
 use std::error;
 use std::fmt;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use std::io;
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::UdpSocket;
+
+use serde::Deserialize;
 
 use oddity_rtsp_protocol as rtsp;
 use video_rs as video;
 
 use crate::media::video::rtp_muxer;
 use crate::media::MediaInfo;
-use crate::net::connection::ResponseSenderTx;
-use crate::session::transport;
+use crate::net::connection::{InterleavedReceivers, InterleavedRx, ResponseSenderTx};
+use crate::session::pacing::Pacing;
+use crate::session::udp::{self, BindError, MulticastGroup, UdpBind};
+use crate::session::{mux_errors, repacketize, rtp, sender_report, transport};
 
 pub struct SessionSetup {
     pub rtsp_transport: rtsp::Transport,
     pub rtp_muxer: video::RtpMuxer,
     pub rtp_target: SessionSetupTarget,
+    /// End the session when the client sends an RTCP BYE instead of wa-
+    /// iting for an RTSP TEARDOWN.
+    pub teardown_on_bye: bool,
+    /// Send a mid-gray H.264 keyframe to the client as soon as it starts
+    /// playing, so that it initializes its decoder and shows something
+    /// before the next natural keyframe arrives. This is a last resort
+    /// for long-GOP sources and is off by default. Multicast groups and
+    /// sinks never get one.
+    pub synthetic_keyframe_on_join: bool,
+    /// SDP describing the muxer output exactly as it was configured, re-
+    /// tained for diagnostics.
+    pub sdp: Option<String>,
+    /// Respond to congestion on interleaved connections by dropping dis-
+    /// posable (non-reference) H.264 frames, such as B-frames, while more
+    /// than this many messages are waiting to be written to the client.
+    /// Reference frames are never dropped. Disabled if `None`.
+    pub drop_disposable_frames_backlog: Option<usize>,
+    /// Directory to additionally write the exact RTP output of the session
+    /// to, as `<session id>.rtp` with RFC 4571 framing, for example to ar-
+    /// chive what the client received. The recording is dropped on the
+    /// first error, so that a full disk cannot affect delivery to the cli-
+    /// ent. Sessions that write to a sink are not recorded.
+    pub record_dir: Option<PathBuf>,
+    /// Identity of the client that set up the session. When a session is
+    /// set up with an identity, any earlier session with the same identity
+    /// is evicted once the new session has registered, so that a recon-
+    /// necting client does not end up receiving the stream twice. If the
+    /// new session fails to register, the earlier sessions are kept.
+    pub client_identity: Option<ClientIdentity>,
+    /// IP address of the client that set up the session, which is the
+    /// address that per-client limits are applied to. Unknown for ses-
+    /// sions that write to a sink.
+    pub client_ip: Option<IpAddr>,
+    /// Largest RTP payload that we produce ourselves for this session. Al-
+    /// ways fits within the limit of the transport.
+    pub max_rtp_payload: usize,
+    /// Where to bind the sockets of sessions over UDP.
+    pub udp_bind: UdpBind,
+    /// RTP and RTCP sockets of a session over UDP, if bound during setup
+    /// with [`SessionSetup::bind_udp`]. The sockets are bound when the
+    /// session starts otherwise, see [`bind_target`]. There is no RTCP socket if RTCP is dis-
+    /// abled.
+    pub udp_sockets: Option<(UdpSocket, Option<UdpSocket>)>,
+    /// Whether the session sends RTCP to the client and listens to RTCP
+    /// from it.
+    pub rtcp_mode: RtcpMode,
+    /// Stop the session if the client shows no sign of life for this
+    /// long. Requests in the context of the session and RTCP from the
+    /// client count as signs of life. Sessions that write to a sink do
+    /// not time out.
+    pub timeout: Duration,
+    /// Send an RTCP sender report to the client on this interval, in
+    /// addition to the reports of the muxer. Disabled if `None`.
+    pub sender_report_interval: Option<Duration>,
+    /// Send RTP and RTCP over UDP to wherever the client sends its own
+    /// packets from, rather than to the ports in the `Transport` header.
+    /// Clients behind NAT often do not know their public ports, but the
+    /// first packet they send reveals them (symmetric RTP, RFC 4961).
+    pub symmetric_rtp: bool,
+    /// Stop a session over UDP after this many sends to the client in a
+    /// row have failed, for example because the client is no longer lis-
+    /// tening and its host replies with ICMP port unreachable.
+    pub max_send_errors: usize,
+    /// Mark RTP and RTCP over UDP with this DSCP value. Traffic is not
+    /// marked (CS0) if `None`.
+    pub dscp: Option<u8>,
+    /// Stop the session after this many packets from the source in a row
+    /// have failed to mux. Packets that fail to mux are dropped until
+    /// then, so that a bad frame does not end the stream.
+    pub max_mux_errors: usize,
+    /// Limit the rate at which the session sends RTP and RTCP over UDP
+    /// or interleaved. Unlimited if `None`.
+    pub pacing: Option<Pacing>,
+    /// Split RTP packets over UDP that would not fit in a packet of this
+    /// many bytes. Packets are sent as the muxer produces them if `None`.
+    pub mtu: Option<usize>,
 }
 
 impl SessionSetup {
@@ -21,45 +108,404 @@ impl SessionSetup {
         candidate_transports: impl IntoIterator<Item = rtsp::Transport>,
         media_info: MediaInfo,
         sender: ResponseSenderTx,
+        interleaved: &InterleavedReceivers,
+        client_ip_addr: Option<IpAddr>,
+        server_ports: &[u16],
+        multicast_group: Option<MulticastGroup>,
     ) -> Result<Self, SessionSetupError> {
-        let transport = candidate_transports
+        let transport::Negotiated {
+            transport,
+            declined,
+        } = candidate_transports
             .into_iter()
-            .find(transport::is_supported)
+            // Skip multicast candidates if the source has no group, so that
+            // the client gets unicast if it offered that as well.
+            .filter(|transport| multicast_group.is_some() || !transport::is_multicast(transport))
+            .find_map(|transport| transport::negotiate(&transport))
             .ok_or(SessionSetupError::TransportNotSupported)?;
-        tracing::trace!(%transport, "selected transport");
+        tracing::trace!(%transport, ?declined, "selected transport");
 
         tracing::trace!("initializing muxer");
         rtp_muxer::make_rtp_muxer()
             .await
             .map_err(SessionSetupError::Media)
-            .and_then(|mut rtp_muxer| {
-                let resolved_transport = transport::resolve_transport(&transport);
+            .and_then(|rtp_muxer| {
+                let mut resolved_transport = transport::resolve_transport(&transport);
                 tracing::trace!(%resolved_transport, "resolved transport");
-                let rtp_target =
-                    SessionSetupTarget::from_rtsp_transport(&resolved_transport, sender)
-                        .ok_or(SessionSetupError::DestinationInvalid)?;
-                tracing::debug!(?rtp_target, "calculated target");
-
-                for stream_info in media_info.streams {
-                    tracing::trace!(stream_index = stream_info.index, "adding stream to muxer");
-                    rtp_muxer = rtp_muxer
-                        .with_stream(stream_info)
-                        .map_err(SessionSetupError::Media)?;
+                let rtp_target = SessionSetupTarget::from_rtsp_transport(
+                    &resolved_transport,
+                    sender,
+                    interleaved,
+                    client_ip_addr,
+                    server_ports,
+                    multicast_group,
+                )?;
+                match &rtp_target {
+                    // Tell the client which group to tune in to.
+                    SessionSetupTarget::RtpUdpMulticast(group) => {
+                        resolved_transport = resolved_transport.with_parameters([
+                            rtsp::Parameter::Destination(group.group.ip()),
+                            rtsp::Parameter::Port(rtsp::Port::Range(
+                                group.rtp_addr().port(),
+                                group.rtcp_addr().port(),
+                            )),
+                            rtsp::Parameter::Ttl(group.ttl as usize),
+                        ]);
+                    }
+                    // Tell the client which channels were assigned if it left
+                    // that to us.
+                    SessionSetupTarget::RtpTcp(target)
+                        if resolved_transport.interleaved_channel().is_none() =>
+                    {
+                        resolved_transport =
+                            resolved_transport.with_parameter(rtsp::Parameter::Interleaved(
+                                rtsp::Channel::Range(target.rtp_channel, target.rtcp_channel),
+                            ));
+                    }
+                    _ => {}
                 }
-
-                Ok(Self {
-                    rtsp_transport: resolved_transport,
+                Self::new(
+                    resolved_transport,
                     rtp_muxer,
                     rtp_target,
-                })
+                    media_info,
+                    client_ip_addr.map(|ip_addr| ip_addr.to_canonical()),
+                )
             })
     }
+
+    /// Set up a session that writes its RTP output to an arbitrary sink
+    /// instead of a client connection. This can be used to chain the mu-
+    /// xed output into another process or custom consumer. The session
+    /// plays as soon as it starts, because there is no client to send
+    /// PLAY.
+    pub async fn from_sink(
+        sink: SendToSink,
+        media_info: MediaInfo,
+    ) -> Result<Self, SessionSetupError> {
+        tracing::trace!("initializing muxer");
+        rtp_muxer::make_rtp_muxer()
+            .await
+            .map_err(SessionSetupError::Media)
+            .and_then(|rtp_muxer| {
+                Self::new(
+                    rtsp::Transport::new(),
+                    rtp_muxer,
+                    SessionSetupTarget::Sink(sink),
+                    media_info,
+                    None,
+                )
+            })
+    }
+
+    /// Set up a session for `rtp_target` that muxes the streams in `me-
+    /// dia_info`, with defaults for everything else.
+    fn new(
+        rtsp_transport: rtsp::Transport,
+        mut rtp_muxer: video::RtpMuxer,
+        rtp_target: SessionSetupTarget,
+        media_info: MediaInfo,
+        client_ip: Option<IpAddr>,
+    ) -> Result<Self, SessionSetupError> {
+        tracing::debug!(?rtp_target, "calculated target");
+
+        for stream_info in media_info.streams {
+            tracing::trace!(stream_index = stream_info.index, "adding stream to muxer");
+            rtp_muxer = rtp_muxer
+                .with_stream(stream_info)
+                .map_err(SessionSetupError::Media)?;
+        }
+
+        let sdp = rtp_muxer.sdp().ok();
+        let max_rtp_payload = rtp_target.max_rtp_payload().min(DEFAULT_MAX_RTP_PAYLOAD);
+
+        Ok(Self {
+            rtsp_transport,
+            rtp_muxer,
+            rtp_target,
+            teardown_on_bye: true,
+            synthetic_keyframe_on_join: false,
+            sdp,
+            drop_disposable_frames_backlog: None,
+            record_dir: None,
+            client_identity: None,
+            client_ip,
+            max_rtp_payload,
+            udp_bind: UdpBind::default(),
+            udp_sockets: None,
+            rtcp_mode: RtcpMode::default(),
+            timeout: DEFAULT_SESSION_TIMEOUT,
+            sender_report_interval: Some(sender_report::DEFAULT_INTERVAL),
+            symmetric_rtp: false,
+            max_send_errors: DEFAULT_MAX_SEND_ERRORS,
+            dscp: None,
+            max_mux_errors: mux_errors::DEFAULT_MAX_CONSECUTIVE,
+            pacing: None,
+            mtu: None,
+        })
+    }
+
+    /// Limit the size of the RTP payloads produced for the session. The
+    /// limit is reconciled with what the transport can carry, see
+    /// [`reconcile_max_rtp_payload`].
+    pub fn with_max_rtp_payload(
+        mut self,
+        limit: RtpPayloadLimit,
+    ) -> Result<Self, SessionSetupError> {
+        self.max_rtp_payload = reconcile_max_rtp_payload(limit, self.rtp_target.max_rtp_payload())?;
+        Ok(self)
+    }
+
+    /// Bind the sockets of the session to the given address and port
+    /// range if the session sends over UDP. Fails if the address cannot
+    /// reach the client, see [`check_address_families`].
+    pub fn with_udp_bind(mut self, udp_bind: UdpBind) -> Result<Self, SessionSetupError> {
+        match &self.rtp_target {
+            SessionSetupTarget::RtpUdp(target) => check_address_families(target, &udp_bind)?,
+            SessionSetupTarget::RtpUdpMulticast(group) => {
+                let remote = group.group.ip();
+                if udp_bind.ip_for(remote).is_none() {
+                    return Err(SessionSetupError::AddressFamilyMismatch {
+                        local: udp_bind.ip,
+                        remote,
+                    });
+                }
+            }
+            SessionSetupTarget::RtpTcp(_) | SessionSetupTarget::Sink(_) => {}
+        }
+        self.udp_bind = udp_bind;
+        Ok(self)
+    }
+
+    /// Learn the RTP and RTCP ports of the client from the packets it
+    /// sends if the session sends over UDP.
+    pub fn with_symmetric_rtp(mut self, symmetric_rtp: bool) -> Self {
+        self.symmetric_rtp = symmetric_rtp;
+        self
+    }
+
+    /// Stop the session after the given number of failed sends in a row
+    /// instead of the default.
+    pub fn with_max_send_errors(mut self, max_send_errors: usize) -> Self {
+        self.max_send_errors = max_send_errors;
+        self
+    }
+
+    /// Mark the traffic of the session with the given DSCP value if the
+    /// session sends over UDP.
+    pub fn with_dscp(mut self, dscp: Option<u8>) -> Self {
+        self.dscp = dscp;
+        self
+    }
+
+    /// Send sender reports on the given interval instead of the default. A
+    /// zero interval disables them. Going below the RFC 3550 minimum (see
+    /// [`sender_report::RFC_3550_MIN_INTERVAL`]) tightens synchronization
+    /// for a single receiver, but is unsafe for multicast groups and other
+    /// large sessions.
+    pub fn with_sender_report_interval(mut self, sender_report_interval: Duration) -> Self {
+        self.sender_report_interval =
+            Some(sender_report_interval).filter(|interval| !interval.is_zero());
+        self
+    }
+
+    /// Stop the session after the given number of packets in a row fail
+    /// to mux instead of the default.
+    pub fn with_max_mux_errors(mut self, max_mux_errors: usize) -> Self {
+        self.max_mux_errors = max_mux_errors;
+        self
+    }
+
+    /// Bind the RTP and RTCP sockets of a session over unicast UDP right
+    /// away, and announce their ports as `server_port` in the transport,
+    /// so that the client knows where the stream comes from before it
+    /// starts. Call this after [`SessionSetup::with_udp_bind`] and [`Ses-
+    /// sionSetup::with_rtcp_mode`]. Does nothing for other targets.
+    pub async fn bind_udp(mut self) -> Result<Self, SessionSetupError> {
+        let remote = match &self.rtp_target {
+            SessionSetupTarget::RtpUdp(target) => target.rtp_remote.ip(),
+            _ => return Ok(self),
+        };
+        let (rtp_socket, rtcp_socket) =
+            udp::bind_sockets(&self.udp_bind, remote, self.rtcp_mode.receives())
+                .await
+                .map_err(SessionSetupError::Bind)?;
+        let port = |socket: &UdpSocket| {
+            socket
+                .local_addr()
+                .map(|local_addr| local_addr.port())
+                .map_err(|err| SessionSetupError::Bind(err.into()))
+        };
+        let server_port = match &rtcp_socket {
+            Some(rtcp_socket) => rtsp::Port::Range(port(&rtp_socket)?, port(rtcp_socket)?),
+            None => rtsp::Port::Single(port(&rtp_socket)?),
+        };
+        self.rtsp_transport = self
+            .rtsp_transport
+            .with_parameter(rtsp::Parameter::ServerPort(server_port));
+        self.udp_sockets = Some((rtp_socket, rtcp_socket));
+        Ok(self)
+    }
+
+    /// Use the given RTCP mode instead of the default, which sends and
+    /// receives RTCP.
+    pub fn with_rtcp_mode(mut self, rtcp_mode: RtcpMode) -> Self {
+        self.rtcp_mode = rtcp_mode;
+        self
+    }
+
+    /// Fit the RTP packets of the session over UDP to the given MTU. Fails
+    /// if the MTU is below [`repacketize::MIN_MTU`].
+    pub fn with_mtu(mut self, mtu: Option<usize>) -> Result<Self, SessionSetupError> {
+        if let Some(mtu) = mtu {
+            if mtu < repacketize::MIN_MTU {
+                return Err(SessionSetupError::MtuTooSmall {
+                    mtu,
+                    min: repacketize::MIN_MTU,
+                });
+            }
+        }
+        self.mtu = mtu;
+        Ok(self)
+    }
+
+    /// Send a synthetic keyframe to the client as soon as it starts play-
+    /// ing, see [`SessionSetup::synthetic_keyframe_on_join`].
+    pub fn with_synthetic_keyframe_on_join(mut self, synthetic_keyframe_on_join: bool) -> Self {
+        self.synthetic_keyframe_on_join = synthetic_keyframe_on_join;
+        self
+    }
+
+    /// Record the session to the given directory as well, see [`Session-
+    /// Setup::record_dir`].
+    pub fn with_record_dir(mut self, record_dir: Option<PathBuf>) -> Self {
+        self.record_dir = record_dir;
+        self
+    }
+
+    /// Drop disposable frames while the client of a session over an in-
+    /// terleaved connection does not keep up, see
+    /// [`SessionSetup::drop_disposable_frames_backlog`].
+    pub fn with_drop_disposable_frames_backlog(
+        mut self,
+        drop_disposable_frames_backlog: Option<usize>,
+    ) -> Self {
+        self.drop_disposable_frames_backlog = drop_disposable_frames_backlog;
+        self
+    }
+
+    /// Pace the packets the session sends so that it does not exceed the
+    /// given bitrate. Unlimited if `None`.
+    pub fn with_pacing(mut self, pacing: Option<Pacing>) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Use the given session timeout instead of the default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// Session timeout unless configured otherwise. This is the default in
+/// RFC 2326 section 12.37.
+pub const DEFAULT_SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Number of failed sends in a row after which a session over UDP is
+/// stopped unless configured otherwise.
+pub const DEFAULT_MAX_SEND_ERRORS: usize = 3;
+
+/// Largest RTP payload we produce unless configured otherwise. This
+/// leaves room for the IP, UDP and RTP headers within a regular Ether-
+/// net MTU.
+pub const DEFAULT_MAX_RTP_PAYLOAD: usize = 1400;
+
+/// Requested limit on the size of RTP payloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtpPayloadLimit {
+    /// Limit that may be lowered to what the transport can carry, such
+    /// as a limit from the server configuration.
+    Preferred(usize),
+    /// Limit that was explicitly demanded (for example by the client
+    /// through the `Blocksize` header) and must not be lowered.
+    Required(usize),
+}
+
+/// Reconcile a requested RTP payload limit with the largest payload the
+/// transport can carry. A preferred limit is clamped to the transport
+/// maximum, a required limit that the transport cannot carry is reject-
+/// ed, so that the problem surfaces during setup instead of as framing
+/// errors in the middle of the stream.
+pub fn reconcile_max_rtp_payload(
+    limit: RtpPayloadLimit,
+    transport_max: usize,
+) -> Result<usize, SessionSetupError> {
+    match limit {
+        RtpPayloadLimit::Preferred(requested) | RtpPayloadLimit::Required(requested)
+            if requested <= transport_max =>
+        {
+            Ok(requested)
+        }
+        RtpPayloadLimit::Preferred(requested) => {
+            tracing::warn!(
+                requested,
+                transport_max,
+                "rtp payload limit exceeds transport maximum, clamping",
+            );
+            Ok(transport_max)
+        }
+        RtpPayloadLimit::Required(requested) => Err(SessionSetupError::RtpPayloadTooLarge {
+            requested,
+            max: transport_max,
+        }),
+    }
+}
+
+/// What a session does with RTCP. Some minimal clients ignore RTCP alto-
+/// gether, in which case the RTCP socket or interleaved channel is only
+/// overhead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RtcpMode {
+    /// Send RTCP to the client and listen to RTCP from it.
+    #[default]
+    Enabled,
+    /// Send only RTP, but still listen to RTCP from the client, such as
+    /// receiver reports that keep the session alive.
+    RtpOnly,
+    /// Neither send nor listen to RTCP. Sessions over UDP do not bind an
+    /// RTCP socket at all.
+    Disabled,
+}
+
+impl RtcpMode {
+    /// Whether RTCP is sent to the client. RTCP that the muxer produces is
+    /// dropped otherwise.
+    pub fn sends(self) -> bool {
+        self == RtcpMode::Enabled
+    }
+
+    /// Whether RTCP from the client is listened to.
+    pub fn receives(self) -> bool {
+        self != RtcpMode::Disabled
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientIdentity {
+    Address(IpAddr),
+    Token(String),
 }
 
 #[derive(Debug)]
 pub enum SessionSetupTarget {
     RtpUdp(SendOverSocket),
+    /// Send over UDP to a multicast group that any number of clients can
+    /// tune in to.
+    RtpUdpMulticast(MulticastGroup),
     RtpTcp(SendInterleaved),
+    Sink(SendToSink),
 }
 
 #[derive(Debug)]
@@ -73,49 +519,364 @@ pub struct SendInterleaved {
     pub sender: ResponseSenderTx,
     pub rtp_channel: u8,
     pub rtcp_channel: u8,
+    pub rtcp_rx: InterleavedRx,
+}
+
+pub struct SendToSink {
+    pub writer: Box<dyn AsyncWrite + Send + Sync + Unpin>,
+    pub framing: SinkFraming,
+}
+
+impl SendToSink {
+    pub fn new(writer: impl AsyncWrite + Send + Sync + Unpin + 'static) -> Self {
+        Self {
+            writer: Box::new(writer),
+            framing: SinkFraming::default(),
+        }
+    }
+
+    /// Use the given framing for packets written to the sink instead of
+    /// the default (length-prefixed) framing.
+    pub fn with_framing(mut self, framing: SinkFraming) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Write RTP and RTCP packets to the sink, framed according to the
+    /// configured [`SinkFraming`].
+    pub async fn write(&mut self, bufs: &[video::RtpBuf]) -> io::Result<()> {
+        let mut out = Vec::new();
+        for buf in bufs {
+            self.framing.encode(buf, &mut out)?;
+        }
+        self.writer.write_all(&out).await?;
+        self.writer.flush().await
+    }
+
+    pub async fn shutdown(&mut self) -> io::Result<()> {
+        self.writer.shutdown().await
+    }
+}
+
+/// Framing of the RTP and RTCP packets written to a sink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkFraming {
+    /// Packets are written back-to-back without any framing:
+    ///
+    /// ```text
+    /// +---------------------+---------------------+-----
+    /// | packet (n bytes)    | packet (m bytes)    | ...
+    /// +---------------------+---------------------+-----
+    /// ```
+    ///
+    /// There are no packet boundaries in the output, so this is only
+    /// useful for consumers that get one packet per write, or that parse
+    /// RTP headers themselves (RTP and RTCP can be told apart by the pay-
+    /// load type byte, see RFC 5761).
+    Raw,
+    /// Packets are framed like interleaved data over RTSP (RFC 2326 sec-
+    /// tion 10.12): a `$` (`0x24`) byte, the channel as one byte and the
+    /// length of the packet as a 16-bit big-endian integer, followed by
+    /// the packet itself:
+    ///
+    /// ```text
+    /// +------+---------+----------------+------------------+
+    /// | 0x24 | channel | length (u16be) | packet (length)  |
+    /// +------+---------+----------------+------------------+
+    /// ```
+    Interleaved { rtp_channel: u8, rtcp_channel: u8 },
+    /// Each packet is prefixed with its length as a 16-bit big-endian in-
+    /// teger, as in RFC 4571:
+    ///
+    /// ```text
+    /// +----------------+------------------+
+    /// | length (u16be) | packet (length)  |
+    /// +----------------+------------------+
+    /// ```
+    ///
+    /// RTP and RTCP are not distinguished by the framing.
+    #[default]
+    LengthPrefixed,
+}
+
+impl SinkFraming {
+    const INTERLEAVED_MAGIC: u8 = b'$';
+
+    /// Append the framed packet to `out`.
+    pub fn encode(&self, buf: &video::RtpBuf, out: &mut Vec<u8>) -> io::Result<()> {
+        let (payload, is_rtcp) = match buf {
+            video::RtpBuf::Rtp(payload) => (payload, false),
+            video::RtpBuf::Rtcp(payload) => (payload, true),
+        };
+        let len = || {
+            u16::try_from(payload.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "packet too large"))
+        };
+        match self {
+            SinkFraming::Raw => {}
+            SinkFraming::Interleaved {
+                rtp_channel,
+                rtcp_channel,
+            } => {
+                let channel = if is_rtcp { *rtcp_channel } else { *rtp_channel };
+                out.push(Self::INTERLEAVED_MAGIC);
+                out.push(channel);
+                out.extend(len()?.to_be_bytes());
+            }
+            SinkFraming::LengthPrefixed => {
+                out.extend(len()?.to_be_bytes());
+            }
+        }
+        out.extend(payload);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for SendToSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SendToSink").finish_non_exhaustive()
+    }
 }
 
 impl SessionSetupTarget {
+    /// Largest RTP payload that the target can carry in a single packet.
+    ///
+    /// Over UDP, this is the largest UDP payload over IPv4 minus the RTP
+    /// header. Interleaved and length-prefixed framing encode the length
+    /// of the packet in 16 bits. Sinks are held to the same limit when
+    /// they write raw packets, to keep the output compatible with the
+    /// other framings.
+    pub fn max_rtp_payload(&self) -> usize {
+        const MAX_UDP_PAYLOAD: usize = 65507;
+        const MAX_FRAMED_PACKET: usize = u16::MAX as usize;
+        let max_packet = match self {
+            SessionSetupTarget::RtpUdp(_) | SessionSetupTarget::RtpUdpMulticast(_) => {
+                MAX_UDP_PAYLOAD
+            }
+            SessionSetupTarget::RtpTcp(_) | SessionSetupTarget::Sink(_) => MAX_FRAMED_PACKET,
+        };
+        max_packet - rtp::HEADER_LEN
+    }
+
+    /// Determine the target from the resolved RTSP transport.
+    ///
+    /// # Arguments
+    ///
+    /// * `rtsp_transport` - Resolved transport.
+    /// * `sender` - Sender for interleaved data over the connection.
+    /// * `interleaved` - Receivers for interleaved data over the connec-
+    ///   tion, used to receive RTCP from the client. The channels of the
+    ///   session are claimed here, and assigned if the client did not ask
+    ///   for any.
+    /// * `client_ip_addr` - Address of the client, where we send RTP to
+    ///   over UDP.
+    /// * `server_ports` - Ports bound by the server itself. Clients on
+    ///   the same host cannot receive RTP on these.
+    /// * `multicast_group` - Group that the source is delivered to, if
+    ///   the client asks for multicast.
     pub fn from_rtsp_transport(
         rtsp_transport: &rtsp::Transport,
         sender: ResponseSenderTx,
-    ) -> Option<Self> {
-        Some(match rtsp_transport.lower_protocol()? {
+        interleaved: &InterleavedReceivers,
+        client_ip_addr: Option<IpAddr>,
+        server_ports: &[u16],
+        multicast_group: Option<MulticastGroup>,
+    ) -> Result<Self, SessionSetupError> {
+        let lower_protocol = rtsp_transport
+            .lower_protocol()
+            .ok_or(SessionSetupError::DestinationInvalid)?;
+        Ok(match lower_protocol {
+            rtsp::Lower::Udp if transport::is_multicast(rtsp_transport) => {
+                let multicast_group =
+                    multicast_group.ok_or(SessionSetupError::TransportNotSupported)?;
+                if !multicast_group.group.ip().is_multicast() {
+                    return Err(SessionSetupError::DestinationInvalid);
+                }
+                SessionSetupTarget::RtpUdpMulticast(multicast_group)
+            }
             rtsp::Lower::Udp => {
-                let client_ip_addr = rtsp_transport.destination()?;
-                let (client_rtp_port, client_rtcp_port) = match rtsp_transport.client_port()? {
-                    rtsp::Port::Single(rtp_port) => (*rtp_port, rtp_port + 1),
+                // The destination parameter is declined during negotiation, so that the server
+                // cannot be used to send streams to a third party.
+                // Clients connected to an IPv6 listener over IPv4 show up with an IPv4-mapped
+                // address, which only dual-stack sockets can send to.
+                let client_ip_addr = client_ip_addr
+                    .ok_or(SessionSetupError::DestinationInvalid)?
+                    .to_canonical();
+                let (client_rtp_port, client_rtcp_port) = match rtsp_transport
+                    .client_port()
+                    .ok_or(SessionSetupError::DestinationInvalid)?
+                {
+                    rtsp::Port::Single(rtp_port) => (*rtp_port, rtp_port.wrapping_add(1)),
                     rtsp::Port::Range(rtp_port, rtcp_port) => (*rtp_port, *rtcp_port),
                 };
 
+                validate_client_ports(
+                    &client_ip_addr,
+                    client_rtp_port,
+                    client_rtcp_port,
+                    server_ports,
+                )
+                .map_err(SessionSetupError::ClientPortInvalid)?;
+
                 SessionSetupTarget::RtpUdp(SendOverSocket {
-                    rtp_remote: (*client_ip_addr, client_rtp_port).into(),
-                    rtcp_remote: (*client_ip_addr, client_rtcp_port).into(),
+                    rtp_remote: (client_ip_addr, client_rtp_port).into(),
+                    rtcp_remote: (client_ip_addr, client_rtcp_port).into(),
                 })
             }
             rtsp::Lower::Tcp => {
-                let (rtp_channel, rtcp_channel) = match rtsp_transport.interleaved_channel()? {
-                    rtsp::Channel::Single(rtp_channel) => (*rtp_channel, rtp_channel + 1),
-                    rtsp::Channel::Range(rtp_channel, rtcp_channel) => {
-                        (*rtp_channel, *rtcp_channel)
-                    }
-                };
+                let (rtp_channel, rtcp_channel, rtcp_rx) =
+                    match rtsp_transport.interleaved_channel() {
+                        Some(channel) => {
+                            let (rtp_channel, rtcp_channel) = match channel {
+                                rtsp::Channel::Single(rtp_channel) => {
+                                    (*rtp_channel, rtp_channel.wrapping_add(1))
+                                }
+                                rtsp::Channel::Range(rtp_channel, rtcp_channel) => {
+                                    (*rtp_channel, *rtcp_channel)
+                                }
+                            };
+                            let rtcp_rx = interleaved.claim(rtp_channel, rtcp_channel).ok_or(
+                                SessionSetupError::InterleavedChannelInUse {
+                                    rtp_channel,
+                                    rtcp_channel,
+                                },
+                            )?;
+                            (rtp_channel, rtcp_channel, rtcp_rx)
+                        }
+                        None => interleaved
+                            .claim_free_pair()
+                            .ok_or(SessionSetupError::NoInterleavedChannelsAvailable)?,
+                    };
 
                 SessionSetupTarget::RtpTcp(SendInterleaved {
                     sender,
                     rtp_channel,
                     rtcp_channel,
+                    rtcp_rx,
                 })
             }
         })
     }
 }
 
+/// Target of a session with the sockets to send from, if the target is
+/// sent to over UDP. There is no RTCP socket if RTCP is disabled.
+#[derive(Debug)]
+pub enum BoundTarget {
+    RtpUdp(SendOverSocket, (UdpSocket, Option<UdpSocket>)),
+    RtpUdpMulticast(MulticastGroup, (UdpSocket, Option<UdpSocket>)),
+    RtpTcp(SendInterleaved),
+    Sink(SendToSink),
+}
+
+/// Bind the RTP and RTCP sockets to send to `target` from, if it is sent
+/// to over UDP. The sockets of a session over unicast UDP may already be
+/// bound with [`SessionSetup::bind_udp`], in which case they are passed
+/// in as `udp_sockets`.
+pub async fn bind_target(
+    target: SessionSetupTarget,
+    udp_sockets: Option<(UdpSocket, Option<UdpSocket>)>,
+    udp_bind: &UdpBind,
+    rtcp_mode: RtcpMode,
+) -> Result<BoundTarget, BindError> {
+    let bind = |remote| udp::bind_sockets(udp_bind, remote, rtcp_mode.receives());
+    Ok(match target {
+        SessionSetupTarget::RtpUdp(target) => {
+            let udp_sockets = match udp_sockets {
+                Some(udp_sockets) => udp_sockets,
+                None => bind(target.rtp_remote.ip()).await?,
+            };
+            BoundTarget::RtpUdp(target, udp_sockets)
+        }
+        SessionSetupTarget::RtpUdpMulticast(group) => {
+            let udp_sockets = bind(group.group.ip()).await?;
+            BoundTarget::RtpUdpMulticast(group, udp_sockets)
+        }
+        SessionSetupTarget::RtpTcp(target) => BoundTarget::RtpTcp(target),
+        SessionSetupTarget::Sink(target) => BoundTarget::Sink(target),
+    })
+}
+
+/// Check that RTP and RTCP can be sent to the client from sockets bound
+/// to `udp_bind`. The client must be in the address family of the bind
+/// address unless it is unspecified. RTP and RTCP always go to the same
+/// address, that of the client, so their families never differ.
+pub fn check_address_families(
+    target: &SendOverSocket,
+    udp_bind: &UdpBind,
+) -> Result<(), SessionSetupError> {
+    let rtp_ip = target.rtp_remote.ip();
+    match udp_bind.ip_for(rtp_ip) {
+        Some(_) => Ok(()),
+        None => Err(SessionSetupError::AddressFamilyMismatch {
+            local: udp_bind.ip,
+            remote: rtp_ip,
+        }),
+    }
+}
+
+/// Check that the client-requested RTP and RTCP ports are plausible
+/// before we start blindly sending packets into them.
+///
+/// Ports must be non-zero, the RTP port must be even and the RTCP port
+/// must directly follow it (RFC 3550 section 11). If the client lives
+/// on the same host as the server, the ports must also not collide with
+/// ports the server itself has bound.
+pub fn validate_client_ports(
+    client_ip_addr: &IpAddr,
+    rtp_port: u16,
+    rtcp_port: u16,
+    server_ports: &[u16],
+) -> Result<(), ClientPortError> {
+    if rtp_port == 0 || rtcp_port == 0 {
+        return Err(ClientPortError::Zero);
+    }
+
+    if rtp_port & 1 != 0 || rtp_port.checked_add(1) != Some(rtcp_port) {
+        return Err(ClientPortError::NotPaired);
+    }
+
+    if (client_ip_addr.is_loopback() || client_ip_addr.is_unspecified())
+        && (server_ports.contains(&rtp_port) || server_ports.contains(&rtcp_port))
+    {
+        return Err(ClientPortError::CollidesWithServer);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ClientPortError {
+    Zero,
+    NotPaired,
+    CollidesWithServer,
+}
+
+impl fmt::Display for ClientPortError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientPortError::Zero => write!(f, "port is zero"),
+            ClientPortError::NotPaired => {
+                write!(f, "rtp port must be even and rtcp port must follow it")
+            }
+            ClientPortError::CollidesWithServer => write!(f, "port is in use by server"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum SessionSetupError {
     TransportNotSupported,
     DestinationInvalid,
+    ClientPortInvalid(ClientPortError),
     Media(video::Error),
+    RtpPayloadTooLarge { requested: usize, max: usize },
+    AddressFamilyMismatch { local: IpAddr, remote: IpAddr },
+    MtuTooSmall { mtu: usize, min: usize },
+    Bind(BindError),
+    InterleavedChannelInUse { rtp_channel: u8, rtcp_channel: u8 },
+    NoInterleavedChannelsAvailable,
 }
 
 impl fmt::Display for SessionSetupError {
@@ -123,9 +884,338 @@ impl fmt::Display for SessionSetupError {
         match self {
             SessionSetupError::TransportNotSupported => write!(f, "transport not supported"),
             SessionSetupError::DestinationInvalid => write!(f, "destination invalid"),
+            SessionSetupError::ClientPortInvalid(error) => {
+                write!(f, "client port invalid: {}", error)
+            }
             SessionSetupError::Media(error) => write!(f, "media error: {}", error),
+            SessionSetupError::RtpPayloadTooLarge { requested, max } => write!(
+                f,
+                "rtp payload of {} bytes exceeds transport maximum of {} bytes",
+                requested, max
+            ),
+            SessionSetupError::AddressFamilyMismatch { local, remote } => write!(
+                f,
+                "cannot send from {} to client at {} of other address family",
+                local, remote
+            ),
+            SessionSetupError::MtuTooSmall { mtu, min } => {
+                write!(f, "mtu of {} bytes is below minimum of {} bytes", mtu, min)
+            }
+            SessionSetupError::Bind(error) => write!(f, "{}", error),
+            SessionSetupError::InterleavedChannelInUse {
+                rtp_channel,
+                rtcp_channel,
+            } => write!(
+                f,
+                "interleaved channels {}-{} invalid or already in use",
+                rtp_channel, rtcp_channel
+            ),
+            SessionSetupError::NoInterleavedChannelsAvailable => {
+                write!(f, "no free interleaved channels left on connection")
+            }
         }
     }
 }
 
 impl error::Error for SessionSetupError {}
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use oddity_rtsp_protocol as rtsp;
+    use video_rs as video;
+
+    use tokio::net::UdpSocket;
+
+    use crate::net::connection::{response_channel, InterleavedReceivers};
+    use crate::session::udp::UdpBind;
+
+    use super::{
+        bind_target, check_address_families, reconcile_max_rtp_payload, validate_client_ports,
+        BoundTarget, ClientPortError, RtcpMode, RtpPayloadLimit, SendOverSocket, SessionSetupError,
+        SessionSetupTarget, SinkFraming,
+    };
+
+    const REMOTE: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    const LOCAL: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn interleaved_target(
+        transport: &str,
+        interleaved: &InterleavedReceivers,
+    ) -> Result<SessionSetupTarget, SessionSetupError> {
+        let (sender, _) = response_channel();
+        SessionSetupTarget::from_rtsp_transport(
+            &transport.parse::<rtsp::Transport>().unwrap(),
+            sender,
+            interleaved,
+            None,
+            &[],
+            None,
+        )
+    }
+
+    fn channels(target: &SessionSetupTarget) -> (u8, u8) {
+        match target {
+            SessionSetupTarget::RtpTcp(target) => (target.rtp_channel, target.rtcp_channel),
+            _ => panic!("not interleaved"),
+        }
+    }
+
+    #[test]
+    fn interleaved_sessions_get_distinct_channels() {
+        let interleaved = InterleavedReceivers::default();
+        let first = interleaved_target("RTP/AVP/TCP;unicast", &interleaved).unwrap();
+        let second = interleaved_target("RTP/AVP/TCP;unicast", &interleaved).unwrap();
+        assert_eq!(channels(&first), (0, 1));
+        assert_eq!(channels(&second), (2, 3));
+
+        assert!(matches!(
+            interleaved_target("RTP/AVP/TCP;unicast;interleaved=2-3", &interleaved),
+            Err(SessionSetupError::InterleavedChannelInUse {
+                rtp_channel: 2,
+                rtcp_channel: 3,
+            }),
+        ));
+        let third =
+            interleaved_target("RTP/AVP/TCP;unicast;interleaved=4-5", &interleaved).unwrap();
+        assert_eq!(channels(&third), (4, 5));
+    }
+
+    #[tokio::test]
+    async fn bind_target_per_target() {
+        let unicast = || {
+            SessionSetupTarget::RtpUdp(SendOverSocket {
+                rtp_remote: (LOCAL, 5000).into(),
+                rtcp_remote: (LOCAL, 5001).into(),
+            })
+        };
+        let rtcp_socket = |bound| match bound {
+            Ok(BoundTarget::RtpUdp(_, (_, rtcp_socket))) => rtcp_socket,
+            _ => panic!("not bound over udp"),
+        };
+        let bound = bind_target(unicast(), None, &UdpBind::default(), RtcpMode::RtpOnly).await;
+        assert!(rtcp_socket(bound).is_some());
+        let bound = bind_target(unicast(), None, &UdpBind::default(), RtcpMode::Disabled).await;
+        assert!(rtcp_socket(bound).is_none());
+
+        // Sockets that were bound during setup are kept.
+        let early = UdpSocket::bind((LOCAL, 0)).await.unwrap();
+        let early_addr = early.local_addr().unwrap();
+        let bound = bind_target(
+            unicast(),
+            Some((early, None)),
+            &UdpBind::default(),
+            RtcpMode::Enabled,
+        )
+        .await;
+        match bound {
+            Ok(BoundTarget::RtpUdp(_, (rtp_socket, None))) => {
+                assert_eq!(rtp_socket.local_addr().unwrap(), early_addr);
+            }
+            _ => panic!("sockets bound during setup not kept"),
+        }
+
+        let interleaved = InterleavedReceivers::default();
+        let tcp = interleaved_target("RTP/AVP/TCP;unicast", &interleaved).unwrap();
+        assert!(matches!(
+            bind_target(tcp, None, &UdpBind::default(), RtcpMode::Enabled).await,
+            Ok(BoundTarget::RtpTcp(_)),
+        ));
+    }
+
+    #[test]
+    fn validate_client_ports_ok() {
+        assert_eq!(validate_client_ports(&REMOTE, 5000, 5001, &[554]), Ok(()));
+    }
+
+    #[test]
+    fn validate_client_ports_zero() {
+        assert_eq!(
+            validate_client_ports(&REMOTE, 0, 1, &[]),
+            Err(ClientPortError::Zero),
+        );
+    }
+
+    #[test]
+    fn validate_client_ports_odd_rtp_port() {
+        assert_eq!(
+            validate_client_ports(&REMOTE, 5001, 5002, &[]),
+            Err(ClientPortError::NotPaired),
+        );
+    }
+
+    #[test]
+    fn validate_client_ports_rtcp_port_not_following() {
+        assert_eq!(
+            validate_client_ports(&REMOTE, 5000, 5003, &[]),
+            Err(ClientPortError::NotPaired),
+        );
+    }
+
+    #[test]
+    fn validate_client_ports_overflow() {
+        // The RTCP port of a single client port is the port that follows
+        // it, which wraps around to zero for the last port.
+        let (sender, _) = response_channel();
+        let transport = "RTP/AVP/UDP;unicast;client_port=65535"
+            .parse::<rtsp::Transport>()
+            .unwrap();
+        assert!(matches!(
+            SessionSetupTarget::from_rtsp_transport(
+                &transport,
+                sender,
+                &InterleavedReceivers::default(),
+                Some(REMOTE),
+                &[],
+                None,
+            ),
+            Err(SessionSetupError::ClientPortInvalid(ClientPortError::Zero)),
+        ));
+    }
+
+    #[test]
+    fn validate_client_ports_collides_with_server_on_same_host() {
+        assert_eq!(
+            validate_client_ports(&LOCAL, 554, 555, &[555]),
+            Err(ClientPortError::CollidesWithServer),
+        );
+    }
+
+    #[test]
+    fn validate_client_ports_server_ports_ignored_for_remote_client() {
+        assert_eq!(validate_client_ports(&REMOTE, 554, 555, &[555]), Ok(()));
+    }
+
+    fn packets() -> Vec<video::RtpBuf> {
+        vec![
+            video::RtpBuf::Rtp(vec![0x80, 96, 0x00, 0x01]),
+            video::RtpBuf::Rtcp(vec![0x80, 200, 0x00, 0x00, 0xaa]),
+        ]
+    }
+
+    fn encode(framing: SinkFraming, packets: &[video::RtpBuf]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for packet in packets {
+            framing.encode(packet, &mut out).unwrap();
+        }
+        out
+    }
+
+    fn payload(packet: &video::RtpBuf) -> &[u8] {
+        match packet {
+            video::RtpBuf::Rtp(payload) => payload,
+            video::RtpBuf::Rtcp(payload) => payload,
+        }
+    }
+
+    #[test]
+    fn sink_framing_raw_round_trip() {
+        let packets = packets();
+        let out = encode(SinkFraming::Raw, &packets);
+        // Without framing, packets can only be recovered when their len-
+        // gths are known.
+        let (first, second) = out.split_at(payload(&packets[0]).len());
+        assert_eq!(first, payload(&packets[0]));
+        assert_eq!(second, payload(&packets[1]));
+    }
+
+    #[test]
+    fn sink_framing_interleaved_round_trip() {
+        let packets = packets();
+        let framing = SinkFraming::Interleaved {
+            rtp_channel: 2,
+            rtcp_channel: 3,
+        };
+        let mut rest = &encode(framing, &packets)[..];
+        let mut decoded = Vec::new();
+        while !rest.is_empty() {
+            assert_eq!(rest[0], b'$');
+            let channel = rest[1];
+            let len = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+            decoded.push((channel, rest[4..4 + len].to_vec()));
+            rest = &rest[4 + len..];
+        }
+        assert_eq!(
+            decoded,
+            vec![
+                (2, payload(&packets[0]).to_vec()),
+                (3, payload(&packets[1]).to_vec()),
+            ],
+        );
+    }
+
+    #[test]
+    fn sink_framing_length_prefixed_round_trip() {
+        let packets = packets();
+        let mut rest = &encode(SinkFraming::LengthPrefixed, &packets)[..];
+        let mut decoded = Vec::new();
+        while !rest.is_empty() {
+            let len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+            decoded.push(rest[2..2 + len].to_vec());
+            rest = &rest[2 + len..];
+        }
+        assert_eq!(
+            decoded,
+            vec![payload(&packets[0]).to_vec(), payload(&packets[1]).to_vec()],
+        );
+    }
+
+    #[test]
+    fn sink_framing_packet_too_large() {
+        let packet = video::RtpBuf::Rtp(vec![0; 0x10000]);
+        assert!(SinkFraming::LengthPrefixed
+            .encode(&packet, &mut Vec::new())
+            .is_err());
+        assert!(SinkFraming::Raw.encode(&packet, &mut Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn reconcile_max_rtp_payload_within_limit() {
+        assert_eq!(
+            reconcile_max_rtp_payload(RtpPayloadLimit::Preferred(1400), 65523).unwrap(),
+            1400,
+        );
+        assert_eq!(
+            reconcile_max_rtp_payload(RtpPayloadLimit::Required(65523), 65523).unwrap(),
+            65523,
+        );
+    }
+
+    #[test]
+    fn reconcile_max_rtp_payload_preferred_is_clamped() {
+        assert_eq!(
+            reconcile_max_rtp_payload(RtpPayloadLimit::Preferred(100_000), 65523).unwrap(),
+            65523,
+        );
+    }
+
+    #[test]
+    fn reconcile_max_rtp_payload_required_is_rejected() {
+        assert!(matches!(
+            reconcile_max_rtp_payload(RtpPayloadLimit::Required(65524), 65523),
+            Err(SessionSetupError::RtpPayloadTooLarge {
+                requested: 65524,
+                max: 65523,
+            }),
+        ));
+    }
+
+    #[test]
+    fn check_address_families_bind_ip() {
+        let target = SendOverSocket {
+            rtp_remote: (Ipv6Addr::LOCALHOST, 5000).into(),
+            rtcp_remote: (Ipv6Addr::LOCALHOST, 5001).into(),
+        };
+        assert!(check_address_families(&target, &UdpBind::default()).is_ok());
+        let bind = UdpBind {
+            ip: LOCAL,
+            port_range: None,
+        };
+        assert!(matches!(
+            check_address_families(&target, &bind),
+            Err(SessionSetupError::AddressFamilyMismatch { local: LOCAL, .. }),
+        ));
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use video_rs as video;
+
+/// Counters of what a session has sent, shared between the worker of
+/// the session, which updates them, and anyone interested in reading
+/// them.
+#[derive(Debug, Default)]
+pub struct SessionCounters {
+    rtp_packets: AtomicU64,
+    rtp_bytes: AtomicU64,
+    rtcp_packets: AtomicU64,
+    rtcp_bytes: AtomicU64,
+    mux_errors: AtomicU64,
+    mux_dropped: AtomicU64,
+    rtp_dropped: AtomicU64,
+    queue_depth: AtomicU64,
+}
+
+impl SessionCounters {
+    /// Count a packet that was sent to the client.
+    pub fn record_sent(&self, buf: &video::RtpBuf) {
+        match buf {
+            video::RtpBuf::Rtp(buf) => self.record_rtp(buf.len()),
+            video::RtpBuf::Rtcp(buf) => self.record_rtcp(buf.len()),
+        }
+    }
+
+    pub fn record_rtp(&self, len: usize) {
+        self.rtp_packets.fetch_add(1, Ordering::Relaxed);
+        self.rtp_bytes.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_rtcp(&self, len: usize) {
+        self.rtcp_packets.fetch_add(1, Ordering::Relaxed);
+        self.rtcp_bytes.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_mux_error(&self) {
+        self.mux_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a packet from the source that was dropped because it failed
+    /// to mux.
+    pub fn record_mux_dropped(&self) {
+        self.mux_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an RTP packet that was dropped because the client did not
+    /// keep up.
+    pub fn record_rtp_dropped(&self) {
+        self.rtp_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Report the number of messages waiting to be written to the client.
+    pub fn set_queue_depth(&self, queue_depth: usize) {
+        self.queue_depth
+            .store(queue_depth as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> SessionStats {
+        SessionStats {
+            rtp_packets: self.rtp_packets.load(Ordering::Relaxed),
+            rtp_bytes: self.rtp_bytes.load(Ordering::Relaxed),
+            rtcp_packets: self.rtcp_packets.load(Ordering::Relaxed),
+            rtcp_bytes: self.rtcp_bytes.load(Ordering::Relaxed),
+            mux_errors: self.mux_errors.load(Ordering::Relaxed),
+            mux_dropped: self.mux_dropped.load(Ordering::Relaxed),
+            rtp_dropped: self.rtp_dropped.load(Ordering::Relaxed),
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Snapshot of the counters of a session. Bytes are counted as RTP and
+/// RTCP packets including their headers, but without any framing or
+/// lower-level headers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionStats {
+    pub rtp_packets: u64,
+    pub rtp_bytes: u64,
+    pub rtcp_packets: u64,
+    pub rtcp_bytes: u64,
+    pub mux_errors: u64,
+    /// Packets from the source that failed to mux and were dropped, while
+    /// the session carried on. A count that keeps rising points to a
+    /// flaky source.
+    pub mux_dropped: u64,
+    pub rtp_dropped: u64,
+    /// Messages waiting to be written to the client when the session last
+    /// sent something. Only sessions interleaved over TCP queue messages.
+    /// A queue that stays deep points to a client that cannot keep up.
+    pub queue_depth: u64,
+}
+
+#[cfg(test)]
+mod tests {
+
+    use video_rs as video;
+
+    use crate::session::delivery::{Delivery, SendSink};
+    use crate::session::setup::SendToSink;
+    use crate::session::tests::playing_session_loop;
+
+    use super::SessionStats;
+
+    #[tokio::test]
+    async fn counters_advance() {
+        let mut session = playing_session_loop();
+        let mut delivery = SendSink::new(SendToSink::new(tokio::io::sink()));
+        delivery
+            .deliver(
+                &mut session,
+                vec![
+                    video::RtpBuf::Rtp(vec![0; 100]),
+                    video::RtpBuf::Rtp(vec![0; 50]),
+                    video::RtpBuf::Rtcp(vec![0; 28]),
+                ],
+            )
+            .await
+            .unwrap();
+        let counters = &session.counters;
+        counters.record_mux_error();
+        counters.record_mux_dropped();
+        counters.record_rtp_dropped();
+        counters.set_queue_depth(3);
+        assert_eq!(
+            counters.snapshot(),
+            SessionStats {
+                rtp_packets: 2,
+                rtp_bytes: 150,
+                rtcp_packets: 1,
+                rtcp_bytes: 28,
+                mux_errors: 1,
+                mux_dropped: 1,
+                rtp_dropped: 1,
+                queue_depth: 3,
+            },
+        );
+    }
+}
@@ -1,52 +1,217 @@
 use oddity_rtsp_protocol as rtsp;
 
 pub fn resolve_transport(rtsp_transport: &rtsp::Transport) -> rtsp::Transport {
-    if rtsp_transport.interleaved_channel().is_some() {
-        rtsp_transport.clone()
-    } else {
-        // Use default channels 0 and 1 if client did not specify preferred
-        // interleaved channels.
-        rtsp_transport
-            .clone()
-            .with_parameter(rtsp::Parameter::Interleaved(rtsp::Channel::Range(0, 1)))
+    match rtsp_transport.lower_protocol() {
+        Some(rtsp::Lower::Udp) => rtsp_transport.clone(),
+        // Multicast is only delivered over UDP.
+        None if is_multicast(rtsp_transport) => {
+            rtsp_transport.clone().with_lower_protocol(rtsp::Lower::Udp)
+        }
+        // Without lower protocol, the transport is UDP (RFC 2326 section 12.39). We only
+        // assume so if the client told us where to send to, and use TCP otherwise.
+        None if rtsp_transport.client_port().is_some() => {
+            rtsp_transport.clone().with_lower_protocol(rtsp::Lower::Udp)
+        }
+        // If the client did not specify preferred interleaved channels,
+        // the first free pair on the connection is assigned during setup.
+        _ => rtsp_transport.clone().with_lower_protocol(rtsp::Lower::Tcp),
     }
 }
 
-pub fn is_supported(transport: &rtsp::Transport) -> bool {
-    return transport
+/// Whether the client asked for multicast rather than unicast delivery.
+pub fn is_multicast(rtsp_transport: &rtsp::Transport) -> bool {
+    rtsp_transport
+        .parameters_iter()
+        .any(|parameter| matches!(parameter, rtsp::Parameter::Multicast))
+}
+
+/// Result of negotiating a transport that the client offered.
+#[derive(Debug, PartialEq)]
+pub struct Negotiated {
+    /// Transport with only the parameters we accepted. This is what we
+    /// reply with, rather than echoing what the client asked for.
+    pub transport: rtsp::Transport,
+    /// Optional parameters that the client asked for but that we do not
+    /// grant.
+    pub declined: Vec<rtsp::Parameter>,
+}
+
+/// Negotiate a transport offered by the client. Returns `None` if we
+/// cannot serve the transport at all. Parameters that are optional to
+/// honour but that we cannot (or will not) grant are declined instead,
+/// so that the client can adapt.
+pub fn negotiate(transport: &rtsp::Transport) -> Option<Negotiated> {
+    if !transport
         .lower_protocol()
         .map(is_lower_protocol_supported)
         .unwrap_or(true)
-        && transport.parameters_iter().all(is_parameter_supported);
+    {
+        return None;
+    }
+
+    if is_multicast(transport) && matches!(transport.lower_protocol(), Some(rtsp::Lower::Tcp)) {
+        return None;
+    }
+
+    let mut accepted = Vec::new();
+    let mut declined = Vec::new();
+    for parameter in transport.parameters_iter() {
+        match negotiate_parameter(parameter) {
+            Negotiation::Accept => accepted.push(parameter.clone()),
+            Negotiation::Decline => declined.push(parameter.clone()),
+            Negotiation::Reject => return None,
+        }
+    }
+
+    let negotiated = match transport.lower_protocol() {
+        Some(lower) => rtsp::Transport::new().with_lower_protocol(lower.clone()),
+        None => rtsp::Transport::new(),
+    };
+    Some(Negotiated {
+        transport: negotiated.with_parameters(accepted),
+        declined,
+    })
+}
+
+enum Negotiation {
+    /// Parameter is supported.
+    Accept,
+    /// Parameter is not supported, but the transport works without it.
+    Decline,
+    /// Parameter is not supported and the transport does not work with-
+    /// out it.
+    Reject,
 }
 
 fn is_lower_protocol_supported(lower: &rtsp::Lower) -> bool {
     match lower {
-        rtsp::Lower::Udp => false,
+        rtsp::Lower::Udp => true,
         rtsp::Lower::Tcp => true,
     }
 }
 
-fn is_parameter_supported(parameter: &rtsp::Parameter) -> bool {
+fn negotiate_parameter(parameter: &rtsp::Parameter) -> Negotiation {
     /*
       Supported parameters are:
       - `unicast`
+      - `multicast` (over UDP)
       - `interleaved`
+      - `client_port`
       - `mode` (if value is "PLAY")
     */
     match parameter {
-        rtsp::Parameter::Unicast => true,
-        rtsp::Parameter::Multicast => false, // Multicast not supported
-        rtsp::Parameter::Destination(_) => false, // UDP not supported
-        rtsp::Parameter::Interleaved(_) => true,
-        rtsp::Parameter::Append => false,    // RECORD not supported
-        rtsp::Parameter::Ttl(_) => false,    // Multicast not supported
-        rtsp::Parameter::Layers(_) => false, // Multicast not supported
-        rtsp::Parameter::Port(_) => false,   // Multicast not supported
-        rtsp::Parameter::ClientPort(_) => false, // UDP not supported
-        rtsp::Parameter::ServerPort(_) => false, // Client cannot choose server ports
-        rtsp::Parameter::Ssrc(_) => false,   // Client cannot choose ssrc
-        rtsp::Parameter::Mode(rtsp::Method::Play) => true,
-        rtsp::Parameter::Mode(_) => false, // Only PLAY is supported for session.
+        rtsp::Parameter::Unicast => Negotiation::Accept,
+        rtsp::Parameter::Multicast => Negotiation::Accept,
+        rtsp::Parameter::Destination(_) => Negotiation::Decline, // We choose where to send to
+        rtsp::Parameter::Interleaved(_) => Negotiation::Accept,
+        rtsp::Parameter::Append => Negotiation::Reject, // RECORD not supported
+        rtsp::Parameter::Ttl(_) => Negotiation::Decline, // TTL of group is configured
+        rtsp::Parameter::Layers(_) => Negotiation::Reject, // Layered encoding not supported
+        rtsp::Parameter::Port(_) => Negotiation::Decline, // Port of group is configured
+        rtsp::Parameter::ClientPort(_) => Negotiation::Accept,
+        rtsp::Parameter::ServerPort(_) => Negotiation::Decline, // Client cannot choose server ports
+        rtsp::Parameter::Ssrc(_) => Negotiation::Decline,       // Client cannot choose ssrc
+        rtsp::Parameter::Mode(rtsp::Method::Play) => Negotiation::Accept,
+        rtsp::Parameter::Mode(_) => Negotiation::Reject, // Only PLAY is supported for session.
+        rtsp::Parameter::RtcpMux => Negotiation::Decline, // RTCP has its own channel
+        rtsp::Parameter::Extension(_) => Negotiation::Decline, // Unknown parameter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use oddity_rtsp_protocol as rtsp;
+
+    use super::{is_multicast, negotiate, resolve_transport};
+
+    fn transport(s: &str) -> rtsp::Transport {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn negotiate_supported() {
+        let negotiated = negotiate(&transport("RTP/AVP/TCP;unicast;interleaved=0-1")).unwrap();
+        assert_eq!(
+            negotiated.transport,
+            transport("RTP/AVP/TCP;unicast;interleaved=0-1"),
+        );
+        assert!(negotiated.declined.is_empty());
+    }
+
+    #[test]
+    fn negotiate_declines_optional_parameters() {
+        let negotiated = negotiate(&transport(
+            "RTP/AVP/TCP;unicast;interleaved=0-1;ssrc=1234ABCD;RTCP-mux;setup=active",
+        ))
+        .unwrap();
+        assert_eq!(
+            negotiated.transport,
+            transport("RTP/AVP/TCP;unicast;interleaved=0-1"),
+        );
+        assert_eq!(
+            negotiated.declined,
+            vec![
+                rtsp::Parameter::Ssrc("1234ABCD".to_string()),
+                rtsp::Parameter::RtcpMux,
+                rtsp::Parameter::Extension("setup=active".to_string()),
+            ],
+        );
+    }
+
+    #[test]
+    fn negotiate_udp_declines_destination() {
+        let negotiated = negotiate(&transport(
+            "RTP/AVP/UDP;unicast;destination=10.0.0.2;client_port=5000-5001",
+        ))
+        .unwrap();
+        assert_eq!(
+            negotiated.transport,
+            transport("RTP/AVP/UDP;unicast;client_port=5000-5001"),
+        );
+        assert_eq!(
+            negotiated.declined,
+            vec![rtsp::Parameter::Destination("10.0.0.2".parse().unwrap())],
+        );
+    }
+
+    #[test]
+    fn negotiate_rejects_unsupported() {
+        assert_eq!(
+            negotiate(&transport("RTP/AVP/TCP;multicast;interleaved=0-1")),
+            None
+        );
+        assert_eq!(negotiate(&transport("RTP/AVP;multicast;layers=2")), None);
+        assert_eq!(
+            negotiate(&transport("RTP/AVP/TCP;interleaved=0-1;mode=RECORD")),
+            None
+        );
+    }
+
+    #[test]
+    fn resolve_transport_lower_protocol() {
+        assert_eq!(
+            resolve_transport(&transport("RTP/AVP;unicast;client_port=5000-5001")),
+            transport("RTP/AVP/UDP;unicast;client_port=5000-5001"),
+        );
+        assert_eq!(
+            resolve_transport(&transport("RTP/AVP;unicast")),
+            transport("RTP/AVP/TCP;unicast"),
+        );
+    }
+
+    #[test]
+    fn negotiate_multicast_declines_group_parameters() {
+        let negotiated = negotiate(&transport(
+            "RTP/AVP;multicast;destination=239.0.0.1;port=6000-6001;ttl=16",
+        ))
+        .unwrap();
+        assert_eq!(negotiated.transport, transport("RTP/AVP;multicast"));
+        assert_eq!(negotiated.declined.len(), 3);
+        assert!(is_multicast(&negotiated.transport));
+        assert_eq!(
+            resolve_transport(&negotiated.transport),
+            transport("RTP/AVP/UDP;multicast"),
+        );
     }
 }
@@ -0,0 +1,390 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::net::UdpSocket;
+
+use socket2::SockRef;
+
+/// Where to bind the UDP sockets that sessions send RTP and RTCP from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UdpBind {
+    /// Address to bind to. If unspecified (all interfaces), the sockets
+    /// are bound to all interfaces of the address family of the client
+    /// instead, so either family works.
+    pub ip: IpAddr,
+    /// Inclusive range of ports to bind to. Ephemeral ports are used if
+    /// `None`.
+    pub port_range: Option<(u16, u16)>,
+}
+
+impl Default for UdpBind {
+    fn default() -> Self {
+        Self {
+            ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port_range: None,
+        }
+    }
+}
+
+impl UdpBind {
+    /// Address to bind to in order to send to `remote`. Dual-stack sock-
+    /// ets are not assumed to be available, so the address must be in the
+    /// same family as `remote`. Returns `None` if it cannot be.
+    pub fn ip_for(&self, remote: IpAddr) -> Option<IpAddr> {
+        match (self.ip.is_unspecified(), remote) {
+            (true, IpAddr::V4(_)) => Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            (true, IpAddr::V6(_)) => Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+            (false, remote) if self.ip.is_ipv4() == remote.is_ipv4() => Some(self.ip),
+            (false, _) => None,
+        }
+    }
+}
+
+/// Multicast group that a source is delivered to. All clients that ask
+/// for multicast delivery of the source tune in to the same group, in-
+/// stead of each receiving a stream of their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MulticastGroup {
+    /// Group address and the port that RTP is sent to. RTCP is sent to
+    /// the port that directly follows it.
+    pub group: SocketAddr,
+    /// Number of hops that packets sent to the group may travel.
+    pub ttl: u32,
+    /// Whether packets sent to the group are looped back to receivers on
+    /// the host of the server itself.
+    pub loopback: bool,
+}
+
+impl MulticastGroup {
+    /// TTL unless configured otherwise. Packets do not leave the local
+    /// network, so that a misconfigured group does not flood anything
+    /// beyond it.
+    pub const DEFAULT_TTL: u32 = 1;
+
+    pub fn new(group: SocketAddr) -> Self {
+        Self {
+            group,
+            ttl: Self::DEFAULT_TTL,
+            loopback: true,
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: u32) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_loopback(mut self, loopback: bool) -> Self {
+        self.loopback = loopback;
+        self
+    }
+
+    pub fn rtp_addr(&self) -> SocketAddr {
+        self.group
+    }
+
+    pub fn rtcp_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.group.ip(), self.group.port().wrapping_add(1))
+    }
+}
+
+/// Bind a pair of sockets for RTP and RTCP to send to `remote`.
+///
+/// Within a port range, the RTP socket is bound to an even port and the
+/// RTCP socket to the port that directly follows it (RFC 3550 section
+/// 11). Ports that are in use are skipped. Without a port range, both
+/// sockets are bound to ephemeral ports, which are not paired.
+pub async fn bind_pair(
+    bind: &UdpBind,
+    remote: IpAddr,
+) -> Result<(UdpSocket, UdpSocket), BindError> {
+    let ip = bind
+        .ip_for(remote)
+        .ok_or(BindError::AddressFamilyMismatch {
+            local: bind.ip,
+            remote,
+        })?;
+    let (min, max) = match bind.port_range {
+        Some(port_range) => port_range,
+        None => {
+            let rtp_socket = UdpSocket::bind((ip, 0)).await?;
+            let rtcp_socket = UdpSocket::bind((ip, 0)).await?;
+            return Ok((rtp_socket, rtcp_socket));
+        }
+    };
+
+    let ports = candidate_ports(min, max).ok_or(BindError::InvalidRange { min, max })?;
+    for (rtp_port, rtcp_port) in ports {
+        let rtp_socket = match UdpSocket::bind((ip, rtp_port)).await {
+            Ok(socket) => socket,
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let rtcp_socket = match UdpSocket::bind((ip, rtcp_port)).await {
+            Ok(socket) => socket,
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => continue,
+            Err(err) => return Err(err.into()),
+        };
+        tracing::trace!(%ip, rtp_port, rtcp_port, "bound rtp and rtcp sockets");
+        return Ok((rtp_socket, rtcp_socket));
+    }
+
+    Err(BindError::NoPortsAvailable { min, max })
+}
+
+/// Bind a socket for RTP only to send to `remote`, for sessions without
+/// RTCP. Within a port range, the socket is bound to an even port as if
+/// it were paired, but the port that follows it may be in use.
+pub async fn bind_rtp(bind: &UdpBind, remote: IpAddr) -> Result<UdpSocket, BindError> {
+    let ip = bind
+        .ip_for(remote)
+        .ok_or(BindError::AddressFamilyMismatch {
+            local: bind.ip,
+            remote,
+        })?;
+    let (min, max) = match bind.port_range {
+        Some(port_range) => port_range,
+        None => return Ok(UdpSocket::bind((ip, 0)).await?),
+    };
+
+    let ports = candidate_ports(min, max).ok_or(BindError::InvalidRange { min, max })?;
+    for (rtp_port, _) in ports {
+        match UdpSocket::bind((ip, rtp_port)).await {
+            Ok(socket) => {
+                tracing::trace!(%ip, rtp_port, "bound rtp socket");
+                return Ok(socket);
+            }
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Err(BindError::NoPortsAvailable { min, max })
+}
+
+/// Bind the sockets to send to `remote` from, see [`bind_pair`]. Only the
+/// RTP socket is bound if `rtcp` is `false`, see [`bind_rtp`].
+pub async fn bind_sockets(
+    bind: &UdpBind,
+    remote: IpAddr,
+    rtcp: bool,
+) -> Result<(UdpSocket, Option<UdpSocket>), BindError> {
+    if rtcp {
+        let (rtp_socket, rtcp_socket) = bind_pair(bind, remote).await?;
+        Ok((rtp_socket, Some(rtcp_socket)))
+    } else {
+        Ok((bind_rtp(bind, remote).await?, None))
+    }
+}
+
+/// DSCP value for expedited forwarding (RFC 3246), the usual marking
+/// for real-time media.
+pub const DSCP_EXPEDITED_FORWARDING: u8 = 46;
+
+/// Mark the traffic sent from `socket` with the given DSCP value, so that
+/// managed networks can prioritize it. Sets the type of service over IPv4
+/// and the traffic class over IPv6. The ECN bits are left zero.
+pub fn set_dscp(socket: &UdpSocket, dscp: u8) -> io::Result<()> {
+    if dscp > 0x3f {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "dscp value does not fit in six bits",
+        ));
+    }
+    // Borrowing the socket leaves ownership of the file descriptor with
+    // tokio.
+    let sock_ref = SockRef::from(socket);
+    let tos = u32::from(dscp) << 2;
+    if socket.local_addr()?.is_ipv6() {
+        set_tclass_v6(&sock_ref, tos)
+    } else {
+        sock_ref.set_tos_v4(tos)
+    }
+}
+
+/// Configure `socket` to send to the given multicast group with its TTL
+/// (hop limit over IPv6) and loopback setting.
+pub fn set_multicast(socket: &UdpSocket, group: &MulticastGroup) -> io::Result<()> {
+    if socket.local_addr()?.is_ipv6() {
+        SockRef::from(socket).set_multicast_hops_v6(group.ttl)?;
+        socket.set_multicast_loop_v6(group.loopback)
+    } else {
+        socket.set_multicast_ttl_v4(group.ttl)?;
+        socket.set_multicast_loop_v4(group.loopback)
+    }
+}
+
+#[cfg(any(
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "macos"
+))]
+fn set_tclass_v6(sock_ref: &SockRef, tclass: u32) -> io::Result<()> {
+    sock_ref.set_tclass_v6(tclass)
+}
+
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "macos"
+)))]
+fn set_tclass_v6(_sock_ref: &SockRef, _tclass: u32) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Pairs of RTP and RTCP ports within the inclusive range `min..=max`.
+/// Returns `None` if the range does not contain any pair.
+fn candidate_ports(min: u16, max: u16) -> Option<impl Iterator<Item = (u16, u16)>> {
+    let first = min.checked_add(min & 1)?;
+    if min == 0 || first >= max {
+        return None;
+    }
+    Some(
+        (first..max)
+            .step_by(2)
+            .map(|rtp_port| (rtp_port, rtp_port + 1)),
+    )
+}
+
+#[derive(Debug)]
+pub enum BindError {
+    InvalidRange { min: u16, max: u16 },
+    NoPortsAvailable { min: u16, max: u16 },
+    AddressFamilyMismatch { local: IpAddr, remote: IpAddr },
+    Io(io::Error),
+}
+
+impl fmt::Display for BindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BindError::InvalidRange { min, max } => {
+                write!(
+                    f,
+                    "port range {}-{} does not contain an rtp/rtcp port pair",
+                    min, max
+                )
+            }
+            BindError::NoPortsAvailable { min, max } => {
+                write!(f, "no free rtp/rtcp port pair in range {}-{}", min, max)
+            }
+            BindError::AddressFamilyMismatch { local, remote } => {
+                write!(f, "cannot send from {} to {}", local, remote)
+            }
+            BindError::Io(error) => write!(f, "failed to bind socket: {}", error),
+        }
+    }
+}
+
+impl error::Error for BindError {}
+
+impl From<io::Error> for BindError {
+    fn from(error: io::Error) -> Self {
+        BindError::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use tokio::net::UdpSocket;
+
+    use socket2::SockRef;
+
+    use super::{
+        bind_pair, bind_rtp, candidate_ports, set_dscp, set_multicast, BindError, MulticastGroup,
+        UdpBind,
+    };
+
+    const LOCAL: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    #[test]
+    fn candidate_ports_are_paired() {
+        assert_eq!(
+            candidate_ports(5001, 5006).unwrap().collect::<Vec<_>>(),
+            vec![(5002, 5003), (5004, 5005)],
+        );
+        assert!(candidate_ports(5000, 5000).is_none());
+        assert!(candidate_ports(5001, 5002).is_none());
+        assert!(candidate_ports(0, 5001).is_none());
+        assert!(candidate_ports(65535, 65535).is_none());
+    }
+
+    #[test]
+    fn ip_for_remote_family() {
+        let remote_v4 = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let remote_v6 = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        let any = UdpBind::default();
+        assert_eq!(
+            any.ip_for(remote_v4),
+            Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+        );
+        assert_eq!(
+            any.ip_for(remote_v6),
+            Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED))
+        );
+        let local = UdpBind {
+            ip: LOCAL,
+            port_range: None,
+        };
+        assert_eq!(local.ip_for(remote_v4), Some(LOCAL));
+        assert_eq!(local.ip_for(remote_v6), None);
+    }
+
+    #[tokio::test]
+    async fn bind_pair_exhausted_range() {
+        // Occupy one port of the only pair in the range.
+        let occupied = UdpSocket::bind((LOCAL, 0)).await.unwrap();
+        let rtp_port = occupied.local_addr().unwrap().port() & !1;
+        let bind = UdpBind {
+            ip: LOCAL,
+            port_range: Some((rtp_port, rtp_port + 1)),
+        };
+        assert!(matches!(
+            bind_pair(&bind, LOCAL).await,
+            Err(BindError::NoPortsAvailable { min, max }) if min == rtp_port && max == rtp_port + 1,
+        ));
+    }
+
+    #[tokio::test]
+    async fn bind_rtp_does_not_need_rtcp_port() {
+        // Occupy the RTCP port of the only pair in the range.
+        let mut occupied = UdpSocket::bind((LOCAL, 0)).await.unwrap();
+        while occupied.local_addr().unwrap().port() & 1 == 0 {
+            occupied = UdpSocket::bind((LOCAL, 0)).await.unwrap();
+        }
+        let rtp_port = occupied.local_addr().unwrap().port() - 1;
+        let bind = UdpBind {
+            ip: LOCAL,
+            port_range: Some((rtp_port, rtp_port + 1)),
+        };
+        assert!(bind_pair(&bind, LOCAL).await.is_err());
+        let socket = bind_rtp(&bind, LOCAL).await.unwrap();
+        assert_eq!(socket.local_addr().unwrap().port(), rtp_port);
+    }
+
+    #[tokio::test]
+    async fn set_dscp_marks_socket() {
+        let socket = UdpSocket::bind((LOCAL, 0)).await.unwrap();
+        set_dscp(&socket, 46).unwrap();
+        assert_eq!(SockRef::from(&socket).tos_v4().unwrap(), 46 << 2);
+        assert!(set_dscp(&socket, 64).is_err());
+    }
+
+    #[tokio::test]
+    async fn set_multicast_ttl_and_loopback() {
+        let socket = UdpSocket::bind((LOCAL, 0)).await.unwrap();
+        let group = MulticastGroup::new("239.1.1.1:5004".parse().unwrap())
+            .with_ttl(4)
+            .with_loopback(false);
+        assert_eq!(group.rtcp_addr(), "239.1.1.1:5005".parse().unwrap());
+        set_multicast(&socket, &group).unwrap();
+        assert_eq!(socket.multicast_ttl_v4().unwrap(), 4);
+        assert!(!socket.multicast_loop_v4().unwrap());
+    }
+}
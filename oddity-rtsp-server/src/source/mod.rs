@@ -9,6 +9,7 @@ use tokio::time::timeout;
 
 use video_rs as video;
 
+use crate::media::video::drift::DriftCompensator;
 use crate::media::video::reader::StreamReader;
 use crate::media::{self, MediaDescriptor};
 use crate::runtime::task_manager::{Task, TaskContext};
@@ -21,6 +22,10 @@ pub enum SourceState {
 pub type SourceStateTx = mpsc::UnboundedSender<SourceState>;
 pub type SourceStateRx = mpsc::UnboundedReceiver<SourceState>;
 
+/// Paths of sources that were removed because they stopped for good.
+pub type SourceRemovedTx = mpsc::UnboundedSender<SourcePath>;
+pub type SourceRemovedRx = mpsc::UnboundedReceiver<SourcePath>;
+
 pub type SourceMediaInfoTx = broadcast::Sender<media::MediaInfo>;
 pub type SourceMediaInfoRx = broadcast::Receiver<media::MediaInfo>;
 
@@ -37,10 +42,54 @@ pub enum SourceControlMessage {
 pub type SourceControlTx = mpsc::UnboundedSender<SourceControlMessage>;
 pub type SourceControlRx = mpsc::UnboundedReceiver<SourceControlMessage>;
 
+/// How a source restarts its upstream after it broke, for sources where
+/// the upstream may come back, such as remote streams. Between attempts
+/// the source backs off exponentially. Sessions of the source stay up
+/// while it reconnects, and resume once it is back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// Delay after the first failed attempt. The delay doubles with every
+    /// attempt that fails after it.
+    pub initial_delay: time::Duration,
+    /// Longest delay between attempts.
+    pub max_delay: time::Duration,
+    /// Give up after this many attempts in a row failed. The source is
+    /// stopped then, which ends its sessions.
+    pub max_attempts: usize,
+}
+
+impl ReconnectPolicy {
+    pub const DEFAULT_INITIAL_DELAY: time::Duration = time::Duration::from_secs(1);
+    pub const DEFAULT_MAX_DELAY: time::Duration = time::Duration::from_secs(60);
+
+    pub fn new(max_attempts: usize) -> Self {
+        Self {
+            initial_delay: Self::DEFAULT_INITIAL_DELAY,
+            max_delay: Self::DEFAULT_MAX_DELAY,
+            max_attempts,
+        }
+    }
+
+    /// Delay before retrying after the given number of attempts in a row
+    /// failed, or `None` to give up.
+    pub fn delay(&self, failed_attempts: usize) -> Option<time::Duration> {
+        if failed_attempts >= self.max_attempts {
+            return None;
+        }
+        let factor = 1u32 << failed_attempts.saturating_sub(1).min(31);
+        Some(
+            self.initial_delay
+                .checked_mul(factor)
+                .map_or(self.max_delay, |delay| delay.min(self.max_delay)),
+        )
+    }
+}
+
 pub struct Source {
     pub name: String,
     pub path: SourcePath,
     pub descriptor: MediaDescriptor,
+    pub max_viewers: Option<usize>,
     control_tx: SourceControlTx,
     media_info_tx: SourceMediaInfoTx,
     reset_tx: SourceResetTx,
@@ -57,13 +106,17 @@ impl Source {
     /// terribly overloaded/broken.
     const MAX_QUEUED_PACKETS: usize = 1024;
 
-    /// Number of seconds between retries.
+    /// Number of seconds between retries without a reconnect policy.
     const RETRY_DELAY_SECS: u64 = 60;
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn start(
         name: &str,
         path: SourcePath,
         descriptor: MediaDescriptor,
+        max_viewers: Option<usize>,
+        drift_compensation: bool,
+        reconnect: Option<ReconnectPolicy>,
         state_tx: SourceStateTx,
         runtime: &Runtime,
     ) -> Result<Self, video::Error> {
@@ -87,6 +140,8 @@ impl Source {
                     Self::run(
                         path,
                         descriptor,
+                        drift_compensation,
+                        reconnect,
                         control_rx,
                         state_tx,
                         media_info_tx,
@@ -103,6 +158,7 @@ impl Source {
             name: name.to_string(),
             path,
             descriptor,
+            max_viewers,
             control_tx,
             media_info_tx,
             reset_tx,
@@ -119,6 +175,8 @@ impl Source {
 
     pub fn delegate(&mut self) -> SourceDelegate {
         SourceDelegate {
+            path: self.path.clone(),
+            max_viewers: self.max_viewers,
             control_tx: self.control_tx.clone(),
             media_info_rx: self.media_info_tx.subscribe(),
             reset_rx: self.reset_tx.subscribe(),
@@ -130,6 +188,8 @@ impl Source {
     async fn run(
         path: SourcePath,
         descriptor: MediaDescriptor,
+        drift_compensation: bool,
+        reconnect: Option<ReconnectPolicy>,
         mut control_rx: SourceControlRx,
         state_tx: SourceStateTx,
         media_info_tx: SourceMediaInfoTx,
@@ -152,6 +212,7 @@ impl Source {
             let mut stream_reader = match outer_stream_reader {
                 Some(stream_reader) => stream_reader,
                 None => {
+                    let mut failed_attempts = 0;
                     'restart: loop {
                         match StreamReader::new(&descriptor).await {
                             Ok(new_stream_reader) => {
@@ -163,18 +224,27 @@ impl Source {
                                 break new_stream_reader;
                             }
                             Err(err) => {
+                                failed_attempts += 1;
+                                let retry_delay = match &reconnect {
+                                    Some(reconnect) => match reconnect.delay(failed_attempts) {
+                                        Some(retry_delay) => retry_delay,
+                                        None => {
+                                            tracing::error!(
+                                              %err, %descriptor, failed_attempts,
+                                              "failed to restart stream (giving up)",
+                                            );
+                                            break 'outer;
+                                        }
+                                    },
+                                    None => time::Duration::from_secs(Self::RETRY_DELAY_SECS),
+                                };
                                 tracing::error!(
-                                  %err, %descriptor, retry_delay=Self::RETRY_DELAY_SECS,
+                                  %err, %descriptor, retry_delay=retry_delay.as_secs_f64(), failed_attempts,
                                   "failed to restart stream (waiting before retrying)",
                                 );
                                 // We want to wait some time before retrying. We wrap `wait_for_stop` in
                                 // a timeout to achieve this ...
-                                match timeout(
-                                    time::Duration::from_secs(Self::RETRY_DELAY_SECS),
-                                    task_context.wait_for_stop(),
-                                )
-                                .await
-                                {
+                                match timeout(retry_delay, task_context.wait_for_stop()).await {
                                     Ok(()) => {
                                         tracing::trace!(%path, "stopping source (during stream restart)");
                                         // If `wait_for_stop` returns, we break out of the outer loop and stop ...
@@ -192,14 +262,22 @@ impl Source {
                 }
             };
 
+            // Timestamps start over with every (re)started stream, and so does measuring their
+            // drift.
+            let clock = time::Instant::now();
+            let mut drift = drift_compensation.then(DriftCompensator::new);
+
             'read: loop {
                 select! {
                   // CANCEL SAFETY: `StreamReader::read` uses `mpsc::UnboundedReceiver::recv`
                   // internally which is cancel safe.
                   packet = stream_reader.read() => {
                     match packet {
-                      Some(Ok(packet)) => {
-                        let _ = packet_tx.send(packet.clone());
+                      Some(Ok(mut packet)) => {
+                        if let Some(drift) = drift.as_mut() {
+                          drift.update(clock.elapsed(), &mut packet);
+                        }
+                        let _ = packet_tx.send(packet);
                       },
                       Some(Err(err)) => {
                         tracing::error!(%path, %err, "failed to read video stream");
@@ -246,7 +324,13 @@ impl Source {
     }
 }
 
+/// Handle for a single session on a source. All delegates of a source
+/// share its reader: each has its own bounded queue of packets, which is
+/// fed by the reader without waiting for the delegate. A delegate that
+/// falls behind by more than the queue holds skips packets.
 pub struct SourceDelegate {
+    path: SourcePath,
+    max_viewers: Option<usize>,
     control_tx: SourceControlTx,
     media_info_rx: SourceMediaInfoRx,
     reset_rx: SourceResetRx,
@@ -254,6 +338,16 @@ pub struct SourceDelegate {
 }
 
 impl SourceDelegate {
+    pub fn path(&self) -> &SourcePathRef {
+        &self.path
+    }
+
+    /// Maximum number of sessions that may be playing this source at the
+    /// same time, if limited.
+    pub fn max_viewers(&self) -> Option<usize> {
+        self.max_viewers
+    }
+
     pub async fn query_media_info(&mut self) -> Option<media::MediaInfo> {
         if let Ok(()) = self.control_tx.send(SourceControlMessage::StreamInfo) {
             self.media_info_rx.recv().await.ok()
@@ -267,6 +361,44 @@ impl SourceDelegate {
     }
 }
 
+/// Source that never reads anything, for testing sessions without a
+/// stream. Its delegates stay connected for as long as it lives.
+#[cfg(test)]
+pub struct IdleSource {
+    path: SourcePath,
+    control_tx: SourceControlTx,
+    _control_rx: SourceControlRx,
+    media_info_tx: SourceMediaInfoTx,
+    reset_tx: SourceResetTx,
+    packet_tx: SourcePacketTx,
+}
+
+#[cfg(test)]
+impl IdleSource {
+    pub fn new(path: &SourcePathRef) -> Self {
+        let (control_tx, control_rx) = mpsc::unbounded_channel();
+        Self {
+            path: normalize_path(path.to_string()),
+            control_tx,
+            _control_rx: control_rx,
+            media_info_tx: broadcast::channel(Source::MAX_QUEUED_INFO).0,
+            reset_tx: broadcast::channel(Source::MAX_QUEUED_INFO).0,
+            packet_tx: broadcast::channel(Source::MAX_QUEUED_PACKETS).0,
+        }
+    }
+
+    pub fn delegate(&self, max_viewers: Option<usize>) -> SourceDelegate {
+        SourceDelegate {
+            path: self.path.clone(),
+            max_viewers,
+            control_tx: self.control_tx.clone(),
+            media_info_rx: self.media_info_tx.subscribe(),
+            reset_rx: self.reset_tx.subscribe(),
+            packet_rx: self.packet_tx.subscribe(),
+        }
+    }
+}
+
 pub type SourcePath = String;
 pub type SourcePathRef = str;
 
@@ -277,3 +409,28 @@ pub fn normalize_path(path: SourcePath) -> SourcePath {
         format!("/{}", &path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use super::ReconnectPolicy;
+
+    #[test]
+    fn reconnect_backs_off_exponentially() {
+        let reconnect = ReconnectPolicy::new(8);
+        let delays = (1..8)
+            .map(|failed_attempts| reconnect.delay(failed_attempts).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            delays,
+            [1, 2, 4, 8, 16, 32, 60].map(Duration::from_secs).to_vec(),
+        );
+        assert_eq!(reconnect.delay(8), None);
+        assert_eq!(
+            ReconnectPolicy::new(usize::MAX).delay(100),
+            Some(Duration::from_secs(60))
+        );
+    }
+}
@@ -14,8 +14,8 @@ use crate::media::MediaDescriptor;
 use crate::runtime::task_manager::{Task, TaskContext};
 use crate::runtime::Runtime;
 use crate::source::{
-    self, Source, SourceDelegate, SourcePath, SourcePathRef, SourceState, SourceStateRx,
-    SourceStateTx,
+    self, ReconnectPolicy, Source, SourceDelegate, SourcePath, SourcePathRef, SourceRemovedTx,
+    SourceState, SourceStateRx, SourceStateTx,
 };
 
 type SourceShared = Arc<Mutex<Source>>;
@@ -32,7 +32,10 @@ pub struct SourceManager {
 }
 
 impl SourceManager {
-    pub async fn start(runtime: Arc<Runtime>) -> Self {
+    /// Start the source manager. Sources that stop for good, because they
+    /// gave up restarting their stream, are removed, and their paths are
+    /// sent to `removed_tx`, if any.
+    pub async fn start(runtime: Arc<Runtime>, removed_tx: Option<SourceRemovedTx>) -> Self {
         let sources = Arc::new(RwLock::new(HashMap::new()));
         let (source_state_tx, source_state_rx) = mpsc::unbounded_channel();
 
@@ -43,7 +46,9 @@ impl SourceManager {
             .task()
             .spawn({
                 let sources = sources.clone();
-                move |task_context| Self::run(sources.clone(), source_state_rx, task_context)
+                move |task_context| {
+                    Self::run(sources.clone(), source_state_rx, removed_tx, task_context)
+                }
             })
             .await;
         tracing::trace!("started source manager");
@@ -71,12 +76,18 @@ impl SourceManager {
         name: &str,
         path: SourcePath,
         descriptor: MediaDescriptor,
+        max_viewers: Option<usize>,
+        drift_compensation: bool,
+        reconnect: Option<ReconnectPolicy>,
     ) -> Result<(), RegisterSourceError> {
         let path = source::normalize_path(path);
         let source = Source::start(
             name,
             path.clone(),
             descriptor,
+            max_viewers,
+            drift_compensation,
+            reconnect,
             self.source_state_tx.clone(),
             self.runtime.as_ref(),
         )
@@ -99,6 +110,11 @@ impl SourceManager {
         Ok(())
     }
 
+    /// Paths of all registered sources.
+    pub async fn paths(&self) -> Vec<SourcePath> {
+        self.sources.read().await.keys().cloned().collect()
+    }
+
     pub async fn describe(&self, path: &SourcePathRef) -> Option<Result<Sdp, SdpError>> {
         let cached_description = self
             .source_descriptions_cache
@@ -144,6 +160,7 @@ impl SourceManager {
     async fn run(
         sources: SourceMap,
         mut source_state_rx: SourceStateRx,
+        removed_tx: Option<SourceRemovedTx>,
         mut task_context: TaskContext,
     ) {
         loop {
@@ -154,6 +171,9 @@ impl SourceManager {
                   Some(SourceState::Stopped(source_id)) => {
                     tracing::trace!(%source_id, "source manager: received stopped");
                     let _ = sources.write().await.remove(&source_id);
+                    if let Some(removed_tx) = removed_tx.as_ref() {
+                      let _ = removed_tx.send(source_id);
+                    }
                   },
                   None => {
                     tracing::error!("source state channel broke unexpectedly");
@@ -189,3 +209,40 @@ impl fmt::Display for RegisterSourceError {
 }
 
 impl error::Error for RegisterSourceError {}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use tokio::sync::{mpsc, RwLock};
+
+    use crate::runtime::Runtime;
+    use crate::source::SourceState;
+
+    use super::SourceManager;
+
+    #[tokio::test]
+    async fn stopped_source_is_reported_removed() {
+        let runtime = Runtime::new();
+        let (source_state_tx, source_state_rx) = mpsc::unbounded_channel();
+        let (removed_tx, mut removed_rx) = mpsc::unbounded_channel();
+        let _worker = runtime
+            .task()
+            .spawn(move |task_context| {
+                SourceManager::run(
+                    Arc::new(RwLock::new(HashMap::new())),
+                    source_state_rx,
+                    Some(removed_tx),
+                    task_context,
+                )
+            })
+            .await;
+        source_state_tx
+            .send(SourceState::Stopped("/gone".to_string()))
+            .unwrap_or_else(|_| panic!("source manager stopped"));
+        assert_eq!(removed_rx.recv().await.as_deref(), Some("/gone"));
+        runtime.stop().await;
+    }
+}